@@ -0,0 +1,271 @@
+use crate::digest::ContentDigest;
+use crate::encoding;
+use crate::formatter::{HeaderOptions, OutputFormat};
+use crate::tokenizer::TokenizerKind;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// What a file's content turned out to be when it was read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CachedContent {
+    /// Text, decoded from `encoding`, along with the digest of the file used to spot duplicates.
+    Text {
+        text: Arc<str>,
+        encoding: EncodingName,
+        digest: ContentDigest,
+    },
+    /// Binary content, which is never included.
+    Binary,
+    /// Content that is not text in any supported encoding.
+    Undecodable,
+}
+
+/// The number of tokens a file costs when rendered in `format` with `header` and counted by
+/// `tokenizer`, in full or as its skeleton, and with secrets redacted by the settings whose
+/// fingerprint is `redaction`, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenCost {
+    pub tokenizer: TokenizerKind,
+    pub format: OutputFormat,
+    #[serde(default)]
+    pub header: HeaderOptions,
+    #[serde(default)]
+    pub skeleton: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redaction: Option<ContentDigest>,
+    pub tokens: usize,
+}
+
+/// A file's content and what has been worked out about it, valid for as long as the file keeps
+/// the same size and modification time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedFile {
+    pub size: u64,
+    pub modified: SystemTime,
+    pub content: CachedContent,
+    #[serde(default)]
+    pub costs: Vec<TokenCost>,
+}
+
+impl CachedFile {
+    /// Looks up what the file, or its skeleton, costs with a tokenizer, format and header, if it
+    /// has been worked out.
+    pub fn cost(
+        &self,
+        tokenizer: TokenizerKind,
+        format: OutputFormat,
+        header: HeaderOptions,
+        skeleton: bool,
+        redaction: Option<ContentDigest>,
+    ) -> Option<usize> {
+        self.costs
+            .iter()
+            .find(|cost| {
+                cost.tokenizer == tokenizer
+                    && cost.format == format
+                    && cost.header == header
+                    && cost.skeleton == skeleton
+                    && cost.redaction == redaction
+            })
+            .map(|cost| cost.tokens)
+    }
+
+    /// Bytes of content held for the file, which is what the cache's size limit counts.
+    fn content_bytes(&self) -> u64 {
+        match &self.content {
+            CachedContent::Text { text, .. } => text.len() as u64,
+            _ => 0,
+        }
+    }
+}
+
+/// Counters describing a cache, as returned by `GET /api/v1/cache/stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_entries: usize,
+    pub max_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// A cache of file contents shared by requests, so that files which have not changed since
+/// they were last read are neither read nor decoded, hashed or tokenized again.
+///
+/// Entries are keyed by path and only used while the file's size and modification time match.
+/// The least recently used entries are evicted once either limit is exceeded.
+#[derive(Debug)]
+pub struct ContentCache {
+    max_entries: usize,
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<PathBuf, Entry>,
+    bytes: u64,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    file: CachedFile,
+    last_used: u64,
+}
+
+/// The on-disk form of a cache.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    entries: Vec<(PathBuf, CachedFile)>,
+}
+
+/// Evicting goes this far below the limits, so that it does not run on every insert once the
+/// cache is full.
+const EVICTION_HEADROOM: f64 = 0.9;
+
+impl ContentCache {
+    pub fn new(max_entries: usize, max_bytes: u64) -> Self {
+        ContentCache {
+            max_entries,
+            max_bytes,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Returns what is known about a file, if it has not changed since it was cached.
+    pub fn get(&self, path: &Path, size: u64, modified: SystemTime) -> Option<CachedFile> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        match state.entries.get_mut(path) {
+            Some(entry) if entry.file.size == size && entry.file.modified == modified => {
+                entry.last_used = clock;
+                let file = entry.file.clone();
+                state.hits += 1;
+                Some(file)
+            }
+            _ => {
+                state.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Stores what is known about a file, replacing any earlier entry for it.
+    pub fn insert(&self, path: PathBuf, file: CachedFile) {
+        // A file that could never fit would only push everything else out
+        if file.content_bytes() > self.max_bytes || self.max_entries == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        state.bytes += file.content_bytes();
+        let entry = Entry {
+            file,
+            last_used: state.clock,
+        };
+        if let Some(old) = state.entries.insert(path, entry) {
+            state.bytes -= old.file.content_bytes();
+        }
+        if state.entries.len() > self.max_entries || state.bytes > self.max_bytes {
+            self.evict(&mut state);
+        }
+    }
+
+    /// Drops the least recently used entries until the cache is comfortably within its limits.
+    fn evict(&self, state: &mut CacheState) {
+        let max_entries = (self.max_entries as f64 * EVICTION_HEADROOM) as usize;
+        let max_bytes = (self.max_bytes as f64 * EVICTION_HEADROOM) as u64;
+
+        let mut by_age: Vec<(u64, PathBuf)> = state
+            .entries
+            .iter()
+            .map(|(path, entry)| (entry.last_used, path.clone()))
+            .collect();
+        by_age.sort_unstable();
+        for (_, path) in by_age {
+            if state.entries.len() <= max_entries && state.bytes <= max_bytes {
+                break;
+            }
+            if let Some(entry) = state.entries.remove(&path) {
+                state.bytes -= entry.file.content_bytes();
+                state.evictions += 1;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            enabled: true,
+            entries: state.entries.len(),
+            bytes: state.bytes,
+            max_entries: self.max_entries,
+            max_bytes: self.max_bytes,
+            hits: state.hits,
+            misses: state.misses,
+            evictions: state.evictions,
+        }
+    }
+
+    /// Writes the cached entries to `path`, so that a later run can start from them.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let snapshot = {
+            let state = self.state.lock().unwrap();
+            let mut entries: Vec<(&PathBuf, &Entry)> = state.entries.iter().collect();
+            entries.sort_by_key(|(_, entry)| entry.last_used);
+            Snapshot {
+                entries: entries
+                    .into_iter()
+                    .map(|(path, entry)| (path.clone(), entry.file.clone()))
+                    .collect(),
+            }
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec(&snapshot)?)
+    }
+
+    /// Creates a cache holding the entries saved at `path`, or an empty one if there are none.
+    pub fn load(path: &Path, max_entries: usize, max_bytes: u64) -> io::Result<Self> {
+        let cache = ContentCache::new(max_entries, max_bytes);
+        let snapshot: Snapshot = match fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e),
+        };
+        // Entries were saved least recently used first, which is the order to insert them in
+        for (path, file) in snapshot.entries {
+            cache.insert(path, file);
+        }
+        Ok(cache)
+    }
+}
+
+/// The name of an encoding, as reported by `encoding::decode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct EncodingName(pub &'static str);
+
+impl<'de> Deserialize<'de> for EncodingName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let label = String::deserialize(deserializer)?;
+        encoding::canonical_name(&label)
+            .map(EncodingName)
+            .ok_or_else(|| serde::de::Error::custom(format!("unknown encoding '{}'", label)))
+    }
+}
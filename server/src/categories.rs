@@ -0,0 +1,190 @@
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// A named output section and the patterns selecting its files.
+///
+/// Patterns use gitignore syntax and are matched against paths relative to the project root:
+/// `*.md` matches at any depth, `tests/` matches everything under any `tests` directory and a
+/// leading `!` keeps otherwise matching files out of the section.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectionRule {
+    pub name: String,
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl SectionRule {
+    fn new(name: &str, patterns: &[&str]) -> Self {
+        SectionRule {
+            name: name.to_string(),
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// Ordered rules assigning gathered files to output sections.
+///
+/// Sections are emitted in the order they are listed. Each file goes to the first listed
+/// section with a matching pattern, or to `default_section` when none match. The default
+/// section may be listed (usually without patterns) to choose its position; otherwise it comes
+/// last.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CategoryRules {
+    pub sections: Vec<SectionRule>,
+    pub default_section: String,
+}
+
+impl Default for CategoryRules {
+    fn default() -> Self {
+        CategoryRules {
+            sections: vec![
+                SectionRule::new(
+                    "Configuration Files",
+                    &[
+                        "*.toml",
+                        "*.json",
+                        "*.yaml",
+                        "*.yml",
+                        "*.ini",
+                        "*.cfg",
+                        "*.conf",
+                        "*.properties",
+                        ".editorconfig",
+                        "Dockerfile",
+                        "Makefile",
+                        "CMakeLists.txt",
+                        "*.cmake",
+                        "meson.build",
+                        // Python
+                        "setup.py",
+                        "requirements*.txt",
+                        "Pipfile",
+                        // JavaScript / TypeScript
+                        ".eslintrc*",
+                        ".prettierrc*",
+                        ".babelrc",
+                        // Go
+                        "go.mod",
+                        "go.sum",
+                        // Java
+                        "pom.xml",
+                        "*.gradle",
+                        "*.gradle.kts",
+                    ],
+                ),
+                SectionRule::new(
+                    "Documentation",
+                    &[
+                        "*.md",
+                        "*.markdown",
+                        "*.txt",
+                        "*.rst",
+                        "*.adoc",
+                        "README*",
+                        "LICENSE*",
+                        "CHANGELOG*",
+                        "CONTRIBUTING*",
+                    ],
+                ),
+                SectionRule::new("Source Files", &[]),
+                SectionRule::new(
+                    "Tests",
+                    &[
+                        "tests/",
+                        "test/",
+                        "__tests__/",
+                        "spec/",
+                        // Python
+                        "test_*.py",
+                        "*_test.py",
+                        "conftest.py",
+                        // JavaScript / TypeScript
+                        "*.test.ts",
+                        "*.test.tsx",
+                        "*.spec.ts",
+                        "*.spec.tsx",
+                        "*.test.js",
+                        "*.test.jsx",
+                        "*.spec.js",
+                        "*.spec.jsx",
+                        // Go
+                        "*_test.go",
+                        // Java
+                        "*Test.java",
+                        "*Tests.java",
+                        "*IT.java",
+                        // C / C++
+                        "*_test.c",
+                        "*_test.cc",
+                        "*_test.cpp",
+                        "*_unittest.cc",
+                        "*_unittest.cpp",
+                        "test_*.c",
+                        "test_*.cpp",
+                    ],
+                ),
+            ],
+            default_section: "Source Files".to_string(),
+        }
+    }
+}
+
+impl CategoryRules {
+    /// Compiles the rules into a matcher, failing on the first invalid pattern.
+    pub fn compile(&self) -> Result<CategoryMatcher, ignore::Error> {
+        let mut names = Vec::new();
+        let mut matchers = Vec::new();
+
+        for section in &self.sections {
+            let mut builder = GitignoreBuilder::new("");
+            for pattern in &section.patterns {
+                builder.add_line(None, pattern)?;
+            }
+            names.push(section.name.clone());
+            matchers.push(builder.build()?);
+        }
+
+        let default_index = match names.iter().position(|n| *n == self.default_section) {
+            Some(index) => index,
+            None => {
+                names.push(self.default_section.clone());
+                matchers.push(Gitignore::empty());
+                names.len() - 1
+            }
+        };
+
+        Ok(CategoryMatcher {
+            names,
+            matchers,
+            default_index,
+        })
+    }
+}
+
+/// Compiled `CategoryRules`.
+pub struct CategoryMatcher {
+    names: Vec<String>,
+    matchers: Vec<Gitignore>,
+    default_index: usize,
+}
+
+impl CategoryMatcher {
+    /// Section names, in output order.
+    pub fn section_names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the index of the section a file belongs to, given its path relative to the
+    /// project root.
+    pub fn categorize(&self, relative_path: &Path) -> usize {
+        self.matchers
+            .iter()
+            .position(|matcher| {
+                matcher
+                    .matched_path_or_any_parents(relative_path, false)
+                    .is_ignore()
+            })
+            .unwrap_or(self.default_index)
+    }
+}
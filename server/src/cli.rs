@@ -1,6 +1,12 @@
 use crate::cli_handlers;
 use crate::config::Config;
-use std::path::PathBuf;
+use crate::contexter::{BudgetStrategy, ContextOptions, GatherOptions, OversizePolicy, SizeLimits};
+use crate::formatter::{HeaderOptions, OutputFormat};
+use crate::git::GitChanges;
+use crate::project::ContexterRequest;
+use crate::tokenizer::TokenizerKind;
+use crate::transform::Transforms;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -20,11 +26,78 @@ pub enum Cli {
         #[structopt(parse(from_os_str))]
         directory: PathBuf,
 
-        #[structopt(short, long, help = "File extensions to include")]
-        extensions: Vec<String>,
+        #[structopt(flatten)]
+        filter: FilterArgs,
 
-        #[structopt(short, long, help = "Patterns to ignore")]
-        ignore: Vec<String>,
+        #[structopt(flatten)]
+        size: SizeArgs,
+
+        #[structopt(flatten)]
+        output: OutputArgs,
+
+        #[structopt(
+            long,
+            help = "Report on stderr which files were included and which were skipped, and why"
+        )]
+        report: bool,
+
+        #[structopt(flatten)]
+        git: GitArgs,
+
+        #[structopt(flatten)]
+        content: ContentArgs,
+    },
+
+    #[structopt(
+        name = "diff",
+        about = "Gather the diffs of changes in git for review, optionally with the whole changed files"
+    )]
+    Diff {
+        #[structopt(parse(from_os_str))]
+        directory: PathBuf,
+
+        #[structopt(
+            help = "Revision to compare from (the working tree is compared against HEAD by default)"
+        )]
+        from: Option<String>,
+
+        #[structopt(help = "Revision to compare to, instead of the working tree")]
+        to: Option<String>,
+
+        #[structopt(
+            short = "U",
+            long,
+            default_value = "3",
+            help = "Lines of context around each change"
+        )]
+        context_lines: u32,
+
+        #[structopt(long, help = "Also include the whole content of the changed files")]
+        full: bool,
+
+        #[structopt(flatten)]
+        filter: FilterArgs,
+
+        #[structopt(flatten)]
+        size: SizeArgs,
+
+        #[structopt(flatten)]
+        output: OutputArgs,
+    },
+
+    #[structopt(
+        name = "ls",
+        about = "List the files of a project or directory with their size, lines, tokens and section"
+    )]
+    Ls {
+        #[structopt(help = "A registered project's name, or a directory")]
+        target: String,
+
+        #[structopt(short, long, help = "Also list skipped and ignored files, and why")]
+        all: bool,
+
+        #[structopt(long, help = "Print the entries as JSON")]
+        json: bool,
     },
 
     #[structopt(name = "config", about = "Manage configuration")]
@@ -32,6 +105,76 @@ pub enum Cli {
         #[structopt(subcommand)]
         cmd: ConfigCommand,
     },
+
+    #[structopt(
+        name = "preset",
+        about = "Manage and run saved selections of project files"
+    )]
+    Preset {
+        #[structopt(subcommand)]
+        cmd: PresetCommand,
+    },
+}
+
+// Parsed once from the command line, so the size of the variants does not matter
+#[allow(clippy::large_enum_variant)]
+#[derive(StructOpt)]
+pub enum PresetCommand {
+    #[structopt(name = "add", about = "Add or replace a preset")]
+    Add {
+        #[structopt(help = "Project name")]
+        project: String,
+
+        #[structopt(help = "Preset name")]
+        name: String,
+
+        #[structopt(
+            help = "Files, directories or gitignore-style globs relative to the project root"
+        )]
+        paths: Vec<String>,
+
+        #[structopt(
+            long,
+            help = "Gitignore-style globs narrowing the selection, relative to the project root"
+        )]
+        include: Vec<String>,
+
+        #[structopt(flatten)]
+        output: OutputArgs,
+
+        #[structopt(flatten)]
+        size: SizeArgs,
+
+        #[structopt(flatten)]
+        git: GitArgs,
+
+        #[structopt(flatten)]
+        content: ContentArgs,
+    },
+
+    #[structopt(name = "list", about = "List a project's presets")]
+    List {
+        #[structopt(help = "Project name")]
+        project: String,
+    },
+
+    #[structopt(name = "remove", about = "Remove a preset")]
+    Remove {
+        #[structopt(help = "Project name")]
+        project: String,
+
+        #[structopt(help = "Preset name")]
+        name: String,
+    },
+
+    #[structopt(name = "run", about = "Gather context using a preset")]
+    Run {
+        #[structopt(help = "Project name")]
+        project: String,
+
+        #[structopt(help = "Preset name")]
+        name: String,
+    },
 }
 
 #[derive(StructOpt)]
@@ -43,6 +186,27 @@ pub enum ConfigCommand {
 
         #[structopt(help = "Project path")]
         path: PathBuf,
+
+        #[structopt(flatten)]
+        profile: ProfileArgs,
+    },
+
+    #[structopt(
+        name = "edit-project",
+        about = "Edit a project's path or gathering profile"
+    )]
+    EditProject {
+        #[structopt(help = "Project name")]
+        name: String,
+
+        #[structopt(long, help = "New project path", parse(from_os_str))]
+        path: Option<PathBuf>,
+
+        #[structopt(long, help = "Clear the profile before applying the given options")]
+        reset: bool,
+
+        #[structopt(flatten)]
+        profile: ProfileArgs,
     },
 
     #[structopt(name = "remove-project", about = "Remove a project")]
@@ -78,10 +242,336 @@ pub enum ConfigCommand {
         address: String,
     },
 
+    #[structopt(
+        name = "set-categories",
+        about = "Set the rules assigning files to output sections from a JSON file"
+    )]
+    SetCategories {
+        #[structopt(help = "JSON file with the section rules", parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(long, help = "Apply the rules to this project only")]
+        project: Option<String>,
+    },
+
+    #[structopt(
+        name = "reset-categories",
+        about = "Restore the default rules assigning files to output sections"
+    )]
+    ResetCategories {
+        #[structopt(long, help = "Reset the rules of this project only")]
+        project: Option<String>,
+    },
+
+    #[structopt(
+        name = "set-size-limits",
+        about = "Set the default size limits, for projects without their own and for gather and diff"
+    )]
+    SetSizeLimits {
+        #[structopt(flatten)]
+        size: SizeArgs,
+
+        #[structopt(long, help = "Clear the limits before applying the given ones")]
+        reset: bool,
+    },
+
     #[structopt(name = "list", about = "List current configuration")]
     List,
 }
 
+// Gathering profile options shared by `add-project` and `edit-project`. Options that are
+// given replace the project's current values; the others are left untouched.
+#[derive(StructOpt)]
+pub struct ProfileArgs {
+    #[structopt(short, long, help = "File extensions to include")]
+    pub extensions: Vec<String>,
+
+    #[structopt(long, help = "Regex patterns to exclude")]
+    pub exclude: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Gitignore-style globs selecting the files to include, relative to the project root"
+    )]
+    pub include: Vec<String>,
+
+    #[structopt(flatten)]
+    pub size: SizeArgs,
+
+    #[structopt(long, help = "Default output format (plain, markdown, xml, json)")]
+    pub format: Option<OutputFormat>,
+}
+
+// Options choosing the files to gather, shared by `gather` and `diff`.
+//
+// Plain comments rather than doc comments on the structs flattened into commands: structopt
+// would take a doc comment as the description of every command the struct is flattened into.
+#[derive(StructOpt)]
+pub struct FilterArgs {
+    #[structopt(short, long, help = "File extensions to include")]
+    pub extensions: Vec<String>,
+
+    #[structopt(short, long, help = "Patterns to ignore")]
+    pub ignore: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Gitignore-style globs selecting the files to include, relative to the directory (excludes take precedence)"
+    )]
+    pub include: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Do not skip version control, dependency and build directories, lock files and logs"
+    )]
+    pub no_default_excludes: bool,
+
+    #[structopt(
+        long,
+        help = "Leave secrets in the files and diffs instead of redacting them"
+    )]
+    pub no_redact: bool,
+}
+
+// Options shaping the output and its token budget, shared by `gather`, `diff` and
+// `preset add`. Options that are not given are left to the defaults, or to the preset's caller.
+#[derive(StructOpt)]
+pub struct OutputArgs {
+    #[structopt(long, help = "Maximum number of tokens in the output")]
+    pub max_tokens: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Tokenizer used to count tokens (estimate, cl100k, o200k; estimate by default)"
+    )]
+    pub tokenizer: Option<TokenizerKind>,
+
+    #[structopt(
+        long,
+        help = "What to do when the token budget is exceeded (stop, truncate, prioritize; stop by default)"
+    )]
+    pub budget_strategy: Option<BudgetStrategy>,
+
+    #[structopt(
+        short,
+        long,
+        help = "Output format (plain, markdown, xml, json; plain by default)"
+    )]
+    pub format: Option<OutputFormat>,
+
+    #[structopt(long, help = "Leave file sizes out of the file headers")]
+    pub omit_size: bool,
+
+    #[structopt(long, help = "Leave modification times out of the file headers")]
+    pub omit_modified: bool,
+}
+
+// Options choosing how each file's content is shown, and whether a tree of the files comes
+// with it, shared by `gather` and `preset add`.
+#[derive(StructOpt)]
+pub struct ContentArgs {
+    #[structopt(
+        long,
+        help = "Gitignore-style globs selecting files reduced to their imports and item signatures, with function bodies elided (Rust, Python and TypeScript)"
+    )]
+    pub skeleton: Vec<String>,
+
+    #[structopt(
+        long,
+        help = "Gitignore-style globs selecting files kept whole even though --skeleton selects them"
+    )]
+    pub focus: Vec<String>,
+
+    #[structopt(flatten)]
+    pub transforms: TransformArgs,
+
+    #[structopt(
+        long,
+        help = "Open the output with a tree of the files and their sizes"
+    )]
+    pub tree: bool,
+
+    #[structopt(
+        long,
+        help = "Output only the tree of the files, without their content"
+    )]
+    pub tree_only: bool,
+
+    #[structopt(long, help = "Also draw the skipped files in the tree, and why")]
+    pub tree_skipped: bool,
+}
+
+// Options restricting gathering to the files changed in git, shared by `gather` and
+// `preset add`.
+#[derive(StructOpt)]
+pub struct GitArgs {
+    #[structopt(
+        long,
+        help = "Only gather files changed since this git ref (branch, tag or commit), committed or not",
+        conflicts_with_all = &["staged", "uncommitted"]
+    )]
+    pub since: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Only gather files with staged changes",
+        conflicts_with = "uncommitted"
+    )]
+    pub staged: bool,
+
+    #[structopt(
+        long,
+        help = "Only gather files with uncommitted changes, staged or not, and untracked files"
+    )]
+    pub uncommitted: bool,
+
+    #[structopt(
+        long,
+        help = "Follow the files with their diffs (needs --since, --staged or --uncommitted)"
+    )]
+    pub diff: bool,
+}
+
+impl GitArgs {
+    /// The changes the options select, if any.
+    pub fn changes(&self) -> Result<Option<GitChanges>, String> {
+        let changes = match (&self.since, self.staged, self.uncommitted) {
+            (Some(reference), _, _) => Some(GitChanges::Since(reference.clone())),
+            (None, true, _) => Some(GitChanges::Staged),
+            (None, false, true) => Some(GitChanges::Uncommitted),
+            (None, false, false) => None,
+        };
+        if self.diff && changes.is_none() {
+            return Err("--diff needs --since, --staged or --uncommitted".to_string());
+        }
+        Ok(changes)
+    }
+}
+
+// Options limiting the size of the files in the output, shared by `gather`, `diff`,
+// `preset add`, `config set-size-limits` and the project profiles. Limits that are not given
+// fall back to the configured ones.
+#[derive(StructOpt)]
+pub struct SizeArgs {
+    #[structopt(
+        long,
+        help = "Skip files larger than this many bytes, or excerpt them with --oversized truncate"
+    )]
+    pub max_file_size: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Skip the files that would take the output past this many bytes of files, or excerpt them with --oversized truncate"
+    )]
+    pub max_total_size: Option<u64>,
+
+    #[structopt(
+        long,
+        help = "Truncate files over a size limit to their start and end instead of skipping them (skip, truncate)"
+    )]
+    pub oversized: Option<OversizePolicy>,
+}
+
+impl SizeArgs {
+    /// The given limits, falling back to `defaults`.
+    pub fn limits(&self, defaults: SizeLimits) -> SizeLimits {
+        SizeLimits {
+            max_file_size: self.max_file_size.or(defaults.max_file_size),
+            max_total_size: self.max_total_size.or(defaults.max_total_size),
+            oversized: self.oversized.unwrap_or(defaults.oversized),
+        }
+    }
+}
+
+// Options transforming each file's content, shared by `gather` and `preset add`.
+#[derive(StructOpt)]
+pub struct TransformArgs {
+    #[structopt(
+        long,
+        help = "Remove comments, in languages whose comment syntax is known"
+    )]
+    pub strip_comments: bool,
+
+    #[structopt(long, help = "Keep doc comments when stripping comments")]
+    pub keep_doc_comments: bool,
+
+    #[structopt(
+        long,
+        help = "Remove the comments a file opens with if they look like a license header"
+    )]
+    pub strip_license: bool,
+
+    #[structopt(
+        long,
+        help = "Regex recognizing license headers, instead of the configured or built-in one"
+    )]
+    pub license_pattern: Option<String>,
+
+    #[structopt(long, help = "Replace runs of blank lines with a single one")]
+    pub collapse_blank_lines: bool,
+
+    #[structopt(long, help = "Remove whitespace at the end of lines")]
+    pub trim_trailing_whitespace: bool,
+}
+
+impl TransformArgs {
+    pub fn transforms(self) -> Transforms {
+        Transforms {
+            strip_comments: self.strip_comments,
+            keep_doc_comments: self.keep_doc_comments,
+            strip_license: self.strip_license,
+            license_pattern: self.license_pattern,
+            collapse_blank_lines: self.collapse_blank_lines,
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+        }
+    }
+}
+
+/// Builds the options `gather` and `diff` gather the files of `directory` with and assemble them
+/// with, restricted to the files in `changes`, if any. What only one of them sets is left to it.
+fn build_options(
+    config: &Config,
+    directory: &Path,
+    filter: FilterArgs,
+    size: &SizeArgs,
+    output: OutputArgs,
+    changes: Option<&GitChanges>,
+) -> (GatherOptions, ContextOptions) {
+    let default_excludes = if filter.no_default_excludes {
+        Vec::new()
+    } else {
+        config.default_exclude_globs()
+    };
+    let size_limits = size.limits(config.size_limits(None));
+    let gather = GatherOptions {
+        extensions: filter.extensions,
+        excludes: filter.ignore,
+        includes: filter.include,
+        max_file_size: size_limits.skip_size(),
+        default_excludes: Some(default_excludes),
+        blocked_files: config.redaction.blocked_files.clone(),
+        git: changes.cloned(),
+        defer_content_check: true,
+    };
+    let options = ContextOptions {
+        max_tokens: output.max_tokens,
+        tokenizer: output.tokenizer.unwrap_or_default(),
+        budget_strategy: output.budget_strategy.unwrap_or_default(),
+        format: output.format.unwrap_or_default(),
+        header: HeaderOptions {
+            omit_size: output.omit_size,
+            omit_modified: output.omit_modified,
+        },
+        categories: config.category_rules(None),
+        root: Some(directory.to_path_buf()),
+        revision: changes.and_then(GitChanges::revision).map(String::from),
+        redaction: config.redaction.redaction().filter(|_| !filter.no_redact),
+        size_limits,
+        ..Default::default()
+    };
+    (gather, options)
+}
+
 pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::from_args();
     let mut config = Config::load()?;
@@ -100,13 +590,62 @@ pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
         }
         Cli::Gather {
             directory,
-            extensions,
-            ignore,
-        } => cli_handlers::handle_gather(directory, extensions, ignore),
+            filter,
+            size,
+            output,
+            report,
+            git,
+            content,
+        } => {
+            let changes = git.changes()?;
+            let (gather, options) =
+                build_options(&config, &directory, filter, &size, output, changes.as_ref());
+            let options = ContextOptions {
+                tree: content.tree,
+                tree_only: content.tree_only,
+                diffs: changes.filter(|_| git.diff),
+                skeleton: content.skeleton,
+                focus: content.focus,
+                transforms: config.transforms(content.transforms.transforms()),
+                ..options
+            };
+            let tree_skipped = content.tree_skipped;
+            cli_handlers::handle_gather(directory, gather, options, report, tree_skipped)
+        }
+        Cli::Diff {
+            directory,
+            from,
+            to,
+            context_lines,
+            full,
+            filter,
+            size,
+            output,
+        } => {
+            let changes = GitChanges::comparing(from, to)?;
+            let (gather, options) =
+                build_options(&config, &directory, filter, &size, output, Some(&changes));
+            let options = ContextOptions {
+                diffs: Some(changes),
+                diff_context: Some(context_lines),
+                diffs_only: !full,
+                ..options
+            };
+            cli_handlers::handle_gather(directory, gather, options, false, false)
+        }
+        Cli::Ls { target, all, json } => cli_handlers::handle_ls(&config, target, all, json),
         Cli::Config { cmd } => match cmd {
-            ConfigCommand::AddProject { name, path } => {
-                cli_handlers::handle_config_add_project(&mut config, name, path)
-            }
+            ConfigCommand::AddProject {
+                name,
+                path,
+                profile,
+            } => cli_handlers::handle_config_add_project(&mut config, name, path, profile),
+            ConfigCommand::EditProject {
+                name,
+                path,
+                reset,
+                profile,
+            } => cli_handlers::handle_config_edit_project(&mut config, name, path, reset, profile),
             ConfigCommand::RemoveProject { name } => {
                 cli_handlers::handle_config_remove_project(&mut config, name)
             }
@@ -126,10 +665,67 @@ pub fn run_cli() -> Result<(), Box<dyn std::error::Error>> {
             ConfigCommand::SetAddress { address } => {
                 cli_handlers::handle_config_set_address(&mut config, address)
             }
+            ConfigCommand::SetCategories { file, project } => {
+                cli_handlers::handle_config_set_categories(&mut config, file, project)
+            }
+            ConfigCommand::ResetCategories { project } => {
+                cli_handlers::handle_config_reset_categories(&mut config, project)
+            }
+            ConfigCommand::SetSizeLimits { size, reset } => {
+                cli_handlers::handle_config_set_size_limits(&mut config, size, reset)
+            }
             ConfigCommand::List => {
                 cli_handlers::handle_config_list(&config);
                 Ok(())
             }
         },
+        Cli::Preset { cmd } => match cmd {
+            PresetCommand::Add {
+                project,
+                name,
+                paths,
+                include,
+                output,
+                size,
+                git,
+                content,
+            } => {
+                let preset = ContexterRequest {
+                    paths: Some(paths).filter(|paths| !paths.is_empty()),
+                    include: Some(include).filter(|include| !include.is_empty()),
+                    max_tokens: output.max_tokens,
+                    tokenizer: output.tokenizer,
+                    budget_strategy: output.budget_strategy,
+                    format: output.format,
+                    max_file_size: size.max_file_size,
+                    max_total_size: size.max_total_size,
+                    oversized: size.oversized,
+                    git: git.changes()?,
+                    git_diff: git.diff.then_some(true),
+                    diff_context: None,
+                    diff_only: None,
+                    skeleton: Some(content.skeleton).filter(|skeleton| !skeleton.is_empty()),
+                    focus: Some(content.focus).filter(|focus| !focus.is_empty()),
+                    transforms: Some(content.transforms.transforms())
+                        .filter(|transforms| *transforms != Transforms::default()),
+                    omit_size: output.omit_size.then_some(true),
+                    omit_modified: output.omit_modified.then_some(true),
+                    tree: content.tree.then_some(true),
+                    tree_only: content.tree_only.then_some(true),
+                    tree_skipped: content.tree_skipped.then_some(true),
+                };
+                cli_handlers::handle_preset_add(&mut config, project, name, preset)
+            }
+            PresetCommand::List { project } => {
+                cli_handlers::handle_preset_list(&config, project);
+                Ok(())
+            }
+            PresetCommand::Remove { project, name } => {
+                cli_handlers::handle_preset_remove(&mut config, project, name)
+            }
+            PresetCommand::Run { project, name } => {
+                cli_handlers::handle_preset_run(&config, project, name)
+            }
+        },
     }
 }
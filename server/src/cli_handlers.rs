@@ -1,21 +1,220 @@
-use crate::config::Config;
-use crate::contexter::{concatenate_files, gather_relevant_files};
+use crate::categories::CategoryRules;
+use crate::cli::{ProfileArgs, SizeArgs};
+use crate::config::{Config, ProjectConfig};
+use crate::contexter::{
+    gather_files_with_report, stream_files_with_options, ContextChunk, ContextOptions,
+    ContextOutput, GatherOptions,
+};
+use crate::listing::{list_project, EntryStatus};
+use crate::project::{stream_project, ContexterRequest, ProjectError};
+use crate::tokenizer::TokenizerKind;
 use crate::utils::{generate_api_key, hash_api_key};
 use log::info;
+use std::io::{self, Write};
 use std::path::PathBuf;
 
 pub fn handle_gather(
     directory: PathBuf,
-    extensions: Vec<String>,
-    ignore: Vec<String>,
+    gather: GatherOptions,
+    mut options: ContextOptions,
+    report: bool,
+    tree_skipped: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let files = gather_relevant_files(
-        directory.to_str().unwrap(),
-        extensions.iter().map(AsRef::as_ref).collect(),
-        ignore,
+    let gathered = gather_files_with_report(&directory, &[], &gather)?;
+    if tree_skipped {
+        options.tree_skipped = gathered.skipped.clone();
+    }
+    options.removed = gathered.removed;
+    let mut stdout = io::stdout().lock();
+    let output = stream_files_with_options(gathered.files, &options, |chunk| {
+        write_chunk(&mut stdout, chunk)
+    })?
+    .with_gather_report(gathered.skipped);
+    writeln!(stdout)?;
+    if report {
+        print_report(&output);
+    }
+    print_summary(&output, options.max_tokens, options.tokenizer, report);
+    Ok(())
+}
+
+/// Lists the included files on stderr, as the first half of the gather report.
+fn print_report(output: &ContextOutput) {
+    eprintln!("Included {} files:", output.files.len());
+    for path in &output.files {
+        eprintln!("  {}", path);
+    }
+}
+
+/// Writes a piece of the context to stdout as soon as it is assembled.
+fn write_chunk(stdout: &mut impl Write, chunk: ContextChunk) -> io::Result<()> {
+    match chunk {
+        ContextChunk::Text(text) => stdout.write_all(text.as_bytes()),
+        ContextChunk::Structure { rendered, .. }
+        | ContextChunk::File { rendered, .. }
+        | ContextChunk::Duplicate { rendered, .. }
+        | ContextChunk::Diff { rendered, .. } => stdout.write_all(rendered.as_bytes()),
+    }
+}
+
+/// Reports how files were decoded, secrets redacted, oversized files excerpted, skipped files
+/// and budget decisions on stderr so stdout stays pipeable. Files skipped by design, such as
+/// binaries and excluded files, and what the transforms saved on each file are only listed in a
+/// full report.
+fn print_summary(
+    output: &ContextOutput,
+    max_tokens: Option<usize>,
+    tokenizer: TokenizerKind,
+    report: bool,
+) {
+    for decoded in &output.decoded {
+        eprintln!("decoded: {} as {}", decoded.path, decoded.encoding);
+    }
+    for redacted in &output.redacted {
+        let redactions: Vec<String> = redacted
+            .redactions
+            .iter()
+            .map(|(kind, count)| format!("{} x{}", kind, count))
+            .collect();
+        let diff = if redacted.diff { " diff" } else { "" };
+        eprintln!(
+            "redacted: {}{} ({})",
+            redacted.path,
+            diff,
+            redactions.join(", ")
+        );
+    }
+    for excerpted in &output.excerpted {
+        eprintln!(
+            "excerpted: {} ({} of {} bytes)",
+            excerpted.path, excerpted.kept, excerpted.size
+        );
+    }
+    for skipped in &output.skipped {
+        if report || !skipped.reason.is_expected() {
+            eprintln!("skipped: {}", skipped);
+        }
+    }
+    if report {
+        for transformed in &output.transformed {
+            eprintln!(
+                "transformed: {} ({} -> {} tokens)",
+                transformed.path, transformed.tokens_before, transformed.tokens_after
+            );
+        }
+    }
+    let Some(max_tokens) = max_tokens else {
+        return;
+    };
+    eprintln!(
+        "Used {} of {} tokens ({} tokenizer) across {} files",
+        output.tokens,
+        max_tokens,
+        tokenizer,
+        output.files.len()
+    );
+    for path in &output.truncated {
+        eprintln!("  truncated: {}", path);
+    }
+    for dropped in &output.dropped {
+        eprintln!("  dropped: {} ({} tokens)", dropped.path, dropped.tokens);
+    }
+}
+
+/// Lists the files of a registered project, or of a directory gathered with the default
+/// excludes, as a table or as JSON.
+pub fn handle_ls(
+    config: &Config,
+    target: String,
+    all: bool,
+    json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (root, gather, categories) = match config.projects.get(&target) {
+        Some(project) => (
+            project.path.clone(),
+            config.gather_options(project),
+            config.category_rules(Some(&target)),
+        ),
+        None => {
+            let directory = PathBuf::from(&target);
+            if !directory.is_dir() {
+                return Err(format!("'{}' is neither a project nor a directory", target).into());
+            }
+            let gather = GatherOptions {
+                default_excludes: Some(config.default_exclude_globs()),
+                blocked_files: config.redaction.blocked_files.clone(),
+                ..Default::default()
+            };
+            (directory, gather, config.category_rules(None))
+        }
+    };
+
+    let mut entries = list_project(&root, &gather, &categories, None)?;
+    if !all {
+        entries.retain(|entry| entry.status == EntryStatus::Included);
+    }
+    let mut stdout = io::stdout().lock();
+    if json {
+        serde_json::to_writer_pretty(&mut stdout, &entries)?;
+        writeln!(stdout)?;
+        return Ok(());
+    }
+
+    let width = entries
+        .iter()
+        .map(|entry| entry.path.len() + usize::from(entry.directory))
+        .max()
+        .unwrap_or(0)
+        .max("PATH".len());
+    let section_width = entries
+        .iter()
+        .filter_map(|entry| entry.section.as_deref())
+        .map(str::len)
+        .max()
+        .unwrap_or(0)
+        .max("SECTION".len());
+    let row = |columns: [&str; 7]| {
+        let mut row = format!(
+            "{:<width$}  {:>10}  {:>7}  {:>7}  {:<12}  {:<section_width$}",
+            columns[0], columns[1], columns[2], columns[3], columns[4], columns[5]
+        );
+        if all {
+            row = format!("{}  {}", row, columns[6]);
+        }
+        row.trim_end().to_string()
+    };
+    writeln!(
+        stdout,
+        "{}",
+        row(["PATH", "SIZE", "LINES", "TOKENS", "LANGUAGE", "SECTION", "STATUS"])
     )?;
-    let (content, _) = concatenate_files(files)?;
-    println!("{}", content);
+    for entry in &entries {
+        let number = |n: Option<usize>| n.map_or("-".to_string(), |n| n.to_string());
+        let status = match (entry.status, entry.reason) {
+            (EntryStatus::Included, _) => "included".to_string(),
+            (EntryStatus::Skipped, Some(reason)) => format!("skipped ({})", reason),
+            (EntryStatus::Skipped, None) => "skipped".to_string(),
+            (EntryStatus::Ignored, _) => "ignored".to_string(),
+        };
+        let path = if entry.directory {
+            format!("{}/", entry.path)
+        } else {
+            entry.path.clone()
+        };
+        writeln!(
+            stdout,
+            "{}",
+            row([
+                &path,
+                &number(entry.size.map(|size| size as usize)),
+                &number(entry.lines),
+                &number(entry.tokens),
+                entry.language.as_deref().unwrap_or("-"),
+                entry.section.as_deref().unwrap_or("-"),
+                &status,
+            ])
+        )?;
+    }
     Ok(())
 }
 
@@ -23,13 +222,70 @@ pub fn handle_config_add_project(
     config: &mut Config,
     name: String,
     path: PathBuf,
+    profile: ProfileArgs,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    config.add_project(name.clone(), path.clone());
+    let mut project = ProjectConfig::new(path.clone());
+    apply_profile_args(&mut project, profile);
+    config.projects.insert(name.clone(), project);
     config.save()?;
     info!("Project '{}' added successfully with path {:?}", name, path);
     Ok(())
 }
 
+pub fn handle_config_edit_project(
+    config: &mut Config,
+    name: String,
+    path: Option<PathBuf>,
+    reset: bool,
+    profile: ProfileArgs,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(project) = config.projects.get_mut(&name) else {
+        println!("Project '{}' not found", name);
+        return Ok(());
+    };
+
+    if reset {
+        *project = ProjectConfig {
+            categories: project.categories.take(),
+            presets: std::mem::take(&mut project.presets),
+            ..ProjectConfig::new(project.path.clone())
+        };
+    }
+    if let Some(path) = path {
+        project.path = path;
+    }
+    apply_profile_args(project, profile);
+
+    config.save()?;
+    info!("Project '{}' updated successfully", name);
+    Ok(())
+}
+
+/// Copies the options given on the command line into a project's profile.
+fn apply_profile_args(project: &mut ProjectConfig, profile: ProfileArgs) {
+    if !profile.extensions.is_empty() {
+        project.extensions = profile.extensions;
+    }
+    if !profile.exclude.is_empty() {
+        project.excludes = profile.exclude;
+    }
+    if !profile.include.is_empty() {
+        project.includes = profile.include;
+    }
+    if profile.size.max_file_size.is_some() {
+        project.max_file_size = profile.size.max_file_size;
+    }
+    if profile.size.max_total_size.is_some() {
+        project.max_total_size = profile.size.max_total_size;
+    }
+    if profile.size.oversized.is_some() {
+        project.oversized = profile.size.oversized;
+    }
+    if profile.format.is_some() {
+        project.format = profile.format;
+    }
+}
+
 pub fn handle_config_remove_project(
     config: &mut Config,
     name: String,
@@ -69,7 +325,7 @@ pub fn handle_config_remove_key(
 
 pub fn handle_config_list_keys(config: &Config) {
     println!("API Keys:");
-    for (name, _) in &config.api_keys {
+    for name in config.api_keys.keys() {
         println!("  {}: {}", name, "*".repeat(40)); // Hide the hashed key in the output
     }
 }
@@ -94,16 +350,238 @@ pub fn handle_config_set_address(
     Ok(())
 }
 
+pub fn handle_config_set_size_limits(
+    config: &mut Config,
+    size: SizeArgs,
+    reset: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if reset {
+        config.max_file_size = None;
+        config.max_total_size = None;
+        config.oversized = None;
+    }
+    if size.max_file_size.is_some() {
+        config.max_file_size = size.max_file_size;
+    }
+    if size.max_total_size.is_some() {
+        config.max_total_size = size.max_total_size;
+    }
+    if size.oversized.is_some() {
+        config.oversized = size.oversized;
+    }
+    config.save()?;
+    info!("Size limits updated successfully");
+    Ok(())
+}
+
+pub fn handle_config_set_categories(
+    config: &mut Config,
+    file: PathBuf,
+    project: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let rules: CategoryRules = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+    rules.compile()?;
+
+    match project {
+        Some(name) => {
+            let Some(project) = config.projects.get_mut(&name) else {
+                println!("Project '{}' not found", name);
+                return Ok(());
+            };
+            project.categories = Some(rules);
+            config.save()?;
+            info!("Section rules for project '{}' set from {:?}", name, file);
+        }
+        None => {
+            config.categories = Some(rules);
+            config.save()?;
+            info!("Section rules set from {:?}", file);
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_config_reset_categories(
+    config: &mut Config,
+    project: Option<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match project {
+        Some(name) => {
+            let Some(project) = config.projects.get_mut(&name) else {
+                println!("Project '{}' not found", name);
+                return Ok(());
+            };
+            project.categories = None;
+            config.save()?;
+            info!("Section rules for project '{}' reset", name);
+        }
+        None => {
+            config.categories = None;
+            config.save()?;
+            info!("Section rules reset to the defaults");
+        }
+    }
+    Ok(())
+}
+
+pub fn handle_preset_add(
+    config: &mut Config,
+    project: String,
+    name: String,
+    preset: ContexterRequest,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match preset.validate(config, &project) {
+        Err(ProjectError::NotFound(_)) => {
+            println!("Project '{}' not found", project);
+            return Ok(());
+        }
+        result => result?,
+    }
+    let project_config = config
+        .projects
+        .get_mut(&project)
+        .expect("the project was found when validating the preset");
+    project_config.presets.insert(name.clone(), preset);
+    config.save()?;
+    info!("Preset '{}' saved for project '{}'", name, project);
+    Ok(())
+}
+
+pub fn handle_preset_list(config: &Config, project: String) {
+    let Some(project_config) = config.projects.get(&project) else {
+        println!("Project '{}' not found", project);
+        return;
+    };
+    let mut names: Vec<&String> = project_config.presets.keys().collect();
+    names.sort();
+    println!("Presets for '{}':", project);
+    for name in names {
+        let preset = &project_config.presets[name];
+        let paths = preset.paths.as_deref().unwrap_or_default();
+        if paths.is_empty() {
+            println!("  {}: (entire project)", name);
+        } else {
+            println!("  {}: {}", name, paths.join(" "));
+        }
+        if let Some(include) = &preset.include {
+            println!("    Include: {}", include.join(" "));
+        }
+        if let Some(max_tokens) = preset.max_tokens {
+            println!("    Max Tokens: {}", max_tokens);
+        }
+        if let Some(tokenizer) = preset.tokenizer {
+            println!("    Tokenizer: {}", tokenizer);
+        }
+        if let Some(budget_strategy) = preset.budget_strategy {
+            println!("    Budget Strategy: {}", budget_strategy);
+        }
+        if let Some(format) = preset.format {
+            println!("    Format: {}", format);
+        }
+    }
+}
+
+pub fn handle_preset_remove(
+    config: &mut Config,
+    project: String,
+    name: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let removed = config
+        .projects
+        .get_mut(&project)
+        .and_then(|project_config| project_config.presets.remove(&name));
+    if removed.is_some() {
+        config.save()?;
+        info!("Preset '{}' removed from project '{}'", name, project);
+    } else {
+        println!("Preset '{}' not found in project '{}'", name, project);
+    }
+    Ok(())
+}
+
+pub fn handle_preset_run(
+    config: &Config,
+    project: String,
+    name: String,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(preset) = config
+        .projects
+        .get(&project)
+        .and_then(|project_config| project_config.presets.get(&name))
+    else {
+        println!("Preset '{}' not found in project '{}'", name, project);
+        return Ok(());
+    };
+    let mut stdout = io::stdout().lock();
+    let output = stream_project(config, &project, preset, None, |chunk| {
+        write_chunk(&mut stdout, chunk)
+    })?;
+    writeln!(stdout)?;
+    print_summary(
+        &output,
+        preset.max_tokens,
+        preset.tokenizer.unwrap_or_default(),
+        false,
+    );
+    Ok(())
+}
+
 pub fn handle_config_list(config: &Config) {
     println!("Current Configuration:");
     println!("Port: {}", config.port);
     println!("Listen Address: {}", config.listen_address);
     println!("Projects:");
-    for (name, path) in &config.projects {
-        println!("  {}: {:?}", name, path);
+    for (name, project) in &config.projects {
+        println!("  {}: {:?}", name, project.path);
+        if !project.extensions.is_empty() {
+            println!("    Extensions: {}", project.extensions.join(", "));
+        }
+        if !project.excludes.is_empty() {
+            println!("    Excludes: {}", project.excludes.join(", "));
+        }
+        if !project.includes.is_empty() {
+            println!("    Includes: {}", project.includes.join(", "));
+        }
+        if let Some(max_file_size) = project.max_file_size {
+            println!("    Max File Size: {} bytes", max_file_size);
+        }
+        if let Some(max_total_size) = project.max_total_size {
+            println!("    Max Total Size: {} bytes", max_total_size);
+        }
+        if let Some(oversized) = project.oversized {
+            println!("    Oversized Files: {}", oversized);
+        }
+        if let Some(format) = project.format {
+            println!("    Format: {}", format);
+        }
+        if project.categories.is_some() {
+            println!("    Sections: custom");
+        }
+        if !project.presets.is_empty() {
+            let mut presets: Vec<&str> = project.presets.keys().map(String::as_str).collect();
+            presets.sort_unstable();
+            println!("    Presets: {}", presets.join(", "));
+        }
+    }
+    if let Some(max_file_size) = config.max_file_size {
+        println!("Max File Size: {} bytes", max_file_size);
+    }
+    if let Some(max_total_size) = config.max_total_size {
+        println!("Max Total Size: {} bytes", max_total_size);
+    }
+    if let Some(oversized) = config.oversized {
+        println!("Oversized Files: {}", oversized);
+    }
+    println!(
+        "Default Excludes: {}",
+        config.default_exclude_globs().join(" ")
+    );
+    println!("Sections:");
+    for section in &config.category_rules(None).sections {
+        println!("  {}: {}", section.name, section.patterns.join(" "));
     }
     println!("API Keys:");
-    for (name, _) in &config.api_keys {
+    for name in config.api_keys.keys() {
         println!("  {}: {}", name, "*".repeat(40)); // Hide the hashed key in the output
     }
 }
@@ -1,15 +1,207 @@
-use serde::{Deserialize, Serialize};
+use crate::cache::ContentCache;
+use crate::categories::CategoryRules;
+use crate::contexter::{GatherOptions, OversizePolicy, SizeLimits, DEFAULT_EXCLUDES};
+use crate::formatter::OutputFormat;
+use crate::project::ContexterRequest;
+use crate::redact::{Redaction, SecretPattern};
+use crate::transform::Transforms;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// A registered project along with the profile used when gathering its files.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ProjectConfig {
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extensions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excludes: Vec<String>, // Regex patterns
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub includes: Vec<String>, // Gitignore-style globs relative to `path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oversized: Option<OversizePolicy>, // Whether files over a limit are skipped or truncated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub categories: Option<CategoryRules>, // Overrides the global section rules
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub presets: HashMap<String, ContexterRequest>, // Saved requests, by name
+}
+
+impl ProjectConfig {
+    pub fn new(path: PathBuf) -> Self {
+        ProjectConfig {
+            path,
+            extensions: Vec::new(),
+            excludes: Vec::new(),
+            includes: Vec::new(),
+            max_file_size: None,
+            max_total_size: None,
+            oversized: None,
+            format: None,
+            categories: None,
+            presets: HashMap::new(),
+        }
+    }
+
+    /// Returns the gathering filters of this project's profile.
+    pub fn gather_options(&self) -> GatherOptions {
+        GatherOptions {
+            extensions: self.extensions.clone(),
+            excludes: self.excludes.clone(),
+            includes: self.includes.clone(),
+            max_file_size: self.max_file_size,
+            default_excludes: None,
+            blocked_files: Vec::new(),
+            git: None,
+            defer_content_check: false,
+        }
+    }
+}
+
+/// Reads the projects map, accepting both full profiles and the bare paths written by earlier
+/// versions.
+fn deserialize_projects<'de, D>(deserializer: D) -> Result<HashMap<String, ProjectConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ProjectEntry {
+        Path(PathBuf),
+        Profile(Box<ProjectConfig>),
+    }
+
+    let entries = HashMap::<String, ProjectEntry>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let project = match entry {
+                ProjectEntry::Path(path) => ProjectConfig::new(path),
+                ProjectEntry::Profile(project) => *project,
+            };
+            (name, project)
+        })
+        .collect())
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Config {
-    pub projects: HashMap<String, PathBuf>,
+    #[serde(deserialize_with = "deserialize_projects")]
+    pub projects: HashMap<String, ProjectConfig>,
     pub port: u16,
     pub listen_address: String,
     #[serde(default)]
     pub api_keys: HashMap<String, String>, // Map from name to hashed API key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub categories: Option<CategoryRules>, // Overrides the built-in section rules
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_excludes: Option<Vec<String>>, // Replaces the built-in default exclude globs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_excludes: Vec<String>, // Added to the default exclude globs
+    #[serde(default, skip_serializing_if = "CacheConfig::is_default")]
+    pub cache: CacheConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license_pattern: Option<String>, // Replaces the built-in regex recognizing license headers
+    #[serde(default, skip_serializing_if = "RedactionConfig::is_default")]
+    pub redaction: RedactionConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>, // Default for projects without one, and for the CLI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oversized: Option<OversizePolicy>,
+}
+
+/// Settings keeping secrets out of the served context.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RedactionConfig {
+    pub enabled: bool,      // Redact secrets from files and diffs
+    pub high_entropy: bool, // Also redact random-looking strings, off by default
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<SecretPattern>, // Custom detectors, by the name of what they find
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blocked_files: Vec<String>, // Added to the built-in blocked file globs
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        RedactionConfig {
+            enabled: true,
+            high_entropy: false,
+            patterns: Vec::new(),
+            blocked_files: Vec::new(),
+        }
+    }
+}
+
+impl RedactionConfig {
+    fn is_default(&self) -> bool {
+        *self == RedactionConfig::default()
+    }
+
+    /// Returns what to redact, or `None` if redaction is disabled.
+    pub fn redaction(&self) -> Option<Redaction> {
+        self.enabled.then(|| Redaction {
+            high_entropy: self.high_entropy,
+            patterns: self.patterns.clone(),
+        })
+    }
+}
+
+/// Settings of the server's cache of file contents.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub max_entries: usize,
+    pub max_bytes: u64, // Total size of the cached contents
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>, // Where the cache is saved on shutdown and loaded from on startup
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: true,
+            max_entries: 100_000,
+            max_bytes: 256 * 1024 * 1024,
+            path: None,
+        }
+    }
+}
+
+impl CacheConfig {
+    fn is_default(&self) -> bool {
+        *self == CacheConfig::default()
+    }
+
+    /// Creates an empty cache with these limits, or returns `None` if caching is disabled.
+    pub fn build(&self) -> Option<Arc<ContentCache>> {
+        self.enabled
+            .then(|| Arc::new(ContentCache::new(self.max_entries, self.max_bytes)))
+    }
+
+    /// Like `build`, but starts from the copy saved at `path`, if there is one.
+    pub fn load(&self) -> io::Result<Option<Arc<ContentCache>>> {
+        match (&self.path, self.enabled) {
+            (Some(path), true) => Ok(Some(Arc::new(ContentCache::load(
+                path,
+                self.max_entries,
+                self.max_bytes,
+            )?))),
+            _ => Ok(self.build()),
+        }
+    }
 }
 
 impl Default for Config {
@@ -19,6 +211,15 @@ impl Default for Config {
             port: 3030,
             listen_address: "127.0.0.1".to_string(),
             api_keys: HashMap::new(),
+            categories: None,
+            default_excludes: None,
+            extra_excludes: Vec::new(),
+            cache: CacheConfig::default(),
+            license_pattern: None,
+            redaction: RedactionConfig::default(),
+            max_file_size: None,
+            max_total_size: None,
+            oversized: None,
         }
     }
 }
@@ -39,20 +240,86 @@ impl Config {
     }
 
     pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let config_path = Config::get_config_path()?;
+        self.save_to(&Config::get_config_path()?)
+    }
+
+    pub fn save_to(&self, config_path: &Path) -> Result<(), Box<dyn std::error::Error>> {
         let config_str = serde_json::to_string_pretty(self)?;
         fs::write(config_path, config_str)?;
         Ok(())
     }
 
     pub fn add_project(&mut self, name: String, path: PathBuf) {
-        self.projects.insert(name, path);
+        self.projects.insert(name, ProjectConfig::new(path));
     }
 
-    pub fn remove_project(&mut self, name: &str) -> Option<PathBuf> {
+    pub fn remove_project(&mut self, name: &str) -> Option<ProjectConfig> {
         self.projects.remove(name)
     }
 
+    /// Returns the section rules for a project, falling back to the global rules and then to
+    /// the built-in defaults.
+    pub fn category_rules(&self, project: Option<&str>) -> CategoryRules {
+        project
+            .and_then(|name| self.projects.get(name))
+            .and_then(|project| project.categories.as_ref())
+            .or(self.categories.as_ref())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns the default exclude globs: the configured ones, or the built-in ones, followed by
+    /// any extra globs.
+    pub fn default_exclude_globs(&self) -> Vec<String> {
+        let mut globs = match &self.default_excludes {
+            Some(globs) => globs.clone(),
+            None => DEFAULT_EXCLUDES
+                .iter()
+                .map(|glob| glob.to_string())
+                .collect(),
+        };
+        globs.extend(self.extra_excludes.iter().cloned());
+        globs
+    }
+
+    /// Fills in the configured license pattern when the transforms do not give their own.
+    pub fn transforms(&self, transforms: Transforms) -> Transforms {
+        Transforms {
+            license_pattern: transforms
+                .license_pattern
+                .or_else(|| self.license_pattern.clone()),
+            ..transforms
+        }
+    }
+
+    /// Returns the size limits for a project, or for the CLI without one: the project's own,
+    /// falling back to the defaults.
+    pub fn size_limits(&self, project: Option<&ProjectConfig>) -> SizeLimits {
+        SizeLimits {
+            max_file_size: project
+                .and_then(|project| project.max_file_size)
+                .or(self.max_file_size),
+            max_total_size: project
+                .and_then(|project| project.max_total_size)
+                .or(self.max_total_size),
+            oversized: project
+                .and_then(|project| project.oversized)
+                .or(self.oversized)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Returns the gathering filters for a project: its profile plus the default excludes, the
+    /// blocked files and the default file size limit.
+    pub fn gather_options(&self, project: &ProjectConfig) -> GatherOptions {
+        GatherOptions {
+            default_excludes: Some(self.default_exclude_globs()),
+            blocked_files: self.redaction.blocked_files.clone(),
+            max_file_size: self.size_limits(Some(project)).skip_size(),
+            ..project.gather_options()
+        }
+    }
+
     pub fn add_api_key(&mut self, name: String, hashed_key: String) {
         self.api_keys.insert(name, hashed_key);
     }
@@ -61,7 +328,7 @@ impl Config {
         self.api_keys.remove(name);
     }
 
-    fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
+    pub fn get_config_path() -> Result<PathBuf, Box<dyn std::error::Error>> {
         let mut path = dirs::config_dir().ok_or("Could not find config directory")?;
         path.push("contexter");
         fs::create_dir_all(&path)?;
@@ -1,11 +1,149 @@
-use ignore::WalkBuilder;
+use crate::cache::{CachedContent, CachedFile, ContentCache, EncodingName, TokenCost};
+use crate::categories::CategoryRules;
+use crate::digest::ContentDigest;
+use crate::encoding;
+use crate::formatter::{FileRecord, HeaderOptions, OutputFormat, OutputFormatter, CHANGES_TITLE};
+use crate::git::{self, GitChanges};
+use crate::pipeline::map_in_order;
+use crate::redact::{Redaction, Redactor, BLOCKED_FILES};
+use crate::skeleton;
+use crate::tokenizer::{Tokenizer, TokenizerKind};
+use crate::transform::{TransformPipeline, Transforms, DEFAULT_LICENSE_PATTERN};
+use crate::tree::{format_size, ProjectTree};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Match, WalkBuilder, WalkState};
 use regex::Regex;
-use std::collections::hash_map::DefaultHasher;
-use std::collections::HashSet;
-use std::fs::{metadata, read_to_string};
-use std::hash::{Hash, Hasher};
-use std::io;
-use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs::{metadata, read, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+/// Errors raised while gathering and assembling files.
+#[derive(Debug, Error)]
+pub enum ContexterError {
+    #[error("Invalid pattern '{pattern}': {message}")]
+    InvalidPattern { pattern: String, message: String },
+    #[error("Path '{path}' is outside the project")]
+    PathOutsideProject { path: String },
+    #[error("Git error: {0}")]
+    Git(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ContexterError {
+    fn invalid_pattern(pattern: &str, message: impl fmt::Display) -> Self {
+        ContexterError::InvalidPattern {
+            pattern: pattern.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// The pattern at fault, if the error is about a pattern.
+    pub fn pattern(&self) -> Option<&str> {
+        match self {
+            ContexterError::InvalidPattern { pattern, .. } => Some(pattern),
+            _ => None,
+        }
+    }
+}
+
+impl From<ignore::Error> for ContexterError {
+    fn from(error: ignore::Error) -> Self {
+        match error {
+            ignore::Error::Glob { glob, err } => ContexterError::InvalidPattern {
+                pattern: glob.unwrap_or_default(),
+                message: err,
+            },
+            ignore::Error::WithLineNumber { err, .. } | ignore::Error::WithPath { err, .. } => {
+                ContexterError::from(*err)
+            }
+            other => ContexterError::InvalidPattern {
+                pattern: String::new(),
+                message: other.to_string(),
+            },
+        }
+    }
+}
+
+impl From<git2::Error> for ContexterError {
+    fn from(error: git2::Error) -> Self {
+        ContexterError::Git(error.message().to_string())
+    }
+}
+
+impl From<ContexterError> for io::Error {
+    fn from(error: ContexterError) -> Self {
+        match error {
+            ContexterError::Io(error) => error,
+            ContexterError::InvalidPattern { .. }
+            | ContexterError::PathOutsideProject { .. }
+            | ContexterError::Git(_) => io::Error::new(io::ErrorKind::InvalidInput, error),
+        }
+    }
+}
+
+/// Options controlling which files are gathered from a project.
+///
+/// Filters are applied in this order, and a file must pass all of them: default excludes,
+/// excludes (which therefore always win over includes), include globs, extensions and the size
+/// limit.
+#[derive(Debug, Clone, Default)]
+pub struct GatherOptions {
+    /// File extensions to include. If empty, all files are considered.
+    pub extensions: Vec<String>,
+    /// Regex patterns for files or directories to exclude, matched against paths relative to the
+    /// project root.
+    pub excludes: Vec<String>,
+    /// Gitignore-style globs, relative to the project root, selecting the files to include.
+    /// The last glob matching a file decides, so `!` globs carve exceptions out of earlier ones.
+    /// If empty, all files are considered.
+    pub includes: Vec<String>,
+    /// Files larger than this many bytes are skipped.
+    pub max_file_size: Option<u64>,
+    /// Gitignore-style globs for files and directories that are never worth gathering, matched
+    /// relative to the project root. If `None`, `DEFAULT_EXCLUDES` is used.
+    pub default_excludes: Option<Vec<String>>,
+    /// Gitignore-style globs for files and directories that are never gathered, matched
+    /// relative to the project root, on top of `BLOCKED_FILES`. Unlike excludes, blocked files
+    /// are left out even when they are asked for by name.
+    pub blocked_files: Vec<String>,
+    /// Only gather the files that changed in the git repository the root is in, as described.
+    pub git: Option<GitChanges>,
+    /// Only skip files with a binary extension, leaving binary content to be found when the
+    /// files are read, so that they are not opened twice. Set this when the gathered files are
+    /// about to be assembled.
+    pub defer_content_check: bool,
+}
+
+/// The built-in default excludes: version control metadata, dependency and build output
+/// directories, editor files, lock files, logs and minified code.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    ".git/",
+    ".svn/",
+    ".hg/",
+    ".DS_Store",
+    "node_modules/",
+    "target/",
+    "build/",
+    "dist/",
+    ".vscode/",
+    ".idea/",
+    ".vs/",
+    "package-lock.json",
+    "*.lock",
+    "*.log",
+    "*.tmp",
+    "*.temp",
+    "*.swp",
+    "*.min.js",
+];
 
 /// Gathers relevant files from a directory based on specified extensions and exclusion patterns.
 ///
@@ -17,84 +155,603 @@ use std::path::{Path, PathBuf};
 ///
 /// # Returns
 ///
-/// A Result containing a Vec of PathBuf for relevant files, or an IO error.
+/// A Result containing a Vec of PathBuf for relevant files, or an error if a pattern is invalid
+/// or a file cannot be read.
 pub fn gather_relevant_files(
     directory: &str,
     extensions: Vec<&str>,
     excludes: Vec<String>,
-) -> io::Result<Vec<PathBuf>> {
-    let project_dir = PathBuf::from(directory);
-    let mut relevant_files = Vec::new();
-
-    // Combine user-provided exclusions with built-in exclusions
-    let mut all_excludes = excludes;
-    all_excludes.extend(vec![
-        String::from(r"\.git"),
-        String::from(r"\.svn"),
-        String::from(r"\.hg"),
-        String::from(r"\.DS_Store"),
-        String::from(r"node_modules"),
-        String::from(r"target"),
-        String::from(r"build"),
-        String::from(r"dist"),
-        String::from(r"\.vscode"),
-        String::from(r"\.idea"),
-        String::from(r"\.vs"),
-        String::from(r"package-lock\.json"),
-        String::from(r"\.lock"),
-        String::from(r"\.log"),
-        String::from(r"\.tmp"),
-        String::from(r"\.temp"),
-        String::from(r"\.swp"),
-        String::from(r"\.min.js"),
-    ]);
-
-    // Create a file system walker that respects .gitignore
-    let walker = WalkBuilder::new(&project_dir)
-        .add_custom_ignore_filename(".gitignore")
-        .build();
+) -> Result<Vec<PathBuf>, ContexterError> {
+    let options = GatherOptions {
+        extensions: extensions.into_iter().map(String::from).collect(),
+        excludes,
+        ..Default::default()
+    };
+    gather_files(Path::new(directory), &[], &options)
+}
+
+/// Gathers relevant files from a project according to `options`.
+///
+/// # Arguments
+///
+/// * `root` - The project root. Include globs are matched relative to it.
+/// * `paths` - Files, directories or gitignore-style globs, relative to `root`, to restrict the
+///   search to. A file is gathered if it is under one of the files or directories or matches one
+///   of the globs. If empty, the whole project is searched.
+/// * `options` - The filters to apply.
+///
+/// # Returns
+///
+/// A Result containing a sorted, deduplicated Vec of PathBuf for relevant files, or an error if a
+/// pattern or a requested path is invalid. Files that cannot be read are left out; use
+/// `gather_files_with_report` to find out which.
+pub fn gather_files(
+    root: &Path,
+    paths: &[String],
+    options: &GatherOptions,
+) -> Result<Vec<PathBuf>, ContexterError> {
+    Ok(gather_files_with_report(root, paths, options)?.files)
+}
+
+/// Files gathered from a project, along with the ones passed over and why.
+#[derive(Debug, Clone, Default)]
+pub struct GatheredFiles {
+    /// The relevant files, sorted and deduplicated.
+    pub files: Vec<PathBuf>,
+    /// Files that were found but left out: excluded, binary, too large or unreadable. Files
+    /// merely not selected by the requested paths, include globs or extensions, and files
+    /// ignored by `.gitignore`, are not listed.
+    pub skipped: Vec<SkippedFile>,
+    /// Files changed in git that the filters select but that are not in the working tree, such
+    /// as deleted ones. There is nothing to read of them, but they have diffs.
+    pub removed: Vec<PathBuf>,
+}
 
+/// Gathers relevant files like `gather_files`, also reporting the files it skipped.
+pub fn gather_files_with_report(
+    root: &Path,
+    paths: &[String],
+    options: &GatherOptions,
+) -> Result<GatheredFiles, ContexterError> {
+    gather(root, paths, options, None)
+}
+
+/// Gathers the relevant files at `path`, which is a file or directory under `root`, descending
+/// into it only if `recursive` is set.
+///
+/// Unlike requesting `path` from `gather_files`, the project is walked from `root` down to it, so
+/// `path` is left out if it is in an ignored or excluded directory, just as it would be when the
+/// whole project is gathered.
+pub(crate) fn gather_within(
+    root: &Path,
+    path: &Path,
+    recursive: bool,
+    options: &GatherOptions,
+) -> Result<GatheredFiles, ContexterError> {
+    let scope = WalkScope {
+        path: path.to_path_buf(),
+        recursive,
+    };
+    gather(root, &[], options, Some(scope))
+}
+
+/// The part of a project a walk is restricted to.
+#[derive(Debug, Clone)]
+struct WalkScope {
+    path: PathBuf,
+    recursive: bool,
+}
+
+impl WalkScope {
+    /// Whether the walk needs to visit `entry`: on the way down to the scope's path, the path
+    /// itself, or anything below it if the scope is recursive.
+    fn admits(&self, entry: &Path) -> bool {
+        self.path.starts_with(entry) || (self.recursive && entry.starts_with(&self.path))
+    }
+}
+
+fn gather(
+    root: &Path,
+    paths: &[String],
+    options: &GatherOptions,
+    scope: Option<WalkScope>,
+) -> Result<GatheredFiles, ContexterError> {
     // Compile exclusion patterns
-    let exclude_patterns: Vec<Regex> = all_excludes
+    let exclude_patterns: Vec<Regex> = options
+        .excludes
         .iter()
-        .map(|pattern| Regex::new(pattern).expect("Invalid regex pattern"))
+        .map(|pattern| Regex::new(pattern).map_err(|e| ContexterError::invalid_pattern(pattern, e)))
+        .collect::<Result<_, _>>()?;
+    let default_excludes = compile_default_excludes(options.default_excludes.as_deref())?;
+    let blocked = Arc::new(BlockedFiles::new(&options.blocked_files)?);
+    let include_filter = IncludeFilter::new(&options.includes)?;
+
+    // Changed files, relative to the root, and the directories leading to them
+    let changed = match &options.git {
+        Some(changes) => Some(git::changed_files(root, changes)?),
+        None => None,
+    };
+    let changed_dirs: Option<Arc<HashSet<PathBuf>>> = changed.as_ref().map(|changed| {
+        Arc::new(
+            changed
+                .iter()
+                .flat_map(|path| path.ancestors().skip(1))
+                .map(Path::to_path_buf)
+                .collect(),
+        )
+    });
+
+    let (globs, paths): (Vec<String>, Vec<String>) =
+        paths.iter().cloned().partition(|p| is_glob(p));
+    let path_globs = IncludeFilter::new(&globs)?;
+
+    // Globs are matched while walking the whole project; plain paths are walked directly
+    let mut targets: Vec<(PathBuf, bool)> = paths
+        .iter()
+        .map(|p| Ok((resolve_within(root, p)?, false)))
+        .collect::<Result<_, ContexterError>>()?;
+    if !globs.is_empty() || paths.is_empty() {
+        targets.push((root.to_path_buf(), !globs.is_empty()));
+    }
+
+    // Changed files missing from the working tree cannot be walked to, so they go through the
+    // same filters here
+    let mut removed: Vec<PathBuf> = changed
+        .iter()
+        .flatten()
+        .map(|path| root.join(path))
+        .filter(|path| {
+            !path.exists()
+                && scope.as_ref().is_none_or(|scope| scope.admits(path))
+                && targets.iter().any(|(target, match_globs)| {
+                    path.starts_with(target) && (!match_globs || path_globs.is_included(root, path))
+                })
+                && include_filter.is_included(root, path)
+                && (options.extensions.is_empty()
+                    || options
+                        .extensions
+                        .iter()
+                        .any(|ext| path.extension().and_then(|e| e.to_str()) == Some(ext)))
+                && blocked.matched(root, path, false).is_none()
+                && default_exclude_match(&default_excludes, root, path, false).is_none()
+                && exclude_match(root, path, &exclude_patterns).is_none()
+        })
         .collect();
+    removed.sort();
 
-    // Iterate through all files in the directory
-    for result in walker {
-        match result {
-            Ok(entry) => {
-                if entry.file_type().map_or(false, |ft| ft.is_file()) {
-                    let path = entry.path();
-                    if !is_excluded(path, &exclude_patterns)
-                        && !is_likely_binary(path)?
-                        && (extensions.is_empty()
-                            || extensions
-                                .iter()
-                                .any(|ext| path.extension().and_then(|e| e.to_str()) == Some(ext)))
-                    {
-                        relevant_files.push(entry.into_path());
+    // The walker threads report what they find here; entries pruned by its filter included
+    let found = Mutex::new(Vec::new());
+    let skipped = Arc::new(Mutex::new(Vec::new()));
+    for (target, match_globs) in targets {
+        // Create a file system walker that respects .gitignore and skips excluded directories
+        let walk_root = root.to_path_buf();
+        let walk_excludes = default_excludes.clone();
+        let walk_blocked = Arc::clone(&blocked);
+        let walk_skipped = Arc::clone(&skipped);
+        let walk_scope = scope.clone();
+        let walk_changed_dirs = changed_dirs.clone();
+        let walker = WalkBuilder::new(&target)
+            .add_custom_ignore_filename(".gitignore")
+            .filter_entry(move |entry| {
+                if !walk_scope
+                    .as_ref()
+                    .is_none_or(|scope| scope.admits(entry.path()))
+                {
+                    return false;
+                }
+                let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+                // Directories without changes are of no interest
+                if let (Some(changed_dirs), true) = (&walk_changed_dirs, is_dir) {
+                    let relative_path = entry
+                        .path()
+                        .strip_prefix(&walk_root)
+                        .unwrap_or(entry.path());
+                    if !changed_dirs.contains(relative_path) {
+                        return false;
                     }
                 }
+                if let Some(glob) = walk_blocked.matched(&walk_root, entry.path(), is_dir) {
+                    walk_skipped
+                        .lock()
+                        .unwrap()
+                        .push(SkippedFile::blocked(entry.path(), glob));
+                    return false;
+                }
+                match default_exclude_match(&walk_excludes, &walk_root, entry.path(), is_dir) {
+                    Some(glob) => {
+                        walk_skipped.lock().unwrap().push(SkippedFile::excluded(
+                            entry.path(),
+                            format!("matches default exclude '{}'", glob),
+                        ));
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .build_parallel();
+
+        // Walk the directory on all cores, checking files as they are found
+        walker.run(|| {
+            let (found, skipped) = (&found, &skipped);
+            let (default_excludes, exclude_patterns) = (&default_excludes, &exclude_patterns);
+            let blocked = &blocked;
+            let (include_filter, path_globs) = (&include_filter, &path_globs);
+            let changed = &changed;
+            Box::new(move |result| {
+                let entry = match result {
+                    Ok(entry) => entry,
+                    Err(err) => {
+                        skipped
+                            .lock()
+                            .unwrap()
+                            .push(SkippedFile::from_walk_error(err));
+                        return WalkState::Continue;
+                    }
+                };
+                if !entry.file_type().is_some_and(|ft| ft.is_file()) {
+                    return WalkState::Continue;
+                }
+                let path = entry.path();
+                if changed.as_ref().is_some_and(|changed| {
+                    !changed.contains(path.strip_prefix(root).unwrap_or(path))
+                }) || !include_filter.is_included(root, path)
+                    || (match_globs && !path_globs.is_included(root, path))
+                    || !(options.extensions.is_empty()
+                        || options
+                            .extensions
+                            .iter()
+                            .any(|ext| path.extension().and_then(|e| e.to_str()) == Some(ext)))
+                {
+                    return WalkState::Continue;
+                }
+                if let Some(glob) = blocked.matched(root, path, false) {
+                    skipped
+                        .lock()
+                        .unwrap()
+                        .push(SkippedFile::blocked(path, glob));
+                    return WalkState::Continue;
+                }
+                match check_file(root, path, default_excludes, exclude_patterns, options) {
+                    Some(file) => skipped.lock().unwrap().push(file),
+                    None => found.lock().unwrap().push(entry.into_path()),
+                }
+                WalkState::Continue
+            })
+        });
+    }
+
+    // Threads finish in no particular order, so sort to keep the result deterministic
+    let mut gathered = GatheredFiles {
+        files: found.into_inner().unwrap(),
+        skipped: std::mem::take(&mut *skipped.lock().unwrap()),
+        removed,
+    };
+    gathered.files.sort();
+    gathered.files.dedup();
+    gathered.skipped.sort_by(|a, b| a.path.cmp(&b.path));
+    gathered.skipped.dedup();
+    Ok(gathered)
+}
+
+/// Finds the files and directories under `root` left out by `.gitignore` and `.ignore` files,
+/// stopping at ignored directories rather than listing everything in them. Entries matching a
+/// default exclude are not listed, as gathering reports those itself.
+pub(crate) fn gather_ignored(
+    root: &Path,
+    options: &GatherOptions,
+) -> Result<Vec<PathBuf>, ContexterError> {
+    let default_excludes = Arc::new(compile_default_excludes(
+        options.default_excludes.as_deref(),
+    )?);
+    let is_excluded = {
+        let root = root.to_path_buf();
+        move |entry: &ignore::DirEntry| {
+            let is_dir = entry.file_type().is_some_and(|ft| ft.is_dir());
+            default_exclude_match(&default_excludes, &root, entry.path(), is_dir).is_some()
+        }
+    };
+
+    // Whatever a walk that ignores nothing finds, but a walk respecting the ignore files does
+    // not, is ignored. Neither walk looks inside default excludes, but the first one notes them.
+    let visible = Arc::new(Mutex::new(HashSet::new()));
+    let walk_visible = Arc::clone(&visible);
+    let walk_excluded = is_excluded.clone();
+    WalkBuilder::new(root)
+        .add_custom_ignore_filename(".gitignore")
+        .filter_entry(move |entry| {
+            walk_visible
+                .lock()
+                .unwrap()
+                .insert(entry.path().to_path_buf());
+            !walk_excluded(entry)
+        })
+        .build()
+        .for_each(drop);
+    let visible = std::mem::take(&mut *visible.lock().unwrap());
+
+    let ignored = Arc::new(Mutex::new(Vec::new()));
+    let walk_ignored = Arc::clone(&ignored);
+    let entries = WalkBuilder::new(root)
+        .ignore(false)
+        .git_ignore(false)
+        .git_global(false)
+        .git_exclude(false)
+        .parents(false)
+        .filter_entry(move |entry| {
+            if !visible.contains(entry.path()) {
+                walk_ignored
+                    .lock()
+                    .unwrap()
+                    .push(entry.path().to_path_buf());
+                return false;
+            }
+            !is_excluded(entry)
+        })
+        .build();
+    // Walk errors are reported when the files are gathered
+    entries.for_each(drop);
+
+    let mut ignored = std::mem::take(&mut *ignored.lock().unwrap());
+    ignored.sort();
+    Ok(ignored)
+}
+
+/// Checks a selected file against the excludes, the size limit and its content, returning why
+/// it has to be skipped, if it does.
+fn check_file(
+    root: &Path,
+    path: &Path,
+    default_excludes: &Gitignore,
+    exclude_patterns: &[Regex],
+    options: &GatherOptions,
+) -> Option<SkippedFile> {
+    if let Some(glob) = default_exclude_match(default_excludes, root, path, false) {
+        return Some(SkippedFile::excluded(
+            path,
+            format!("matches default exclude '{}'", glob),
+        ));
+    }
+    if let Some(pattern) = exclude_match(root, path, exclude_patterns) {
+        return Some(SkippedFile::excluded(
+            path,
+            format!("matches exclude pattern '{}'", pattern),
+        ));
+    }
+    if let Some(max) = options.max_file_size {
+        match metadata(path) {
+            Ok(metadata) if metadata.len() > max => {
+                return Some(SkippedFile::new(
+                    path,
+                    SkipReason::TooLarge,
+                    Some(format!("{} bytes, limit is {}", metadata.len(), max)),
+                ))
+            }
+            Ok(_) => {}
+            Err(e) => return Some(SkippedFile::unreadable(path, &e)),
+        }
+    }
+    if options.defer_content_check {
+        return has_binary_extension(path)
+            .then(|| SkippedFile::new(path, SkipReason::Binary, None));
+    }
+    match is_likely_binary(path) {
+        Ok(true) => Some(SkippedFile::new(path, SkipReason::Binary, None)),
+        Ok(false) => None,
+        Err(e) => Some(SkippedFile::unreadable(path, &e)),
+    }
+}
+
+/// Include globs compiled with gitignore semantics: a file is included when it, or one of its
+/// parent directories, matches a glob, unless a later `!` glob matches it. If every glob is a
+/// `!` glob, everything they do not match is included.
+pub struct IncludeFilter {
+    globs: Gitignore,
+    has_positive_globs: bool,
+}
+
+impl IncludeFilter {
+    pub fn new(includes: &[String]) -> Result<Self, ContexterError> {
+        let mut builder = GitignoreBuilder::new("");
+        for include in includes {
+            builder.add_line(None, include)?;
+        }
+        Ok(IncludeFilter {
+            has_positive_globs: includes.iter().any(|i| !i.starts_with('!')),
+            globs: builder.build()?,
+        })
+    }
+
+    /// Checks a file, with include globs matched relative to `root`.
+    pub fn is_included(&self, root: &Path, path: &Path) -> bool {
+        if self.globs.is_empty() {
+            return true;
+        }
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        match self.globs.matched_path_or_any_parents(relative_path, false) {
+            Match::Ignore(_) => true,
+            Match::Whitelist(_) => false,
+            Match::None => !self.has_positive_globs,
+        }
+    }
+}
+
+/// Which files are reduced to their skeleton: those selected by the skeleton globs, unless the
+/// focus globs select them too.
+struct SkeletonFilter {
+    skeleton: IncludeFilter,
+    focus: Option<IncludeFilter>,
+}
+
+impl SkeletonFilter {
+    /// Compiles the globs, returning `None` if there are no skeleton globs, as then every file
+    /// is kept whole.
+    fn new(skeleton: &[String], focus: &[String]) -> Result<Option<Self>, ContexterError> {
+        if skeleton.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(SkeletonFilter {
+            skeleton: IncludeFilter::new(skeleton)?,
+            focus: match focus {
+                [] => None,
+                focus => Some(IncludeFilter::new(focus)?),
+            },
+        }))
+    }
+
+    fn applies(&self, root: &Path, path: &Path) -> bool {
+        self.skeleton.is_included(root, path)
+            && !self
+                .focus
+                .as_ref()
+                .is_some_and(|focus| focus.is_included(root, path))
+    }
+}
+
+/// Checks the files, directories and globs that select files from the project at `root`, as
+/// gathering them would, without walking the project.
+pub fn check_paths(root: &Path, paths: &[String]) -> Result<(), ContexterError> {
+    let (globs, paths): (Vec<String>, Vec<String>) =
+        paths.iter().cloned().partition(|p| is_glob(p));
+    IncludeFilter::new(&globs)?;
+    for path in &paths {
+        resolve_within(root, path)?;
+    }
+    Ok(())
+}
+
+/// Joins a requested path to the project root, refusing paths that lead outside of it: absolute
+/// paths, `..` components that climb above the root and symlinks pointing elsewhere.
+fn resolve_within(root: &Path, path: &str) -> Result<PathBuf, ContexterError> {
+    let outside = || ContexterError::PathOutsideProject {
+        path: path.to_string(),
+    };
+    if Path::new(path).has_root()
+        || matches!(
+            Path::new(path).components().next(),
+            Some(Component::Prefix(_))
+        )
+    {
+        return Err(outside());
+    }
+
+    let joined = root.join(path);
+    match (joined.canonicalize(), root.canonicalize()) {
+        (Ok(resolved), Ok(canonical_root)) => {
+            if !resolved.starts_with(&canonical_root) {
+                return Err(outside());
+            }
+        }
+        // Missing paths cannot be resolved, so check that they do not climb out lexically
+        _ => {
+            let mut depth = 0usize;
+            for component in Path::new(path).components() {
+                match component {
+                    Component::ParentDir if depth == 0 => return Err(outside()),
+                    Component::ParentDir => depth -= 1,
+                    Component::Normal(_) => depth += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(joined)
+}
+
+/// Checks if a requested path is a glob rather than a plain file or directory.
+fn is_glob(path: &str) -> bool {
+    path.starts_with('!') || path.contains(['*', '?', '[', '{'])
+}
+
+/// Finds the first exclusion pattern matching a file path, relative to the project root.
+fn exclude_match<'a>(root: &Path, path: &Path, exclude_patterns: &'a [Regex]) -> Option<&'a str> {
+    let path_str = path.strip_prefix(root).unwrap_or(path).to_string_lossy();
+    exclude_patterns
+        .iter()
+        .find(|re| re.is_match(&path_str))
+        .map(Regex::as_str)
+}
+
+/// Compiles the default exclude globs, falling back to the built-in ones.
+fn compile_default_excludes(globs: Option<&[String]>) -> Result<Gitignore, ContexterError> {
+    let mut builder = GitignoreBuilder::new("");
+    match globs {
+        Some(globs) => {
+            for glob in globs {
+                builder.add_line(None, glob)?;
+            }
+        }
+        None => {
+            for glob in DEFAULT_EXCLUDES {
+                builder.add_line(None, glob)?;
             }
-            Err(err) => {
-                eprintln!("Error reading file: {}", err);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// Finds the default exclude matching a path, or one of its parents below the project root.
+fn default_exclude_match(
+    default_excludes: &Gitignore,
+    root: &Path,
+    path: &Path,
+    is_dir: bool,
+) -> Option<String> {
+    match path.strip_prefix(root) {
+        Ok(relative_path) if !relative_path.as_os_str().is_empty() => {
+            match default_excludes.matched_path_or_any_parents(relative_path, is_dir) {
+                Match::Ignore(glob) => Some(glob.original().to_string()),
+                _ => None,
             }
         }
+        _ => None,
     }
+}
 
-    relevant_files.sort();
-    Ok(relevant_files)
+/// The files that are never gathered: the built-in `BLOCKED_FILES` and the configured ones.
+/// They are kept apart so that a configured `!` glob cannot let a built-in one through.
+struct BlockedFiles {
+    built_in: Gitignore,
+    configured: Gitignore,
 }
 
-/// Checks if a file path matches any of the exclusion patterns.
-fn is_excluded(path: &Path, exclude_patterns: &[Regex]) -> bool {
-    let path_str = path.to_string_lossy();
-    exclude_patterns.iter().any(|re| re.is_match(&path_str))
+impl BlockedFiles {
+    fn new(configured: &[String]) -> Result<Self, ContexterError> {
+        let mut built_in = GitignoreBuilder::new("");
+        for glob in BLOCKED_FILES {
+            built_in.add_line(None, glob)?;
+        }
+        let mut builder = GitignoreBuilder::new("");
+        for glob in configured {
+            builder.add_line(None, glob)?;
+        }
+        Ok(BlockedFiles {
+            built_in: built_in.build()?,
+            configured: builder.build()?,
+        })
+    }
+
+    /// Finds the glob blocking a path, or one of its parents below the project root.
+    fn matched(&self, root: &Path, path: &Path, is_dir: bool) -> Option<String> {
+        default_exclude_match(&self.built_in, root, path, is_dir)
+            .or_else(|| default_exclude_match(&self.configured, root, path, is_dir))
+    }
 }
 
 /// Determines if a file is likely to be binary based on its extension or content.
 fn is_likely_binary(path: &Path) -> io::Result<bool> {
+    if has_binary_extension(path) {
+        return Ok(true);
+    }
+
+    // If not a known binary extension, check the start of the file's content
+    let mut file = std::fs::File::open(path)?;
+    let mut buffer = [0; BINARY_SNIFF_LENGTH];
+    let bytes_read = std::io::Read::read(&mut file, &mut buffer)?;
+    Ok(is_binary_content(&buffer[..bytes_read]))
+}
+
+/// Checks if a file has a common binary file extension.
+fn has_binary_extension(path: &Path) -> bool {
     // List of common binary file extensions
     const BINARY_EXTENSIONS: &[&str] = &[
         "exe", "dll", "so", "dylib", "bin", "obj", "o", "a", "lib", "pyc", "pyd", "pyo", "jpg",
@@ -102,26 +759,471 @@ fn is_likely_binary(path: &Path) -> io::Result<bool> {
         "zip", "tar", "gz", "rar", "7z", "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx",
     ];
 
-    // Check if the file has a known binary extension
-    if let Some(ext) = path.extension() {
-        if BINARY_EXTENSIONS.contains(&ext.to_str().unwrap_or("")) {
-            return Ok(true);
+    path.extension()
+        .is_some_and(|ext| BINARY_EXTENSIONS.contains(&ext.to_str().unwrap_or("")))
+}
+
+/// How much of a file's content is looked at to tell if it is binary.
+const BINARY_SNIFF_LENGTH: usize = 1024;
+
+/// Checks the start of a file's content for null bytes, which UTF-16 text is full of but other
+/// text never has.
+fn is_binary_content(bytes: &[u8]) -> bool {
+    let start = &bytes[..bytes.len().min(BINARY_SNIFF_LENGTH)];
+    start.contains(&0) && !encoding::looks_like_utf16(start)
+}
+
+/// Appended to a file that was cut short by `BudgetStrategy::Truncate`.
+const TRUNCATION_MARKER: &str = "\n[... truncated to fit the token budget ...]\n";
+
+/// How files are chosen when the gathered context would exceed its token budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetStrategy {
+    /// Include files in output order and drop everything from the first file that does not fit.
+    #[default]
+    Stop,
+    /// Like `Stop`, but the first file that does not fit is cut down to the remaining budget.
+    Truncate,
+    /// Fit as many files as possible, smallest first, while keeping the output order.
+    Prioritize,
+}
+
+impl FromStr for BudgetStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "stop" => Ok(BudgetStrategy::Stop),
+            "truncate" => Ok(BudgetStrategy::Truncate),
+            "prioritize" | "prioritise" => Ok(BudgetStrategy::Prioritize),
+            _ => Err(format!(
+                "Unknown budget strategy '{}' (expected stop, truncate or prioritize)",
+                s
+            )),
         }
     }
+}
 
-    // If not a known binary extension, check the file content for null bytes
-    let mut file = std::fs::File::open(path)?;
-    let mut buffer = [0; 1024];
-    let bytes_read = std::io::Read::read(&mut file, &mut buffer)?;
+impl fmt::Display for BudgetStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BudgetStrategy::Stop => "stop",
+            BudgetStrategy::Truncate => "truncate",
+            BudgetStrategy::Prioritize => "prioritize",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What happens to a file over a size limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizePolicy {
+    /// Leave the file out.
+    #[default]
+    Skip,
+    /// Keep as much of the start and the end of the file as the limit allows.
+    Truncate,
+}
+
+impl FromStr for OversizePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(OversizePolicy::Skip),
+            "truncate" => Ok(OversizePolicy::Truncate),
+            _ => Err(format!(
+                "Unknown oversize policy '{}' (expected skip or truncate)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OversizePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OversizePolicy::Skip => "skip",
+            OversizePolicy::Truncate => "truncate",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Limits on how much of the files goes into the output, in bytes on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SizeLimits {
+    /// Files larger than this are over the limit.
+    pub max_file_size: Option<u64>,
+    /// Once the files placed add up to this, the files that follow are over the limit.
+    pub max_total_size: Option<u64>,
+    /// What happens to files over a limit.
+    pub oversized: OversizePolicy,
+}
+
+impl SizeLimits {
+    /// The size above which files can be skipped as soon as they are gathered, without reading
+    /// them: the file size limit, unless oversized files are truncated instead.
+    pub fn skip_size(&self) -> Option<u64> {
+        match self.oversized {
+            OversizePolicy::Skip => self.max_file_size,
+            OversizePolicy::Truncate => None,
+        }
+    }
+}
+
+/// Files are not truncated to fit what is left of the total size limit if less than this is
+/// left, as so little of them would say nothing.
+const MIN_EXCERPT_SIZE: u64 = 1024;
+
+/// Options controlling how gathered files are assembled into a single context.
+#[derive(Debug, Clone, Default)]
+pub struct ContextOptions {
+    /// Maximum number of tokens the output may contain. `None` means unlimited.
+    pub max_tokens: Option<usize>,
+    /// Tokenizer used to measure the output.
+    pub tokenizer: TokenizerKind,
+    /// What to do with files that do not fit within `max_tokens`.
+    pub budget_strategy: BudgetStrategy,
+    /// How files and sections are rendered.
+    pub format: OutputFormat,
+    /// What file headers show besides the path.
+    pub header: HeaderOptions,
+    /// Rules assigning files to output sections.
+    pub categories: CategoryRules,
+    /// Directory that category patterns are matched relative to. Defaults to the deepest
+    /// directory containing every file.
+    pub root: Option<PathBuf>,
+    /// Cache of file contents to read through, shared with other assemblies.
+    pub cache: Option<Arc<ContentCache>>,
+    /// Open the output with a "Project Structure" tree of the files, with their sizes.
+    pub tree: bool,
+    /// Send the tree alone, without reading any file. Implies `tree`.
+    pub tree_only: bool,
+    /// Files left out while gathering, drawn in the tree too along with why they were left out.
+    pub tree_skipped: Vec<SkippedFile>,
+    /// Follow the files with a "Changes" section holding the diff of each, showing how it
+    /// changed as described.
+    pub diffs: Option<GitChanges>,
+    /// Lines of unchanged context around each change in the diffs. Defaults to git's three.
+    pub diff_context: Option<u32>,
+    /// Send the diffs without the content of the files they are for.
+    pub diffs_only: bool,
+    /// Changed files that are not in the working tree, as gathered, whose diffs are sent too.
+    pub removed: Vec<PathBuf>,
+    /// Read the files' content from this git revision rather than the working tree. Files that
+    /// are not in it only get their diffs, and changed files that are only in it are read too.
+    pub revision: Option<String>,
+    /// Gitignore-style globs, relative to the root, selecting files to reduce to their skeleton:
+    /// their imports and item signatures, with function bodies elided. Only Rust, Python and
+    /// TypeScript files are reduced; others are kept whole.
+    pub skeleton: Vec<String>,
+    /// Globs selecting files kept whole even though `skeleton` selects them.
+    pub focus: Vec<String>,
+    /// Transforms applied to each file's content, such as stripping comments.
+    pub transforms: Transforms,
+    /// Replace secrets in the files and diffs with markers naming their kind. `None` leaves
+    /// the content as it is.
+    pub redaction: Option<Redaction>,
+    /// Limits on the size of each file and of all of them together.
+    pub size_limits: SizeLimits,
+}
+
+/// A file left out of the output because it did not fit within the token budget.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DroppedFile {
+    pub path: String,
+    pub tokens: usize,
+    /// Set when what was dropped is the file's diff rather than its content.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub diff: bool,
+}
+
+/// Why a file was left out of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    /// Matched a default exclude or an exclude pattern.
+    Excluded,
+    /// Matched a blocked file, which is never served as it is likely to hold secrets.
+    Blocked,
+    /// Has a binary file extension or binary content.
+    Binary,
+    /// Larger than the file size limit.
+    TooLarge,
+    /// Would have taken the files placed past the total size limit.
+    TotalSizeExceeded,
+    /// Has the same content as a file already included.
+    Duplicate,
+    /// Could not be read for lack of permission.
+    PermissionDenied,
+    /// Could not be read for another reason.
+    Unreadable,
+    /// The content is not text in any supported encoding.
+    InvalidEncoding,
+}
+
+impl SkipReason {
+    /// Checks if files are skipped for this reason by design rather than because something went
+    /// wrong.
+    pub fn is_expected(&self) -> bool {
+        matches!(
+            self,
+            SkipReason::Excluded | SkipReason::Blocked | SkipReason::Binary | SkipReason::Duplicate
+        )
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            SkipReason::Excluded => "excluded",
+            SkipReason::Blocked => "blocked",
+            SkipReason::Binary => "binary",
+            SkipReason::TooLarge => "too large",
+            SkipReason::TotalSizeExceeded => "total size exceeded",
+            SkipReason::Duplicate => "duplicate",
+            SkipReason::PermissionDenied => "permission denied",
+            SkipReason::Unreadable => "unreadable",
+            SkipReason::InvalidEncoding => "invalid encoding",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// A file left out of the output for a reason other than the token budget.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SkippedFile {
+    pub path: String,
+    pub reason: SkipReason,
+    /// More about the reason, such as the pattern that excluded the file or the error met
+    /// reading it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl SkippedFile {
+    pub fn new(path: &Path, reason: SkipReason, detail: Option<String>) -> Self {
+        SkippedFile {
+            path: path.to_string_lossy().to_string(),
+            reason,
+            detail,
+        }
+    }
+
+    fn excluded(path: &Path, detail: String) -> Self {
+        SkippedFile::new(path, SkipReason::Excluded, Some(detail))
+    }
+
+    fn blocked(path: &Path, glob: String) -> Self {
+        SkippedFile::new(
+            path,
+            SkipReason::Blocked,
+            Some(format!("matches blocked file '{}'", glob)),
+        )
+    }
+
+    /// Records a file that could not be read, telling missing permissions apart.
+    fn unreadable(path: &Path, error: &io::Error) -> Self {
+        let reason = match error.kind() {
+            io::ErrorKind::PermissionDenied => SkipReason::PermissionDenied,
+            _ => SkipReason::Unreadable,
+        };
+        SkippedFile::new(path, reason, Some(error.to_string()))
+    }
+
+    /// Records an entry the directory walker could not visit.
+    fn from_walk_error(error: ignore::Error) -> Self {
+        let path = walk_error_path(&error)
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        match error.io_error() {
+            Some(io_error) => SkippedFile::unreadable(&path, io_error),
+            None => SkippedFile::new(&path, SkipReason::Unreadable, Some(error.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for SkippedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} ({}: {})", self.path, self.reason, detail),
+            None => write!(f, "{} ({})", self.path, self.reason),
+        }
+    }
+}
+
+/// Finds the path a directory walker error is about.
+fn walk_error_path(error: &ignore::Error) -> Option<&Path> {
+    match error {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+/// A file whose content was decoded from an encoding other than UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedFile {
+    pub path: String,
+    pub encoding: String,
+}
+
+/// A file with secrets replaced in the output, with how many of each kind.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RedactedFile {
+    pub path: String,
+    pub redactions: BTreeMap<String, usize>,
+    /// Set when the secrets were in the file's diff rather than its content.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub diff: bool,
+}
+
+/// A file over a size limit of which only the start and the end were included.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExcerptedFile {
+    pub path: String,
+    /// The size of the whole file, in bytes.
+    pub size: u64,
+    /// How many of its bytes were included.
+    pub kept: u64,
+}
+
+/// A file whose content was changed by the transforms, with what it costs before and after.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransformedFile {
+    pub path: String,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+}
+
+/// The assembled context along with what was left out of it.
+#[derive(Debug, Clone, Default)]
+pub struct ContextOutput {
+    /// The concatenated content.
+    pub content: String,
+    /// Files included in the content, in output order.
+    pub files: Vec<String>,
+    /// SHA-256 digests of the included files, by path.
+    pub digests: BTreeMap<String, ContentDigest>,
+    /// Number of tokens in `content`, as measured by the selected tokenizer on each of its
+    /// pieces.
+    pub tokens: usize,
+    /// Files dropped to stay within the token budget.
+    pub dropped: Vec<DroppedFile>,
+    /// Files included only partially to stay within the token budget.
+    pub truncated: Vec<String>,
+    /// Files included as their skeleton rather than in full.
+    pub skeletonized: Vec<String>,
+    /// Files over a size limit included as an excerpt of their start and end.
+    pub excerpted: Vec<ExcerptedFile>,
+    /// Included files changed by the transforms.
+    pub transformed: Vec<TransformedFile>,
+    /// Included files and diffs that had secrets redacted.
+    pub redacted: Vec<RedactedFile>,
+    /// Included files that were not UTF-8, with the encoding they were decoded from.
+    pub decoded: Vec<DecodedFile>,
+    /// Files left out for a reason other than the token budget.
+    pub skipped: Vec<SkippedFile>,
+}
+
+impl ContextOutput {
+    /// Adds the files skipped while gathering to the report, ahead of those skipped while
+    /// assembling.
+    pub fn with_gather_report(mut self, skipped: Vec<SkippedFile>) -> Self {
+        self.skipped.splice(0..0, skipped);
+        self
+    }
+}
+
+/// A file that has been read, waiting to be placed in the output.
+struct FileBlock {
+    section: usize,
+    path: PathBuf,
+    /// The path shown in the output, relative to the root.
+    shown_path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+    content: Arc<str>,
+    encoding: &'static str,
+    digest: ContentDigest,
+    /// Set when `content` is the file's skeleton rather than the whole file.
+    skeleton: bool,
+    /// How many bytes of the file `content` was read from, if only an excerpt was read.
+    excerpt: Option<u64>,
+    /// What the content cost before and after the transforms changed it.
+    transformed: Option<(usize, usize)>,
+    /// How many secrets of each kind were redacted from the content.
+    redactions: Option<BTreeMap<String, usize>>,
+    /// What the file costs in full, worked out when there is a token budget.
+    cost: Option<usize>,
+}
+
+/// The outcome of reading a gathered file.
+enum FileRead {
+    Block(FileBlock),
+    Skipped(SkippedFile),
+    /// A file with the same content as `original`, which comes before it in the output.
+    Duplicate {
+        block: FileBlock,
+        original: PathBuf,
+    },
+}
 
-    Ok(buffer[..bytes_read].contains(&0))
+impl FileBlock {
+    fn record<'a>(&'a self, content: &'a str, header: HeaderOptions) -> FileRecord<'a> {
+        FileRecord {
+            path: &self.shown_path,
+            size: (!header.omit_size).then_some(self.size),
+            modified: (!header.omit_modified).then_some(self.modified),
+            digest: self.digest,
+            content,
+        }
+    }
 }
 
-/// Calculates a hash for the given value.
-fn calculate_hash<T: Hash>(t: &T) -> u64 {
-    let mut s = DefaultHasher::new();
-    t.hash(&mut s);
-    s.finish()
+/// Whether, and how much of, a file makes it into the output.
+enum Selection {
+    Full,
+    Truncated(String),
+    Dropped(usize),
+}
+
+/// A piece of an assembled context, handed out as soon as it is ready.
+pub enum ContextChunk<'a> {
+    /// Text around the files: the start and end of the output and section headers.
+    Text(String),
+    /// The tree of the project's files, along with its rendering.
+    Structure { tree: String, rendered: String },
+    /// A file placed in the output, along with its rendering.
+    File {
+        path: &'a Path,
+        record: FileRecord<'a>,
+        section: &'a str,
+        rendered: String,
+        truncated: bool,
+        skeleton: bool,
+        /// Set when only the start and the end of the file are included.
+        excerpt: bool,
+    },
+    /// A note standing in for a file whose content is identical to one already placed.
+    Duplicate {
+        path: &'a Path,
+        original: &'a Path,
+        section: &'a str,
+        rendered: String,
+    },
+    /// The diff of a file, in the "Changes" section after the files.
+    Diff {
+        path: &'a Path,
+        patch: &'a str,
+        rendered: String,
+    },
 }
 
 /// Concatenates the contents of multiple files, categorizing them and removing duplicates.
@@ -133,75 +1235,1060 @@ fn calculate_hash<T: Hash>(t: &T) -> u64 {
 /// # Returns
 ///
 /// A Result containing a tuple of the concatenated content string and a vector of processed filenames.
-pub fn concatenate_files(mut files: Vec<PathBuf>) -> io::Result<(String, Vec<String>)> {
+pub fn concatenate_files(files: Vec<PathBuf>) -> Result<(String, Vec<String>), ContexterError> {
+    let output = concatenate_files_with_options(files, &ContextOptions::default())?;
+    Ok((output.content, output.files))
+}
+
+/// Concatenates the contents of multiple files like `concatenate_files`, rendering them in the
+/// requested format and keeping the result within the token budget given in `options`.
+///
+/// # Arguments
+///
+/// * `files` - A vector of PathBuf representing the files to concatenate.
+/// * `options` - The output format, token budget and tokenizer to apply.
+///
+/// # Returns
+///
+/// A Result containing the assembled context and a record of any files dropped or truncated.
+pub fn concatenate_files_with_options(
+    files: Vec<PathBuf>,
+    options: &ContextOptions,
+) -> Result<ContextOutput, ContexterError> {
     let mut content = String::new();
-    let mut filenames = Vec::new();
-    let mut seen_hashes = HashSet::new();
+    let mut output = stream_files_with_options(files, options, |chunk| {
+        match chunk {
+            ContextChunk::Text(text) => content.push_str(&text),
+            ContextChunk::Structure { rendered, .. }
+            | ContextChunk::File { rendered, .. }
+            | ContextChunk::Duplicate { rendered, .. }
+            | ContextChunk::Diff { rendered, .. } => content.push_str(&rendered),
+        }
+        Ok(())
+    })?;
+    output.content = content;
+    Ok(output)
+}
+
+/// Assembles the same context as `concatenate_files_with_options`, but hands it to `sink`
+/// piece by piece instead of building it in memory. Only one file is held at a time.
+///
+/// # Arguments
+///
+/// * `files` - A vector of PathBuf representing the files to concatenate.
+/// * `options` - The output format, token budget and tokenizer to apply.
+/// * `sink` - Receives the chunks of the output in order. An error stops the assembly.
+///
+/// # Returns
+///
+/// A Result containing a record of the files included, dropped or truncated. Its `content` is
+/// left empty.
+pub fn stream_files_with_options<F>(
+    mut files: Vec<PathBuf>,
+    options: &ContextOptions,
+    mut sink: F,
+) -> Result<ContextOutput, ContexterError>
+where
+    F: FnMut(ContextChunk<'_>) -> io::Result<()>,
+{
+    let categories = options.categories.compile()?;
+    let root = match &options.root {
+        Some(root) => root.clone(),
+        None => common_root(&files),
+    };
+    let formatter = options.format.formatter();
+    let tokenizer = options.tokenizer.build();
+    // Diffs go in a section of their own after the files
+    let mut sections = categories.section_names().to_vec();
+    let changes_section = sections.len();
+    sections.push(CHANGES_TITLE.to_string());
+
+    // Diffs are for the changed files whether they are still in the working tree or not
+    let diff_files: Vec<PathBuf> = files.iter().chain(&options.removed).cloned().collect();
+    let revision = match &options.revision {
+        Some(revision) => {
+            let revision = git::RevisionFiles::open(&root, revision, &diff_files)?;
+            files = diff_files
+                .iter()
+                .filter(|path| revision.contains(path))
+                .cloned()
+                .collect();
+            Some(revision)
+        }
+        None => None,
+    };
 
     // Sort files alphabetically by their file name
     files.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
 
-    // Initialize strings for different file categories
-    let mut config_files = String::new();
-    let mut source_files = String::new();
-    let mut doc_files = String::new();
-    let mut test_files = String::new();
+    // Categorize each file by its path within the project, then emit them section by section,
+    // keeping their sorted order within a section
+    let mut planned: Vec<(usize, PathBuf)> = files
+        .into_iter()
+        .map(|path| (categories.categorize(shown_path(&root, &path)), path))
+        .collect();
+    planned.sort_by_key(|(section, _)| *section);
+    // Only the content of the files counts against the size limits
+    let (oversized, excerpts) = match options.tree_only || options.diffs_only {
+        true => (Vec::new(), HashMap::new()),
+        false => apply_size_limits(&mut planned, &options.size_limits, revision.as_ref()),
+    };
 
-    // Process each file
-    for path in files {
-        let file_content = read_to_string(&path)?;
-        let file_hash = calculate_hash(&file_content);
+    let mut budget = options.max_tokens.map(|max_tokens| {
+        Budget::new(
+            max_tokens,
+            &sections,
+            formatter.as_ref(),
+            tokenizer.as_ref(),
+        )
+    });
+    // The structure comes first, so the files make do with what it leaves of the budget. A
+    // structure that does not fit on its own is cut down to the budget, or left out
+    let structure = match options.tree || options.tree_only {
+        true => {
+            let tree = draw_tree(&root, &planned, &options.tree_skipped);
+            let tree = match &mut budget {
+                Some(budget) => budget.take_structure(tree, formatter.as_ref(), tokenizer.as_ref()),
+                None => Some(tree),
+            };
+            tree.map(|tree| {
+                let rendered = formatter.structure(&tree, 0);
+                let cost = tokenizer.count_tokens(&rendered);
+                (tree, rendered, cost)
+            })
+        }
+        false => None,
+    };
+    if options.tree_only {
+        planned.clear();
+    }
+    let diffs = match &options.diffs {
+        Some(changes) if !options.tree_only && !diff_files.is_empty() => {
+            let context_lines = options.diff_context.unwrap_or(git::DEFAULT_CONTEXT_LINES);
+            git::file_diffs(&root, changes, &diff_files, context_lines)?
+        }
+        _ => Vec::new(),
+    };
+    if options.diffs_only {
+        planned.clear();
+    }
+    let skeletons = SkeletonFilter::new(&options.skeleton, &options.focus)?;
+    let transforms = match options.transforms.is_active() {
+        true => Some(options.transforms.compile().map_err(|e| {
+            let pattern = options.transforms.license_pattern.as_deref();
+            ContexterError::invalid_pattern(pattern.unwrap_or(DEFAULT_LICENSE_PATTERN), e)
+        })?),
+        false => None,
+    };
+    let redactor = match &options.redaction {
+        Some(redaction) => Some((redaction.compile()?, redaction.fingerprint())),
+        None => None,
+    };
+    let reader = FileReader {
+        root: &root,
+        cache: options.cache.as_deref(),
+        revision: revision.as_ref(),
+        excerpts: &excerpts,
+        skeletons: skeletons.as_ref(),
+        redactor: redactor
+            .as_ref()
+            .map(|(redactor, fingerprint)| (redactor, *fingerprint)),
+        transforms: transforms
+            .as_ref()
+            .map(|transforms| (transforms, tokenizer.as_ref())),
+        costing: budget
+            .is_some()
+            .then(|| (formatter.as_ref(), tokenizer.as_ref())),
+        format: options.format,
+        header: options.header,
+        tokenizer: options.tokenizer,
+    };
+    // Prioritizing has to know what every file costs before placing the first one, so the
+    // files it reads are kept to be placed rather than read again
+    let (prioritized, mut selections) = match &mut budget {
+        Some(budget) if options.budget_strategy == BudgetStrategy::Prioritize => {
+            let (reads, selections) = prioritize(std::mem::take(&mut planned), budget, &reader)?;
+            (Some(reads), Some(selections))
+        }
+        _ => (None, None),
+    };
 
+    let mut output = ContextOutput {
+        skipped: oversized,
+        ..Default::default()
+    };
+    let begin = formatter.begin();
+    output.tokens += tokenizer.count_tokens(&begin);
+    sink(ContextChunk::Text(begin))?;
+
+    // The structure and duplicate notes placed, which are numbered along with the files
+    let mut notes = 0;
+    if let Some((tree, rendered, cost)) = structure {
+        output.tokens += cost;
+        notes += 1;
+        sink(ContextChunk::Structure { tree, rendered })?;
+    }
+
+    let mut seen = HashMap::new();
+    let mut budget_exhausted = false;
+    let mut current_section = None;
+    // Files are read on all cores, but placed one at a time in output order
+    let mut place = |read: FileRead| -> Result<(), ContexterError> {
         // Only process the file if its content hasn't been seen before
-        if !seen_hashes.contains(&file_hash) {
-            seen_hashes.insert(file_hash);
-
-            // Prepare file metadata
-            let mut file_info = String::new();
-            let metadata = metadata(&path)?;
-            file_info.push_str(&format!(
-                "========================================\n\
-                File: {:?}\n\
-                Size: {} bytes\n\
-                Last Modified: {:?}\n\
-                ========================================\n",
-                path,
-                metadata.len(),
-                metadata.modified()?
-            ));
-
-            file_info.push_str(&file_content);
-            file_info.push('\n');
-
-            // Categorize the file based on its extension
-            let file_ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("").to_lowercase();
-            match file_ext.as_str() {
-                "toml" | "json" | "yaml" | "yml" => config_files.push_str(&file_info),
-                "rs" => source_files.push_str(&file_info),
-                "md" | "txt" => doc_files.push_str(&file_info),
-                _ if file_ext.contains("test") => test_files.push_str(&file_info),
-                _ => source_files.push_str(&file_info),
-            }
-
-            filenames.push(path.to_string_lossy().to_string());
-        }
-    }
-
-    // Append sections in the desired order
-    let sections = [
-        ("Configuration Files", &config_files),
-        ("Documentation", &doc_files),
-        ("Source Files", &source_files),
-        ("Tests", &test_files),
-    ];
+        let block = match deduplicate(read, &mut seen) {
+            FileRead::Block(block) => block,
+            FileRead::Skipped(skipped) => {
+                output.skipped.push(skipped);
+                return Ok(());
+            }
+            FileRead::Duplicate { block, original } => {
+                output.skipped.push(SkippedFile::new(
+                    &block.path,
+                    SkipReason::Duplicate,
+                    Some(format!("identical to {}", original.display())),
+                ));
+                // Say where the content is, as long as that file made it into the output
+                if !output.digests.contains_key(&*original.to_string_lossy()) {
+                    return Ok(());
+                }
+                let section = block.section;
+                let rendered = formatter.duplicate(
+                    &block.shown_path,
+                    shown_path(&root, &original),
+                    output.files.len() + notes,
+                );
+                let mut cost = tokenizer.count_tokens(&rendered);
+                if let Some(budget) = &mut budget {
+                    if budget_exhausted || !budget.try_take(section, cost) {
+                        return Ok(());
+                    }
+                }
+                if current_section != Some(section) {
+                    current_section = Some(section);
+                    let header = formatter.section(&sections[section]);
+                    cost += tokenizer.count_tokens(&header);
+                    sink(ContextChunk::Text(header))?;
+                }
+                output.tokens += cost;
+                notes += 1;
+                sink(ContextChunk::Duplicate {
+                    path: &block.path,
+                    original: &original,
+                    section: &sections[section],
+                    rendered,
+                })?;
+                return Ok(());
+            }
+        };
+        let section = block.section;
+
+        let selection = match (&mut budget, &mut selections) {
+            (None, _) => Selection::Full,
+            (Some(_), Some(selections)) => selections
+                .remove(&block.path)
+                .expect("prioritized files are placed as they were read"),
+            (Some(budget), None) => {
+                let cost = block.cost.expect("files are costed when there is a budget");
+                if !budget_exhausted && budget.try_take(section, cost) {
+                    Selection::Full
+                } else if budget_exhausted || options.budget_strategy == BudgetStrategy::Stop {
+                    budget_exhausted = true;
+                    Selection::Dropped(cost)
+                } else {
+                    budget_exhausted = true;
+                    budget
+                        .truncate(
+                            &block,
+                            output.files.len() + notes,
+                            options.header,
+                            formatter.as_ref(),
+                            tokenizer.as_ref(),
+                        )
+                        .map_or(Selection::Dropped(cost), Selection::Truncated)
+                }
+            }
+        };
+
+        let path = block.path.to_string_lossy().to_string();
+        let (content, truncated) = match selection {
+            Selection::Full => (&*block.content, false),
+            Selection::Truncated(ref content) => {
+                output.truncated.push(path.clone());
+                (content.as_str(), true)
+            }
+            Selection::Dropped(tokens) => {
+                output.dropped.push(DroppedFile {
+                    path,
+                    tokens,
+                    diff: false,
+                });
+                return Ok(());
+            }
+        };
+        if block.encoding != encoding::UTF_8 {
+            output.decoded.push(DecodedFile {
+                path: path.clone(),
+                encoding: block.encoding.to_string(),
+            });
+        }
+        if block.skeleton {
+            output.skeletonized.push(path.clone());
+        }
+        if let Some(kept) = block.excerpt {
+            output.excerpted.push(ExcerptedFile {
+                path: path.clone(),
+                size: block.size,
+                kept,
+            });
+        }
+        if let Some(redactions) = &block.redactions {
+            output.redacted.push(RedactedFile {
+                path: path.clone(),
+                redactions: redactions.clone(),
+                diff: false,
+            });
+        }
+        if let Some((tokens_before, tokens_after)) = block.transformed {
+            output.transformed.push(TransformedFile {
+                path: path.clone(),
+                tokens_before,
+                tokens_after,
+            });
+        }
+
+        if current_section != Some(section) {
+            current_section = Some(section);
+            let header = formatter.section(&sections[section]);
+            output.tokens += tokenizer.count_tokens(&header);
+            sink(ContextChunk::Text(header))?;
+        }
+        let record = block.record(content, options.header);
+        let rendered = formatter.file(&record, output.files.len() + notes);
+        output.tokens += tokenizer.count_tokens(&rendered);
+        output.digests.insert(path.clone(), block.digest);
+        output.files.push(path);
+        sink(ContextChunk::File {
+            path: &block.path,
+            record,
+            section: &sections[section],
+            rendered,
+            truncated,
+            skeleton: block.skeleton,
+            excerpt: block.excerpt.is_some(),
+        })?;
+        Ok(())
+    };
+    match prioritized {
+        Some(reads) => reads.into_iter().try_for_each(&mut place)?,
+        None => map_in_order(planned, |file| reader.read(file), &mut place)?,
+    }
+
+    for mut diff in diffs {
+        let path = root.join(&diff.path);
+        if let Some((redactor, _)) = &redactor {
+            if let Some(redacted) = redactor.redact(&diff.path, &diff.patch) {
+                diff.patch = redacted.content;
+                output.redacted.push(RedactedFile {
+                    path: path.to_string_lossy().to_string(),
+                    redactions: redacted.redactions,
+                    diff: true,
+                });
+            }
+        }
+        let rendered = formatter.diff(&diff.path, &diff.patch, output.files.len() + notes);
+        let cost = tokenizer.count_tokens(&rendered);
+        if let Some(budget) = &mut budget {
+            if budget_exhausted || !budget.try_take(changes_section, cost) {
+                output.dropped.push(DroppedFile {
+                    path: path.to_string_lossy().to_string(),
+                    tokens: cost,
+                    diff: true,
+                });
+                continue;
+            }
+        }
+        if current_section != Some(changes_section) {
+            current_section = Some(changes_section);
+            let header = formatter.section(&sections[changes_section]);
+            output.tokens += tokenizer.count_tokens(&header);
+            sink(ContextChunk::Text(header))?;
+        }
+        output.tokens += cost;
+        notes += 1;
+        sink(ContextChunk::Diff {
+            path: &path,
+            patch: &diff.patch,
+            rendered,
+        })?;
+    }
+
+    let end = formatter.end();
+    output.tokens += tokenizer.count_tokens(&end);
+    sink(ContextChunk::Text(end))?;
+    Ok(output)
+}
+
+/// Reads gathered files for the output, through the cache if there is one, and works out what
+/// each costs if there is a token budget. This is the part of placing a file that can run in
+/// parallel.
+struct FileReader<'a> {
+    root: &'a Path,
+    cache: Option<&'a ContentCache>,
+    /// Where to read the files from instead of the working tree, if anywhere.
+    revision: Option<&'a git::RevisionFiles>,
+    /// Files over a size limit, with how many of their bytes to read.
+    excerpts: &'a HashMap<PathBuf, u64>,
+    skeletons: Option<&'a SkeletonFilter>,
+    /// The detectors, with the fingerprint of their settings that cached costs are kept by.
+    redactor: Option<(&'a Redactor, ContentDigest)>,
+    transforms: Option<(&'a TransformPipeline, &'a dyn Tokenizer)>,
+    costing: Option<(&'a dyn OutputFormatter, &'a dyn Tokenizer)>,
+    format: OutputFormat,
+    header: HeaderOptions,
+    tokenizer: TokenizerKind,
+}
+
+impl FileReader<'_> {
+    /// Reads, decodes and hashes a file, skipping it if it cannot be read, is binary or cannot
+    /// be decoded. Files that have not changed since they were cached are not read again, and
+    /// excerpts of files and files read from a revision are never cached.
+    fn read(&self, (section, path): (usize, PathBuf)) -> FileRead {
+        let revision = self.revision.filter(|revision| revision.contains(&path));
+        let key = match revision.and_then(|revision| revision.size(&path)) {
+            Some(size) => Ok((
+                size,
+                revision.map_or(UNIX_EPOCH, git::RevisionFiles::modified),
+            )),
+            None => metadata(&path).and_then(|metadata| Ok((metadata.len(), metadata.modified()?))),
+        };
+        let (size, modified) = match key {
+            Ok(key) => key,
+            Err(e) => return FileRead::Skipped(SkippedFile::unreadable(&path, &e)),
+        };
+        let excerpt = self.excerpts.get(&path).copied();
+        let uncached = excerpt.is_some() || revision.is_some();
+        let cached = self
+            .cache
+            .filter(|_| !uncached)
+            .and_then(|cache| cache.get(&path, size, modified));
+        let mut changed = cached.is_none();
+        let mut kept = None;
+        let mut file = match cached {
+            Some(file) => file,
+            None => {
+                let content = match (revision, excerpt) {
+                    (Some(revision), Some(limit)) => revision.read(&path).map(|bytes| {
+                        let (content, bytes) = excerpt_of(&bytes, limit);
+                        kept = Some(bytes);
+                        content
+                    }),
+                    (Some(revision), None) => revision.read(&path).map(|bytes| classify(&bytes)),
+                    (None, Some(limit)) => {
+                        read_excerpt(&path, size, limit).map(|(content, bytes)| {
+                            kept = Some(bytes);
+                            content
+                        })
+                    }
+                    (None, None) => read(&path).map(|bytes| classify(&bytes)),
+                };
+                match content {
+                    Ok(content) => CachedFile {
+                        size,
+                        modified,
+                        content,
+                        costs: Vec::new(),
+                    },
+                    Err(e) => return FileRead::Skipped(SkippedFile::unreadable(&path, &e)),
+                }
+            }
+        };
+
+        let read = match &file.content {
+            CachedContent::Text {
+                text,
+                encoding,
+                digest,
+            } => {
+                // Secrets go first, so that nothing made from the content can hold them
+                let redacted = self
+                    .redactor
+                    .and_then(|(redactor, _)| redactor.redact(&path, text));
+                let (text, redactions) = match redacted {
+                    Some(redacted) => (Arc::from(redacted.content), Some(redacted.redactions)),
+                    None => (Arc::clone(text), None),
+                };
+                let skeleton = self
+                    .skeletons
+                    .filter(|skeletons| skeletons.applies(self.root, &path))
+                    .and_then(|_| skeleton::skeletonize(&path, &text));
+                let mut block = FileBlock {
+                    section,
+                    shown_path: shown_path(self.root, &path).to_path_buf(),
+                    path: path.clone(),
+                    size,
+                    modified,
+                    skeleton: skeleton.is_some(),
+                    excerpt: kept,
+                    content: skeleton.map_or(text, Arc::from),
+                    encoding: encoding.0,
+                    digest: *digest,
+                    transformed: None,
+                    redactions,
+                    cost: None,
+                };
+                if let Some((transforms, tokenizer)) = self.transforms {
+                    let content = transforms.apply(&path, &block.content);
+                    if *content != *block.content {
+                        block.transformed = Some((
+                            tokenizer.count_tokens(&block.content),
+                            tokenizer.count_tokens(&content),
+                        ));
+                        block.content = content.into();
+                    }
+                }
+                if let Some((formatter, tokenizer)) = self.costing {
+                    let redaction = self.redactor.map(|(_, fingerprint)| fingerprint);
+                    // Costs after transforms depend on their settings, so they are not cached
+                    let cost = if self.transforms.is_some() {
+                        file_cost(&block, self.header, formatter, tokenizer)
+                    } else if let Some(cost) = file.cost(
+                        self.tokenizer,
+                        self.format,
+                        self.header,
+                        block.skeleton,
+                        redaction,
+                    ) {
+                        cost
+                    } else {
+                        let tokens = file_cost(&block, self.header, formatter, tokenizer);
+                        file.costs.push(TokenCost {
+                            tokenizer: self.tokenizer,
+                            format: self.format,
+                            header: self.header,
+                            skeleton: block.skeleton,
+                            redaction,
+                            tokens,
+                        });
+                        changed = true;
+                        tokens
+                    };
+                    block.cost = Some(cost);
+                }
+                FileRead::Block(block)
+            }
+            CachedContent::Binary => {
+                FileRead::Skipped(SkippedFile::new(&path, SkipReason::Binary, None))
+            }
+            CachedContent::Undecodable => {
+                FileRead::Skipped(SkippedFile::new(&path, SkipReason::InvalidEncoding, None))
+            }
+        };
+        if let (Some(cache), true, false) = (self.cache, changed, uncached) {
+            cache.insert(path, file);
+        }
+        read
+    }
+}
 
-    // Construct the final content string
-    for (section_name, section_content) in sections.iter() {
-        if !section_content.is_empty() {
-            content.push_str(&format!("========================================\nSection: {}\n========================================\n", section_name));
-            content.push_str(section_content);
+/// A gathered file's text, read like files are read for the output.
+pub(crate) struct TextFile {
+    pub content: Arc<str>,
+    pub digest: ContentDigest,
+}
+
+/// Reads and decodes a file through `cache`, if given, returning why it cannot be included if it
+/// is unreadable, binary or undecodable.
+pub(crate) fn read_text_file(
+    path: PathBuf,
+    cache: Option<&ContentCache>,
+) -> Result<TextFile, SkippedFile> {
+    let reader = FileReader {
+        root: Path::new(""),
+        cache,
+        revision: None,
+        excerpts: &HashMap::new(),
+        skeletons: None,
+        redactor: None,
+        transforms: None,
+        costing: None,
+        format: OutputFormat::default(),
+        header: HeaderOptions::default(),
+        tokenizer: TokenizerKind::default(),
+    };
+    match reader.read((0, path)) {
+        FileRead::Block(block) => Ok(TextFile {
+            content: block.content,
+            digest: block.digest,
+        }),
+        FileRead::Skipped(skipped) => Err(skipped),
+        FileRead::Duplicate { .. } => unreachable!("only deduplicate finds duplicates"),
+    }
+}
+
+/// Applies the size limits to the files about to be placed, in output order. Files over a
+/// limit are skipped, or, if oversized files are truncated, only as many of their bytes as the
+/// limit allows are to be read. Returns the files skipped and the number of bytes to read of
+/// each file to be excerpted.
+fn apply_size_limits(
+    planned: &mut Vec<(usize, PathBuf)>,
+    limits: &SizeLimits,
+    revision: Option<&git::RevisionFiles>,
+) -> (Vec<SkippedFile>, HashMap<PathBuf, u64>) {
+    let mut skipped = Vec::new();
+    let mut excerpts = HashMap::new();
+    if limits.max_file_size.is_none() && limits.max_total_size.is_none() {
+        return (skipped, excerpts);
+    }
+    let truncate = limits.oversized == OversizePolicy::Truncate;
+    let mut total = 0;
+    planned.retain(|(_, path)| {
+        // Files that cannot be read are reported when they are
+        let size = match revision.and_then(|revision| revision.size(path)) {
+            Some(size) => size,
+            None => match metadata(path) {
+                Ok(metadata) => metadata.len(),
+                Err(_) => return true,
+            },
+        };
+        let mut kept = size;
+        if let Some(max) = limits.max_file_size.filter(|max| size > *max) {
+            if !truncate {
+                let detail = format!("{} bytes, limit is {}", size, max);
+                skipped.push(SkippedFile::new(path, SkipReason::TooLarge, Some(detail)));
+                return false;
+            }
+            kept = max;
+        }
+        if let Some(max) = limits.max_total_size {
+            let left = max.saturating_sub(total);
+            if kept > left {
+                if !truncate || left < MIN_EXCERPT_SIZE {
+                    let detail = format!(
+                        "{} bytes with {} of the {} allowed in total left",
+                        kept, left, max
+                    );
+                    skipped.push(SkippedFile::new(
+                        path,
+                        SkipReason::TotalSizeExceeded,
+                        Some(detail),
+                    ));
+                    return false;
+                }
+                kept = left;
+            }
+        }
+        total += kept;
+        if kept < size {
+            excerpts.insert(path.clone(), kept);
         }
+        true
+    });
+    (skipped, excerpts)
+}
+
+/// Reads the start and the end of a file, `limit` bytes of it in all, with a note of how much
+/// was left out between them. The excerpts are cut at line breaks where there are any, so that
+/// no line is cut in two. Only the excerpts are read, so their digest is of the bytes they hold
+/// rather than of the whole file.
+///
+/// Returns the excerpts' content and how many bytes of the file they hold.
+fn read_excerpt(path: &Path, size: u64, limit: u64) -> io::Result<(CachedContent, u64)> {
+    let mut file = File::open(path)?;
+    let head_limit = limit / 2;
+    let tail_limit = limit - head_limit;
+
+    let mut head = Vec::with_capacity(head_limit as usize);
+    (&mut file).take(head_limit).read_to_end(&mut head)?;
+    let mut tail = Vec::with_capacity(tail_limit as usize);
+    file.seek(SeekFrom::Start(size.saturating_sub(tail_limit)))?;
+    file.take(tail_limit).read_to_end(&mut tail)?;
+    Ok(join_excerpt(&head, &tail, size))
+}
+
+/// Like `read_excerpt`, for a file already in memory.
+fn excerpt_of(bytes: &[u8], limit: u64) -> (CachedContent, u64) {
+    let head_limit = (limit / 2) as usize;
+    let tail_limit = (limit as usize - head_limit).min(bytes.len());
+    let head = &bytes[..head_limit.min(bytes.len())];
+    let tail = &bytes[bytes.len() - tail_limit..];
+    join_excerpt(head, tail, bytes.len() as u64)
+}
+
+/// Decodes the start and the end of a file of `size` bytes, cut at line breaks, and joins them
+/// with a note of how much was left out between them. The end is decoded in the encoding found
+/// for the start, from the first whole character in it.
+///
+/// Returns the excerpts' content and how many bytes of the file they hold.
+fn join_excerpt(head: &[u8], tail: &[u8], size: u64) -> (CachedContent, u64) {
+    if is_binary_content(head) {
+        return (CachedContent::Binary, 0);
     }
+    // The end of the file starts at an odd offset when half a UTF-16 code unit is cut off
+    let tail_offset = (size - tail.len() as u64) as usize;
+    let (head, tail) = match encoding::utf16_little_endian(&head[..head.len() & !1]) {
+        Some(little_endian) => (
+            utf16_head(head, little_endian),
+            utf16_tail(&tail[tail_offset % 2..], little_endian),
+        ),
+        None => (utf8_head(head), utf8_tail(tail)),
+    };
+    let Some(head_text) = encoding::decode(head) else {
+        return (CachedContent::Undecodable, 0);
+    };
+    // A start in plain ASCII says nothing about the encoding, so the end finds its own
+    let ascii_head = head_text.encoding == encoding::UTF_8 && head_text.text.is_ascii();
+    let tail_text = match ascii_head {
+        true => encoding::decode(tail),
+        false => encoding::decode_as(head_text.encoding, tail),
+    };
+    let Some(tail_text) = tail_text else {
+        return (CachedContent::Undecodable, 0);
+    };
 
-    Ok((content, filenames))
-}
\ No newline at end of file
+    let kept = (head.len() + tail.len()) as u64;
+    let encoding = match ascii_head {
+        true => tail_text.encoding,
+        false => head_text.encoding,
+    };
+    let mut text = head_text.text;
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push_str(&format!("[... {} bytes omitted ...]\n", size - kept));
+    text.push_str(&tail_text.text);
+    let content = CachedContent::Text {
+        text: text.into(),
+        encoding: EncodingName(encoding),
+        digest: ContentDigest::of_parts(&[head, tail]),
+    };
+    (content, kept)
+}
+
+/// The start of a file in UTF-8, or a single-byte encoding, up to its last line break.
+fn utf8_head(head: &[u8]) -> &[u8] {
+    match head.iter().rposition(|&byte| byte == b'\n') {
+        Some(end) => &head[..=end],
+        None => &head[..utf8_boundary(head)],
+    }
+}
+
+/// The end of a file in UTF-8, or a single-byte encoding, from its first line break.
+fn utf8_tail(tail: &[u8]) -> &[u8] {
+    match tail.iter().position(|&byte| byte == b'\n') {
+        Some(end) => &tail[end + 1..],
+        // Continuation bytes of a UTF-8 character that began before the excerpt
+        None => &tail[tail.iter().take_while(|&&byte| byte & 0xC0 == 0x80).count()..],
+    }
+}
+
+/// The start of a file in UTF-16, up to its last line break.
+fn utf16_head(head: &[u8], little_endian: bool) -> &[u8] {
+    let units = utf16_units(head, little_endian);
+    match units.iter().rposition(|&unit| unit == u16::from(b'\n')) {
+        Some(end) => &head[..(end + 1) * 2],
+        // Leave out the first half of a surrogate pair whose second half was cut off
+        None => match units.last() {
+            Some(0xD800..=0xDBFF) => &head[..(units.len() - 1) * 2],
+            _ => &head[..units.len() * 2],
+        },
+    }
+}
+
+/// The end of a file in UTF-16, starting on a code unit, from its first line break.
+fn utf16_tail(tail: &[u8], little_endian: bool) -> &[u8] {
+    let units = utf16_units(tail, little_endian);
+    match units.iter().position(|&unit| unit == u16::from(b'\n')) {
+        Some(end) => &tail[(end + 1) * 2..],
+        // The second half of a surrogate pair whose first half was cut off
+        None => match units.first() {
+            Some(0xDC00..=0xDFFF) => &tail[2..],
+            _ => tail,
+        },
+    }
+}
+
+fn utf16_units(bytes: &[u8], little_endian: bool) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| match little_endian {
+            true => u16::from_le_bytes([pair[0], pair[1]]),
+            false => u16::from_be_bytes([pair[0], pair[1]]),
+        })
+        .collect()
+}
+
+/// The length of the longest prefix of `bytes` that does not end part way through a UTF-8
+/// character.
+fn utf8_boundary(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => bytes.len(),
+    }
+}
+
+/// Works out what a file's content is: binary, undecodable, or text in some encoding.
+fn classify(bytes: &[u8]) -> CachedContent {
+    if is_binary_content(bytes) {
+        return CachedContent::Binary;
+    }
+    match encoding::decode(bytes) {
+        Some(decoded) => CachedContent::Text {
+            digest: ContentDigest::of(bytes),
+            text: decoded.text.into(),
+            encoding: EncodingName(decoded.encoding),
+        },
+        None => CachedContent::Undecodable,
+    }
+}
+
+/// Turns a file read by `FileReader` into a duplicate if its content has already been seen,
+/// remembering the file it was first seen in. Files have to go through this in output order, so
+/// that the first copy is the one kept.
+fn deduplicate(read: FileRead, seen: &mut HashMap<ContentDigest, PathBuf>) -> FileRead {
+    match read {
+        FileRead::Block(block) => match seen.get(&block.digest) {
+            Some(original) => FileRead::Duplicate {
+                original: original.clone(),
+                block,
+            },
+            None => {
+                seen.insert(block.digest, block.path.clone());
+                FileRead::Block(block)
+            }
+        },
+        read => read,
+    }
+}
+
+/// Draws the files about to be placed, with their sizes, and the files skipped while gathering
+/// that are to be shown, with why they were skipped.
+fn draw_tree(root: &Path, planned: &[(usize, PathBuf)], skipped: &[SkippedFile]) -> String {
+    let mut tree = ProjectTree::new(root);
+    let relative = |path: &'_ Path| path.strip_prefix(root).unwrap_or(path).to_path_buf();
+    for (_, path) in planned {
+        let size = metadata(path)
+            .ok()
+            .map(|m| format!("({})", format_size(m.len())));
+        tree.add(&relative(path), false, size);
+    }
+    for skipped in skipped {
+        let path = Path::new(&skipped.path);
+        let metadata = metadata(path).ok();
+        let note = match metadata.as_ref().filter(|m| m.is_file()) {
+            Some(m) => format!("({}) [{}]", format_size(m.len()), skipped.reason),
+            None => format!("[{}]", skipped.reason),
+        };
+        tree.add(
+            &relative(path),
+            metadata.is_some_and(|m| m.is_dir()),
+            Some(note),
+        );
+    }
+    tree.render()
+}
+
+/// The path a file is shown under in the output: relative to `root`, or just its name if it is
+/// not under it.
+fn shown_path<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    path.strip_prefix(root)
+        .ok()
+        .or_else(|| path.file_name().map(Path::new))
+        .unwrap_or(path)
+}
+
+/// Returns the deepest directory containing every file.
+fn common_root(files: &[PathBuf]) -> PathBuf {
+    let mut root = match files.first().and_then(|f| f.parent()) {
+        Some(parent) => parent.to_path_buf(),
+        None => return PathBuf::new(),
+    };
+    for file in &files[1..] {
+        while !file.starts_with(&root) {
+            if !root.pop() {
+                return PathBuf::new();
+            }
+        }
+    }
+    root
+}
+
+/// Files are measured as if placed this far down the output. That is an upper bound on what
+/// they cost once placed, as formats render a file the same way wherever it is except for its
+/// index, which takes no more digits than this in any output that fits in a context window.
+const COST_INDEX: usize = 99_999;
+
+/// Measures a file's cost once placed in the output. It does not depend on where the file is
+/// placed, so it can be cached.
+fn file_cost(
+    block: &FileBlock,
+    header: HeaderOptions,
+    formatter: &dyn OutputFormatter,
+    tokenizer: &dyn Tokenizer,
+) -> usize {
+    tokenizer.count_tokens(&formatter.file(&block.record(&block.content, header), COST_INDEX))
+}
+
+/// Picks the files that fit when the cheapest are considered first; output order is
+/// unaffected. Every file is read ahead of the output, and the reads are returned in output
+/// order to be placed as they are, along with the selection for each file that is not a
+/// duplicate, by path.
+fn prioritize(
+    planned: Vec<(usize, PathBuf)>,
+    budget: &mut Budget,
+    reader: &FileReader,
+) -> Result<(Vec<FileRead>, HashMap<PathBuf, Selection>), ContexterError> {
+    let mut seen = HashMap::new();
+    let mut reads = Vec::new();
+    map_in_order(
+        planned,
+        |file| reader.read(file),
+        |read| -> Result<(), ContexterError> {
+            reads.push(deduplicate(read, &mut seen));
+            Ok(())
+        },
+    )?;
+
+    let mut costs: Vec<(&Path, usize, usize)> = reads
+        .iter()
+        .filter_map(|read| match read {
+            FileRead::Block(block) => {
+                let cost = block.cost.expect("files are costed when there is a budget");
+                Some((block.path.as_path(), block.section, cost))
+            }
+            _ => None,
+        })
+        .collect();
+    // A stable sort keeps files of the same cost in output order
+    costs.sort_by_key(|&(_, _, cost)| cost);
+
+    let mut selections = HashMap::new();
+    for (path, section, cost) in costs {
+        let selection = match budget.try_take(section, cost) {
+            true => Selection::Full,
+            false => Selection::Dropped(cost),
+        };
+        selections.insert(path.to_path_buf(), selection);
+    }
+    Ok((reads, selections))
+}
+
+/// Keeps track of how much of the token budget has been spent.
+struct Budget {
+    max_tokens: usize,
+    used: usize,
+    section_costs: Vec<usize>,
+    opened_sections: Vec<bool>,
+}
+
+impl Budget {
+    fn new(
+        max_tokens: usize,
+        sections: &[String],
+        formatter: &dyn OutputFormatter,
+        tokenizer: &dyn Tokenizer,
+    ) -> Self {
+        Budget {
+            max_tokens,
+            used: tokenizer.count_tokens(&formatter.begin())
+                + tokenizer.count_tokens(&formatter.end()),
+            section_costs: sections
+                .iter()
+                .map(|name| tokenizer.count_tokens(&formatter.section(name)))
+                .collect(),
+            opened_sections: vec![false; sections.len()],
+        }
+    }
+
+    /// What it costs to open a section, which is nothing once it has been opened.
+    fn section_cost(&self, section: usize) -> usize {
+        if self.opened_sections[section] {
+            0
+        } else {
+            self.section_costs[section]
+        }
+    }
+
+    /// Spends `cost` tokens on a file in `section` if they fit in what is left.
+    fn try_take(&mut self, section: usize, cost: usize) -> bool {
+        let cost = self.section_cost(section) + cost;
+        if self.used + cost > self.max_tokens {
+            return false;
+        }
+        self.opened_sections[section] = true;
+        self.used += cost;
+        true
+    }
+
+    /// Cuts a file's content down to what is left of the budget, returning `None` if nothing
+    /// of it fits.
+    fn truncate(
+        &mut self,
+        block: &FileBlock,
+        index: usize,
+        header: HeaderOptions,
+        formatter: &dyn OutputFormatter,
+        tokenizer: &dyn Tokenizer,
+    ) -> Option<String> {
+        let available = self
+            .max_tokens
+            .saturating_sub(self.used + self.section_cost(block.section));
+        let content = fit_rendered(&block.content, available, tokenizer, |content| {
+            formatter.file(&block.record(content, header), index)
+        })?;
+        self.opened_sections[block.section] = true;
+        self.used = self.max_tokens;
+        Some(content)
+    }
+
+    /// Spends what the tree of the files costs. A tree that does not fit in what is left is cut
+    /// down to it, and uses it up, and `None` is returned if nothing of it fits.
+    fn take_structure(
+        &mut self,
+        tree: String,
+        formatter: &dyn OutputFormatter,
+        tokenizer: &dyn Tokenizer,
+    ) -> Option<String> {
+        let cost = tokenizer.count_tokens(&formatter.structure(&tree, 0));
+        if self.used + cost <= self.max_tokens {
+            self.used += cost;
+            return Some(tree);
+        }
+        let available = self.max_tokens.saturating_sub(self.used);
+        let tree = fit_rendered(&tree, available, tokenizer, |tree| {
+            formatter.structure(tree, 0)
+        })?;
+        self.used = self.max_tokens;
+        Some(tree)
+    }
+}
+
+/// Cuts `text` down to the longest prefix that, followed by the truncation marker, `render`s
+/// within `available` tokens, or `None` if not even one character fits.
+///
+/// What the text costs on its own is only a first guess: the formatter may escape or wrap it, so
+/// each cut is measured on the rendered output.
+fn fit_rendered(
+    text: &str,
+    available: usize,
+    tokenizer: &dyn Tokenizer,
+    render: impl Fn(&str) -> String,
+) -> Option<String> {
+    let overhead = tokenizer.count_tokens(&render("")) + tokenizer.count_tokens(TRUNCATION_MARKER);
+    let guess = tokenizer.truncate(text, available.saturating_sub(overhead));
+    let ends: Vec<usize> = guess
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .collect();
+    let cut = |chars: usize| format!("{}{}", &guess[..ends[chars - 1]], TRUNCATION_MARKER);
+    let (mut low, mut high) = (0, ends.len());
+    while low < high {
+        let mid = (low + high).div_ceil(2);
+        if tokenizer.count_tokens(&render(&cut(mid))) <= available {
+            low = mid;
+        } else {
+            high = mid - 1;
+        }
+    }
+    (low > 0).then(|| cut(low))
+}
@@ -0,0 +1,56 @@
+use hex::FromHex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// The SHA-256 digest of a file's content, which identifies it independently of its path and of
+/// the Rust version the server was built with.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ContentDigest([u8; 32]);
+
+impl ContentDigest {
+    pub fn of(bytes: &[u8]) -> Self {
+        ContentDigest(Sha256::digest(bytes).into())
+    }
+
+    /// The digest of `parts` one after the other, as if they were a single piece.
+    pub fn of_parts(parts: &[&[u8]]) -> Self {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        ContentDigest(hasher.finalize().into())
+    }
+
+    /// The digest as 64 lowercase hex digits, as printed by `sha256sum`.
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+}
+
+impl fmt::Display for ContentDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for ContentDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ContentDigest({})", self)
+    }
+}
+
+impl Serialize for ContentDigest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for ContentDigest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let digest = String::deserialize(deserializer)?;
+        <[u8; 32]>::from_hex(&digest)
+            .map(ContentDigest)
+            .map_err(|e| serde::de::Error::custom(format!("invalid digest '{}': {}", digest, e)))
+    }
+}
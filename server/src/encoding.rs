@@ -0,0 +1,113 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1252};
+
+/// Name of the encoding most files are in.
+pub const UTF_8: &str = "UTF-8";
+
+/// A file's content decoded to text.
+pub struct DecodedText {
+    pub text: String,
+    /// Name of the encoding the content was decoded from, e.g. `UTF-8` or `UTF-16LE`.
+    pub encoding: &'static str,
+}
+
+/// Detects the encoding of a file's content and decodes it.
+///
+/// A byte order mark decides the encoding when present. Otherwise the content is read as
+/// UTF-8 if valid, as UTF-16 if it looks like it, and as Windows-1252 (a superset of Latin-1)
+/// as a last resort. Returns `None` if the content does not decode to plausible text.
+pub fn decode(bytes: &[u8]) -> Option<DecodedText> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        return decode_strictly(encoding, &bytes[bom_length..]);
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Some(DecodedText {
+            text: text.to_string(),
+            encoding: UTF_8,
+        });
+    }
+    if let Some(encoding) = sniff_utf16(bytes) {
+        return decode_strictly(encoding, bytes);
+    }
+
+    let (text, _) = WINDOWS_1252.decode_without_bom_handling(bytes);
+    if !is_plausible_text(&text) {
+        return None;
+    }
+    Some(DecodedText {
+        text: text.into_owned(),
+        encoding: WINDOWS_1252.name(),
+    })
+}
+
+/// Decodes content known to be in the encoding `decode` reported as `name`, such as the end of a
+/// file whose start was decoded. Returns `None` if the content is not valid in it.
+pub fn decode_as(name: &str, bytes: &[u8]) -> Option<DecodedText> {
+    let encoding = Encoding::for_label(name.as_bytes())?;
+    if encoding != WINDOWS_1252 {
+        return decode_strictly(encoding, bytes);
+    }
+    let (text, _) = WINDOWS_1252.decode_without_bom_handling(bytes);
+    is_plausible_text(&text).then(|| DecodedText {
+        text: text.into_owned(),
+        encoding: WINDOWS_1252.name(),
+    })
+}
+
+/// Returns whether content in UTF-16 is little-endian, or `None` if it is not UTF-16.
+pub fn utf16_little_endian(bytes: &[u8]) -> Option<bool> {
+    let encoding = match Encoding::for_bom(bytes) {
+        Some((encoding, _)) => encoding,
+        None => sniff_utf16(bytes)?,
+    };
+    (encoding == UTF_16LE || encoding == UTF_16BE).then_some(encoding == UTF_16LE)
+}
+
+/// Returns the name `decode` reports for an encoding, given any of its labels.
+pub fn canonical_name(label: &str) -> Option<&'static str> {
+    Encoding::for_label(label.as_bytes()).map(Encoding::name)
+}
+
+/// Checks if content looks like UTF-16 text, with or without a byte order mark.
+pub fn looks_like_utf16(bytes: &[u8]) -> bool {
+    matches!(Encoding::for_bom(bytes), Some((encoding, _)) if encoding != encoding_rs::UTF_8)
+        || sniff_utf16(bytes).is_some()
+}
+
+fn decode_strictly(encoding: &'static Encoding, bytes: &[u8]) -> Option<DecodedText> {
+    let text = encoding.decode_without_bom_handling_and_without_replacement(bytes)?;
+    Some(DecodedText {
+        text: text.into_owned(),
+        encoding: encoding.name(),
+    })
+}
+
+/// Recognizes BOM-less UTF-16 holding mostly ASCII text, where every other byte is zero.
+fn sniff_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let zero_high = bytes.chunks_exact(2).filter(|pair| pair[1] == 0).count();
+    let zero_low = bytes.chunks_exact(2).filter(|pair| pair[0] == 0).count();
+    if zero_high * 10 >= pairs * 9 && zero_low == 0 {
+        Some(UTF_16LE)
+    } else if zero_low * 10 >= pairs * 9 && zero_high == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+/// Rejects text where more than one character in a hundred is a control character other than
+/// common whitespace, which suggests the content was not text in the guessed encoding.
+fn is_plausible_text(text: &str) -> bool {
+    let mut chars = 0;
+    let mut controls = 0;
+    for c in text.chars() {
+        chars += 1;
+        if c.is_control() && !matches!(c, '\n' | '\r' | '\t' | '\x0c') {
+            controls += 1;
+        }
+    }
+    controls * 100 <= chars
+}
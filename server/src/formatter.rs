@@ -0,0 +1,398 @@
+use crate::digest::ContentDigest;
+use crate::language::detect_language;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// The title of the tree of the project's files that may open the output.
+pub const STRUCTURE_TITLE: &str = "Project Structure";
+
+/// The title of the section holding the diffs of changed files, after the files themselves.
+pub const CHANGES_TITLE: &str = "Changes";
+
+/// A file as it is handed to an `OutputFormatter`.
+pub struct FileRecord<'a> {
+    /// Relative to the project root.
+    pub path: &'a Path,
+    /// Left out of the header when `None`.
+    pub size: Option<u64>,
+    /// Left out of the header when `None`.
+    pub modified: Option<SystemTime>,
+    pub digest: ContentDigest,
+    pub content: &'a str,
+}
+
+/// What a file's header shows besides its path. Leaving out sizes and modification times makes
+/// the output for unchanged files the same from one run to the next, and easy to diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct HeaderOptions {
+    #[serde(default)]
+    pub omit_size: bool,
+    #[serde(default)]
+    pub omit_modified: bool,
+}
+
+/// Renders gathered files into the text sent to an LLM.
+///
+/// Output is produced piece by piece: `begin`, the `structure` of the project if asked for,
+/// then for each section its `section` header followed by its files, then `end`. This lets
+/// callers measure and emit files incrementally.
+pub trait OutputFormatter: Send + Sync {
+    /// Text emitted before anything else.
+    fn begin(&self) -> String {
+        String::new()
+    }
+
+    /// Text opening a section of related files.
+    fn section(&self, name: &str) -> String;
+
+    /// Renders a single file. `index` is its position in the output, starting at zero.
+    fn file(&self, file: &FileRecord, index: usize) -> String;
+
+    /// Renders a note standing in for a file left out because its content is identical to
+    /// `original`, which is in the output. `index` is its position, counted like a file's.
+    fn duplicate(&self, path: &Path, original: &Path, _index: usize) -> String {
+        format!(
+            "{} is identical to {}\n",
+            path.display(),
+            original.display()
+        )
+    }
+
+    /// Renders the tree of the project's files, drawn by `ProjectTree`. It comes before any
+    /// file, at `index` zero, and files are counted after it.
+    fn structure(&self, tree: &str, _index: usize) -> String {
+        format!("{}{}\n", self.section(STRUCTURE_TITLE), tree)
+    }
+
+    /// Renders the unified diff of a changed file. `index` is its position, counted like a
+    /// file's.
+    fn diff(&self, path: &Path, patch: &str, _index: usize) -> String {
+        format!("Diff of {}:\n{}", path.display(), with_final_newline(patch))
+    }
+
+    /// Text emitted after everything else.
+    fn end(&self) -> String {
+        String::new()
+    }
+}
+
+/// The banner-delimited format contexter has always produced.
+pub struct PlainFormatter;
+
+const BANNER: &str = "========================================\n";
+
+impl OutputFormatter for PlainFormatter {
+    fn section(&self, name: &str) -> String {
+        format!("{}Section: {}\n{}", BANNER, name, BANNER)
+    }
+
+    fn file(&self, file: &FileRecord, _index: usize) -> String {
+        let mut rendered = format!("{}File: {}\n", BANNER, file.path.display());
+        if let Some(size) = file.size {
+            rendered.push_str(&format!("Size: {} bytes\n", size));
+        }
+        if let Some(modified) = file.modified {
+            rendered.push_str(&format!(
+                "Last Modified: {}\n",
+                humantime::format_rfc3339_seconds(modified)
+            ));
+        }
+        rendered.push_str(BANNER);
+        rendered.push_str(file.content);
+        rendered.push('\n');
+        rendered
+    }
+
+    fn duplicate(&self, path: &Path, original: &Path, _index: usize) -> String {
+        format!(
+            "{}File: {}\nIdentical to: {}\n{}",
+            BANNER,
+            path.display(),
+            original.display(),
+            BANNER
+        )
+    }
+
+    fn diff(&self, path: &Path, patch: &str, _index: usize) -> String {
+        format!(
+            "{}Diff: {}\n{}{}",
+            BANNER,
+            path.display(),
+            BANNER,
+            with_final_newline(patch)
+        )
+    }
+}
+
+/// Markdown with one fenced, language-tagged code block per file.
+pub struct MarkdownFormatter;
+
+impl OutputFormatter for MarkdownFormatter {
+    fn section(&self, name: &str) -> String {
+        format!("## {}\n\n", name)
+    }
+
+    fn file(&self, file: &FileRecord, _index: usize) -> String {
+        let fence = fence_for(file.content);
+        let language = detect_language(file.path).unwrap_or("");
+
+        let mut rendered = format!(
+            "### {}\n\n{}{}\n{}",
+            file.path.display(),
+            fence,
+            language,
+            file.content
+        );
+        if !file.content.ends_with('\n') {
+            rendered.push('\n');
+        }
+        rendered.push_str(&fence);
+        rendered.push_str("\n\n");
+        rendered
+    }
+
+    fn duplicate(&self, path: &Path, original: &Path, _index: usize) -> String {
+        format!(
+            "### {}\n\nIdentical to `{}`.\n\n",
+            path.display(),
+            original.display()
+        )
+    }
+
+    fn structure(&self, tree: &str, _index: usize) -> String {
+        format!("## {}\n\n```\n{}```\n\n", STRUCTURE_TITLE, tree)
+    }
+
+    fn diff(&self, path: &Path, patch: &str, _index: usize) -> String {
+        let fence = fence_for(patch);
+        format!(
+            "### {}\n\n{}diff\n{}{}\n\n",
+            path.display(),
+            fence,
+            with_final_newline(patch),
+            fence
+        )
+    }
+}
+
+/// Anthropic-style `<documents>` XML. Paths, contents, diffs and the tree are all escaped, so
+/// the output is well-formed whatever the files contain.
+pub struct XmlFormatter;
+
+impl OutputFormatter for XmlFormatter {
+    fn begin(&self) -> String {
+        "<documents>\n".to_string()
+    }
+
+    fn section(&self, _name: &str) -> String {
+        String::new()
+    }
+
+    fn file(&self, file: &FileRecord, index: usize) -> String {
+        let mut rendered = format!(
+            "<document index=\"{}\">\n<source>{}</source>\n<document_content>\n{}",
+            index + 1,
+            escape_xml(&file.path.display().to_string()),
+            escape_xml(file.content)
+        );
+        if !file.content.ends_with('\n') {
+            rendered.push('\n');
+        }
+        rendered.push_str("</document_content>\n</document>\n");
+        rendered
+    }
+
+    fn duplicate(&self, path: &Path, original: &Path, index: usize) -> String {
+        format!(
+            "<document index=\"{}\">\n<source>{}</source>\n<identical_to>{}</identical_to>\n</document>\n",
+            index + 1,
+            escape_xml(&path.display().to_string()),
+            escape_xml(&original.display().to_string())
+        )
+    }
+
+    fn structure(&self, tree: &str, index: usize) -> String {
+        format!(
+            "<document index=\"{}\">\n<source>{}</source>\n<document_content>\n{}</document_content>\n</document>\n",
+            index + 1,
+            STRUCTURE_TITLE,
+            escape_xml(tree)
+        )
+    }
+
+    fn diff(&self, path: &Path, patch: &str, index: usize) -> String {
+        format!(
+            "<document index=\"{}\">\n<source>{}</source>\n<diff>\n{}</diff>\n</document>\n",
+            index + 1,
+            escape_xml(&path.display().to_string()),
+            escape_xml(&with_final_newline(patch))
+        )
+    }
+
+    fn end(&self) -> String {
+        "</documents>\n".to_string()
+    }
+}
+
+/// A JSON array with one `{path, size, modified, content}` object per file.
+pub struct JsonFormatter;
+
+#[derive(Serialize)]
+struct JsonFileRecord<'a> {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified: Option<String>,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonDuplicateRecord {
+    path: String,
+    identical_to: String,
+}
+
+#[derive(Serialize)]
+struct JsonStructureRecord<'a> {
+    structure: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonDiffRecord<'a> {
+    path: String,
+    diff: &'a str,
+}
+
+impl OutputFormatter for JsonFormatter {
+    fn begin(&self) -> String {
+        "[\n".to_string()
+    }
+
+    fn section(&self, _name: &str) -> String {
+        String::new()
+    }
+
+    fn file(&self, file: &FileRecord, index: usize) -> String {
+        let record = JsonFileRecord {
+            path: file.path.display().to_string(),
+            size: file.size,
+            modified: file
+                .modified
+                .map(|modified| humantime::format_rfc3339_seconds(modified).to_string()),
+            content: file.content,
+        };
+        json_element(&record, index)
+    }
+
+    fn duplicate(&self, path: &Path, original: &Path, index: usize) -> String {
+        let record = JsonDuplicateRecord {
+            path: path.display().to_string(),
+            identical_to: original.display().to_string(),
+        };
+        json_element(&record, index)
+    }
+
+    fn structure(&self, tree: &str, index: usize) -> String {
+        json_element(&JsonStructureRecord { structure: tree }, index)
+    }
+
+    fn diff(&self, path: &Path, patch: &str, index: usize) -> String {
+        let record = JsonDiffRecord {
+            path: path.display().to_string(),
+            diff: patch,
+        };
+        json_element(&record, index)
+    }
+
+    fn end(&self) -> String {
+        "\n]\n".to_string()
+    }
+}
+
+/// Renders an element of the JSON array, preceded by a separator unless it is the first.
+fn json_element<T: Serialize>(record: &T, index: usize) -> String {
+    let separator = if index == 0 { "  " } else { ",\n  " };
+    format!(
+        "{}{}",
+        separator,
+        serde_json::to_string(record).expect("file records always serialize")
+    )
+}
+
+/// Picks a Markdown code fence longer than any backtick run inside `content`.
+fn fence_for(content: &str) -> String {
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest_run.max(2) + 1)
+}
+
+/// Ends text with a newline, so that what follows starts on a line of its own.
+fn with_final_newline(text: &str) -> String {
+    if text.is_empty() || text.ends_with('\n') {
+        text.to_string()
+    } else {
+        format!("{}\n", text)
+    }
+}
+
+/// Escapes the characters that are significant inside XML text.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The output formats that can be selected from the CLI, the config or an HTTP request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Plain,
+    Markdown,
+    Xml,
+    Json,
+}
+
+impl OutputFormat {
+    /// Creates the formatter for this format.
+    pub fn formatter(self) -> Box<dyn OutputFormatter> {
+        match self {
+            OutputFormat::Plain => Box::new(PlainFormatter),
+            OutputFormat::Markdown => Box::new(MarkdownFormatter),
+            OutputFormat::Xml => Box::new(XmlFormatter),
+            OutputFormat::Json => Box::new(JsonFormatter),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" | "text" => Ok(OutputFormat::Plain),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "xml" => Ok(OutputFormat::Xml),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!(
+                "Unknown format '{}' (expected plain, markdown, xml or json)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OutputFormat::Plain => "plain",
+            OutputFormat::Markdown => "markdown",
+            OutputFormat::Xml => "xml",
+            OutputFormat::Json => "json",
+        };
+        write!(f, "{}", name)
+    }
+}
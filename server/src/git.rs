@@ -0,0 +1,249 @@
+use git2::{Diff, DiffDelta, DiffOptions, ObjectType, Oid, Patch, Repository, Tree};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Which files to gather from the git repository a project is in, by how they changed. Only the
+/// local repository is read; nothing is fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitChanges {
+    /// Files that differ from a ref, such as a branch, tag or commit, whether the changes are
+    /// committed or not, along with untracked files.
+    Since(String),
+    /// Files with staged changes.
+    Staged,
+    /// Files with changes not committed yet, staged or not, along with untracked files.
+    Uncommitted,
+    /// Files that differ between two revisions. Their content is read from `to`.
+    Between { from: String, to: String },
+}
+
+impl GitChanges {
+    /// Works out what to compare from up to two revisions, as `git diff` does: the working tree
+    /// against `HEAD` without any, against `from` with one, or `from` against `to` with both.
+    pub fn comparing(from: Option<String>, to: Option<String>) -> Result<Self, git2::Error> {
+        match (from, to) {
+            (None, None) => Ok(GitChanges::Uncommitted),
+            (Some(from), None) => Ok(GitChanges::Since(from)),
+            (Some(from), Some(to)) => Ok(GitChanges::Between { from, to }),
+            (None, Some(_)) => Err(git2::Error::from_str(
+                "a revision to compare to needs one to compare from",
+            )),
+        }
+    }
+
+    /// The revision the changed files' content is read from, or `None` for the working tree.
+    pub fn revision(&self) -> Option<&str> {
+        match self {
+            GitChanges::Between { to, .. } => Some(to),
+            _ => None,
+        }
+    }
+}
+
+/// Lines of unchanged context shown around each change, as git shows by default.
+pub const DEFAULT_CONTEXT_LINES: u32 = 3;
+
+/// The unified diff of a changed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: PathBuf, // Relative to the project root
+    pub patch: String,
+}
+
+/// Finds the files under `root` that have changed as `changes` describes, relative to `root`.
+/// Deleted files are included: there is nothing left of them to gather, but their diffs still
+/// say what went.
+pub fn changed_files(root: &Path, changes: &GitChanges) -> Result<HashSet<PathBuf>, git2::Error> {
+    let project = ProjectRepository::open(root)?;
+    let diff = project.diff(changes, DEFAULT_CONTEXT_LINES)?;
+    Ok(diff
+        .deltas()
+        .filter_map(|delta| project.relative(delta_path(&delta)?))
+        .collect())
+}
+
+/// Works out the unified diff of each of `files` that has changed as `changes` describes, in
+/// the order of their paths, with `context_lines` of unchanged lines around each hunk. The
+/// hunks come from git, so files that are no longer in the working tree, such as deleted ones,
+/// have diffs too.
+pub fn file_diffs(
+    root: &Path,
+    changes: &GitChanges,
+    files: &[PathBuf],
+    context_lines: u32,
+) -> Result<Vec<FileDiff>, git2::Error> {
+    let wanted: HashSet<&Path> = files
+        .iter()
+        .map(|file| file.strip_prefix(root).unwrap_or(file))
+        .collect();
+    let project = ProjectRepository::open(root)?;
+    let diff = project.diff(changes, context_lines)?;
+
+    let mut diffs = Vec::new();
+    for (i, delta) in diff.deltas().enumerate() {
+        let Some(path) = delta_path(&delta).and_then(|p| project.relative(p)) else {
+            continue;
+        };
+        if !wanted.contains(path.as_path()) {
+            continue;
+        }
+        let Some(mut patch) = Patch::from_diff(&diff, i)? else {
+            continue;
+        };
+        let patch = String::from_utf8_lossy(&patch.to_buf()?).into_owned();
+        diffs.push(FileDiff { path, patch });
+    }
+    diffs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(diffs)
+}
+
+/// The path of the file a change is to, or was to before it was deleted.
+fn delta_path<'a>(delta: &DiffDelta<'a>) -> Option<&'a Path> {
+    delta.new_file().path().or_else(|| delta.old_file().path())
+}
+
+/// The files of a project as they are at a revision, read from git rather than the working
+/// tree.
+pub struct RevisionFiles {
+    // Repositories cannot be shared between threads, but files are read in parallel
+    repository: Mutex<Repository>,
+    /// The time of the revision's commit, standing in for the files' modification times.
+    modified: SystemTime,
+    /// The blob and size of each file, by its path in the project.
+    blobs: HashMap<PathBuf, (Oid, u64)>,
+}
+
+impl RevisionFiles {
+    /// Looks up `files`, paths under `root`, in the tree of `revision`. Files that are not in
+    /// it are left out.
+    pub fn open(root: &Path, revision: &str, files: &[PathBuf]) -> Result<Self, git2::Error> {
+        let project = ProjectRepository::open(root)?;
+        let (modified, blobs) = {
+            let object = project.repository.revparse_single(revision)?;
+            let tree = object.peel_to_tree()?;
+            let modified = match object.peel_to_commit() {
+                Ok(commit) => {
+                    UNIX_EPOCH + Duration::from_secs(commit.time().seconds().max(0) as u64)
+                }
+                Err(_) => UNIX_EPOCH,
+            };
+            let odb = project.repository.odb()?;
+            let mut blobs = HashMap::new();
+            for file in files {
+                let relative = file.strip_prefix(root).unwrap_or(file);
+                let Ok(entry) = tree.get_path(&project.prefix.join(relative)) else {
+                    continue;
+                };
+                if entry.kind() != Some(ObjectType::Blob) {
+                    continue;
+                }
+                let (size, _) = odb.read_header(entry.id())?;
+                blobs.insert(file.clone(), (entry.id(), size as u64));
+            }
+            (modified, blobs)
+        };
+        Ok(RevisionFiles {
+            repository: Mutex::new(project.repository),
+            modified,
+            blobs,
+        })
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.blobs.contains_key(path)
+    }
+
+    /// The size of a file at the revision, if it is there.
+    pub fn size(&self, path: &Path) -> Option<u64> {
+        self.blobs.get(path).map(|(_, size)| *size)
+    }
+
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+
+    /// Reads a file's content at the revision.
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let (id, _) = self
+            .blobs
+            .get(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not in the revision"))?;
+        let repository = self.repository.lock().unwrap();
+        let blob = repository.find_blob(*id).map_err(io::Error::other)?;
+        Ok(blob.content().to_vec())
+    }
+}
+
+/// The repository containing a project, and where the project is in it.
+struct ProjectRepository {
+    repository: Repository,
+    prefix: PathBuf, // The project root, relative to the working tree
+}
+
+impl ProjectRepository {
+    fn open(root: &Path) -> Result<Self, git2::Error> {
+        let repository = Repository::discover(root)?;
+        let workdir = repository
+            .workdir()
+            .ok_or_else(|| git2::Error::from_str("the repository has no working tree"))?;
+        let canonical = |path: &Path| {
+            path.canonicalize()
+                .map_err(|e| git2::Error::from_str(&format!("{}: {}", path.display(), e)))
+        };
+        let prefix = canonical(root)?
+            .strip_prefix(canonical(workdir)?)
+            .map(Path::to_path_buf)
+            .map_err(|_| git2::Error::from_str("the project is outside the working tree"))?;
+        Ok(ProjectRepository { repository, prefix })
+    }
+
+    /// Turns a path relative to the working tree into one relative to the project root, if it is
+    /// in the project.
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        path.strip_prefix(&self.prefix).ok().map(Path::to_path_buf)
+    }
+
+    /// Compares the working tree, index or revisions as `changes` asks, within the project.
+    fn diff(&self, changes: &GitChanges, context_lines: u32) -> Result<Diff<'_>, git2::Error> {
+        let mut options = DiffOptions::new();
+        options.context_lines(context_lines);
+        if !self.prefix.as_os_str().is_empty() {
+            options.pathspec(&self.prefix);
+        }
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        let repository = &self.repository;
+        match changes {
+            GitChanges::Since(reference) => {
+                let tree = repository.revparse_single(reference)?.peel_to_tree()?;
+                repository.diff_tree_to_workdir_with_index(Some(&tree), Some(&mut options))
+            }
+            GitChanges::Staged => {
+                repository.diff_tree_to_index(self.head()?.as_ref(), None, Some(&mut options))
+            }
+            GitChanges::Uncommitted => repository
+                .diff_tree_to_workdir_with_index(self.head()?.as_ref(), Some(&mut options)),
+            GitChanges::Between { from, to } => {
+                let from = repository.revparse_single(from)?.peel_to_tree()?;
+                let to = repository.revparse_single(to)?.peel_to_tree()?;
+                repository.diff_tree_to_tree(Some(&from), Some(&to), Some(&mut options))
+            }
+        }
+    }
+
+    /// The tree of the commit checked out, or `None` before the first commit.
+    fn head(&self) -> Result<Option<Tree<'_>>, git2::Error> {
+        match self.repository.head() {
+            Ok(head) => head.peel_to_tree().map(Some),
+            Err(e) if e.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
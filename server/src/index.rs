@@ -0,0 +1,303 @@
+use crate::config::Config;
+use crate::contexter::{gather_files, gather_within, ContexterError, GatherOptions};
+use log::{error, info, warn};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long the watcher waits for a burst of changes, such as a checkout or a build, to settle
+/// before updating the indexes.
+const SETTLE_TIME: Duration = Duration::from_millis(50);
+
+/// The longest the watcher holds on to changes while they keep coming.
+const MAX_DELAY: Duration = Duration::from_secs(1);
+
+/// Beyond this many changed paths at once, walking the whole project again is cheaper.
+const MAX_CHANGES: usize = 1_000;
+
+/// Files whose rules decide what is gathered from the directory they are in.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// The files gathered from a project, kept so that listing them does not mean walking the disk.
+#[derive(Debug)]
+pub struct ProjectIndex {
+    root: PathBuf,
+    watched: PathBuf, // The root with links resolved, as the watcher reports paths under it
+    options: GatherOptions,
+    files: RwLock<Option<BTreeSet<PathBuf>>>, // None until the project has been walked
+}
+
+/// A change to apply to an index.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Change {
+    /// Nothing is left at the path, or below it.
+    Removed(PathBuf),
+    /// The file or directory at the path has to be gathered again, recursively for a directory.
+    Rescan { path: PathBuf, recursive: bool },
+}
+
+impl ProjectIndex {
+    /// Creates an index of the project at `root`, gathering with `options`. It is empty until
+    /// `rebuild` is called.
+    pub fn new(root: PathBuf, options: GatherOptions) -> Self {
+        let watched = fs::canonicalize(&root).unwrap_or_else(|_| root.clone());
+        ProjectIndex {
+            root,
+            watched,
+            options,
+            files: RwLock::new(None),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns `path` under the project's root as it is configured, or `None` if it is not in
+    /// the project. The watcher reports paths with links resolved, so a project configured
+    /// through a symlink would otherwise never see its changes.
+    pub fn in_project(&self, path: &Path) -> Option<PathBuf> {
+        if path.starts_with(&self.root) {
+            return Some(path.to_path_buf());
+        }
+        path.strip_prefix(&self.watched)
+            .ok()
+            .map(|relative| self.root.join(relative))
+    }
+
+    /// Returns the indexed files, sorted, or `None` if the project has not been indexed yet.
+    pub fn files(&self) -> Option<Vec<PathBuf>> {
+        let files = self.files.read().unwrap();
+        files.as_ref().map(|files| files.iter().cloned().collect())
+    }
+
+    /// Walks the whole project, replacing the index.
+    pub fn rebuild(&self) -> Result<(), ContexterError> {
+        match gather_files(&self.root, &[], &self.options) {
+            Ok(files) => {
+                *self.files.write().unwrap() = Some(files.into_iter().collect());
+                Ok(())
+            }
+            Err(e) => {
+                *self.files.write().unwrap() = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Brings the index up to date with changes to the files or directories at `changed`,
+    /// gathering only what is at or below them. If the project has not been indexed yet, or a
+    /// great many paths changed, the whole project is walked instead.
+    pub fn update(&self, changed: &[PathBuf]) -> Result<(), ContexterError> {
+        if changed.len() > MAX_CHANGES || self.files.read().unwrap().is_none() {
+            return self.rebuild();
+        }
+
+        // Gather outside the lock, so that the index can still be read meanwhile
+        let changes = self.changes(changed);
+        let mut found = Vec::new();
+        for change in &changes {
+            if let Change::Rescan { path, recursive } = change {
+                match gather_within(&self.root, path, *recursive, &self.options) {
+                    Ok(gathered) => found.extend(gathered.files),
+                    Err(e) => {
+                        // Better to walk the disk than to answer from an index that is wrong
+                        *self.files.write().unwrap() = None;
+                        return Err(e);
+                    }
+                }
+            }
+        }
+
+        let mut files = self.files.write().unwrap();
+        let Some(files) = files.as_mut() else {
+            return Ok(());
+        };
+        for change in &changes {
+            match change {
+                Change::Removed(path)
+                | Change::Rescan {
+                    path,
+                    recursive: true,
+                } => remove_below(files, path),
+                Change::Rescan { path, .. } => {
+                    files.remove(path);
+                }
+            }
+        }
+        files.extend(found);
+        Ok(())
+    }
+
+    /// Works out what has to be gathered again for changes at `changed`, leaving out paths that
+    /// are covered by a directory being gathered again as a whole.
+    fn changes(&self, changed: &[PathBuf]) -> Vec<Change> {
+        let mut changes: Vec<Change> = changed
+            .iter()
+            .filter_map(|path| self.in_project(path))
+            .filter(|path| !is_in_git_dir(path))
+            .map(|path| {
+                let is_ignore_file = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| IGNORE_FILES.contains(&name));
+                match (is_ignore_file, fs::metadata(&path)) {
+                    (true, _) => Change::Rescan {
+                        path: path.parent().unwrap_or(&self.root).to_path_buf(),
+                        recursive: true,
+                    },
+                    (false, Ok(metadata)) => Change::Rescan {
+                        recursive: metadata.is_dir(),
+                        path,
+                    },
+                    (false, Err(_)) => Change::Removed(path),
+                }
+            })
+            .collect();
+        changes.sort();
+        changes.dedup();
+
+        let directories: Vec<PathBuf> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Rescan {
+                    path,
+                    recursive: true,
+                } => Some(path.clone()),
+                _ => None,
+            })
+            .collect();
+        changes.retain(|change| {
+            let path = match change {
+                Change::Removed(path) | Change::Rescan { path, .. } => path,
+            };
+            !directories
+                .iter()
+                .any(|dir| path != dir && path.starts_with(dir))
+        });
+        changes
+    }
+}
+
+/// Removes `path` and everything below it from the set.
+fn remove_below(files: &mut BTreeSet<PathBuf>, path: &Path) {
+    // Paths sort component by component, so everything below a path comes right after it
+    let doomed: Vec<PathBuf> = files
+        .range(path.to_path_buf()..)
+        .take_while(|file| file.starts_with(path))
+        .cloned()
+        .collect();
+    for file in doomed {
+        files.remove(&file);
+    }
+}
+
+/// Git rewrites its own files all the time, and they are never gathered.
+fn is_in_git_dir(path: &Path) -> bool {
+    path.components()
+        .any(|component| component == Component::Normal(".git".as_ref()))
+}
+
+/// Indexes of every registered project, kept up to date by watching their directories.
+pub struct ProjectIndexes {
+    indexes: HashMap<String, Arc<ProjectIndex>>,
+    _watcher: RecommendedWatcher, // Dropping it stops the updates
+}
+
+impl ProjectIndexes {
+    /// Starts watching the directories of the projects in `config`, indexing them in the
+    /// background. Projects that cannot be watched are left out, and listed from disk instead.
+    pub fn watch(config: &Config) -> notify::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+        let mut indexes = HashMap::new();
+        for (name, project) in &config.projects {
+            if let Err(e) = watcher.watch(&project.path, RecursiveMode::Recursive) {
+                warn!("Not watching project {}: {}", name, e);
+                continue;
+            }
+            let index = ProjectIndex::new(project.path.clone(), config.gather_options(project));
+            indexes.insert(name.clone(), Arc::new(index));
+        }
+
+        let watched: Vec<(String, Arc<ProjectIndex>)> = indexes
+            .iter()
+            .map(|(name, index)| (name.clone(), Arc::clone(index)))
+            .collect();
+        thread::spawn(move || keep_up_to_date(watched, rx));
+        Ok(ProjectIndexes {
+            indexes,
+            _watcher: watcher,
+        })
+    }
+
+    /// Returns the index of a project, if it is being watched.
+    pub fn get(&self, project: &str) -> Option<&ProjectIndex> {
+        self.indexes.get(project).map(|index| index.as_ref())
+    }
+}
+
+/// Indexes the projects, then applies the changes reported by the watcher until it is dropped.
+fn keep_up_to_date(
+    indexes: Vec<(String, Arc<ProjectIndex>)>,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+) {
+    for (name, index) in &indexes {
+        match index.rebuild() {
+            Ok(()) => info!(
+                "Indexed {} files in project {}",
+                index.files().map_or(0, |files| files.len()),
+                name
+            ),
+            Err(e) => error!("Could not index project {}: {}", name, e),
+        }
+    }
+
+    while let Ok(first) = events.recv() {
+        let mut batch = vec![first];
+        let started = Instant::now();
+        while started.elapsed() < MAX_DELAY {
+            match events.recv_timeout(SETTLE_TIME) {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+
+        let mut changed = Vec::new();
+        let mut rescan = false;
+        for event in batch {
+            match event {
+                Ok(event) if event.need_rescan() => rescan = true,
+                // Reading files, which the server does all the time, changes nothing
+                Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+                Ok(event) => changed.extend(event.paths),
+                Err(e) => {
+                    warn!("Error while watching projects: {}", e);
+                    rescan = true;
+                }
+            }
+        }
+
+        for (name, index) in &indexes {
+            let result = if rescan {
+                index.rebuild()
+            } else {
+                let mine: Vec<PathBuf> = changed
+                    .iter()
+                    .filter_map(|path| index.in_project(path))
+                    .collect();
+                if mine.is_empty() {
+                    continue;
+                }
+                index.update(&mine)
+            };
+            if let Err(e) = result {
+                error!("Could not update the index of project {}: {}", name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::path::Path;
+
+/// Detects the language of a file from its name, returning the identifier commonly used to tag
+/// Markdown code fences (e.g. `rust`, `python`, `typescript`).
+pub fn detect_language(path: &Path) -> Option<&'static str> {
+    let file_name = path.file_name()?.to_str()?;
+
+    // Well-known files without a telling extension
+    match file_name {
+        "Dockerfile" => return Some("dockerfile"),
+        "Makefile" | "makefile" | "GNUmakefile" => return Some("makefile"),
+        "CMakeLists.txt" => return Some("cmake"),
+        _ => {}
+    }
+
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let language = match ext.as_str() {
+        "rs" => "rust",
+        "py" | "pyi" => "python",
+        "ts" | "mts" | "cts" => "typescript",
+        "tsx" => "tsx",
+        "js" | "mjs" | "cjs" => "javascript",
+        "jsx" => "jsx",
+        "go" => "go",
+        "java" => "java",
+        "kt" | "kts" => "kotlin",
+        "scala" => "scala",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "cxx" | "hh" | "hpp" | "hxx" => "cpp",
+        "cs" => "csharp",
+        "swift" => "swift",
+        "rb" => "ruby",
+        "php" => "php",
+        "sh" | "bash" | "zsh" => "bash",
+        "ps1" => "powershell",
+        "sql" => "sql",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "vue" => "vue",
+        "toml" => "toml",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "xml" => "xml",
+        "ini" | "cfg" => "ini",
+        "gradle" => "groovy",
+        "proto" => "protobuf",
+        "md" | "markdown" => "markdown",
+        "rst" => "rst",
+        "txt" => "text",
+        _ => return None,
+    };
+    Some(language)
+}
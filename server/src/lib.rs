@@ -1,9 +1,25 @@
+pub mod cache;
+pub mod categories;
 pub mod cli;
 pub mod config;
 pub mod contexter;
+pub mod digest;
+pub mod encoding;
+pub mod formatter;
+pub mod git;
+pub mod index;
+pub mod language;
+pub mod listing;
+pub mod project;
+pub mod redact;
 pub mod server;
+pub mod skeleton;
+pub mod tokenizer;
+pub mod transform;
+pub mod tree;
 pub mod utils;
 
 // These modules are not public, but their contents are used internally
 mod cli_handlers;
+mod pipeline;
 pub mod server_handlers; // Make this module public
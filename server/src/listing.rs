@@ -0,0 +1,138 @@
+use crate::cache::ContentCache;
+use crate::categories::CategoryRules;
+use crate::contexter::{
+    gather_files_with_report, gather_ignored, read_text_file, ContexterError, GatherOptions,
+    SkipReason, SkippedFile,
+};
+use crate::digest::ContentDigest;
+use crate::language::detect_language;
+use crate::pipeline::map_in_order;
+use crate::tokenizer::TokenizerKind;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Whether a file found in a project makes it into its context.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    /// Gathered, and included unless it turns out to be a duplicate or over the token budget.
+    Included,
+    /// Found, but left out for the entry's `reason`.
+    Skipped,
+    /// Left out by a `.gitignore` or `.ignore` file.
+    Ignored,
+}
+
+/// A file or directory of a project, with what is known about it.
+///
+/// Sizes, times, languages and sections are given wherever they can be worked out; lines, token
+/// estimates and digests only for included files, whose content is read.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub path: String, // Relative to the project root
+    pub status: EntryStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<SkipReason>, // Why a skipped entry was skipped
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub directory: bool, // Set for directories skipped or ignored as a whole
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>, // RFC 3339
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub section: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lines: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>, // Estimated, at about four characters per token
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<ContentDigest>,
+}
+
+impl FileEntry {
+    fn new(root: &Path, path: &Path, status: EntryStatus, section: Option<String>) -> Self {
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        let metadata = fs::metadata(path).ok();
+        FileEntry {
+            path: relative_path.to_string_lossy().into_owned(),
+            status,
+            reason: None,
+            directory: metadata.as_ref().is_some_and(|metadata| metadata.is_dir()),
+            size: metadata
+                .as_ref()
+                .filter(|metadata| metadata.is_file())
+                .map(|metadata| metadata.len()),
+            modified: metadata
+                .and_then(|metadata| metadata.modified().ok())
+                .map(|modified| humantime::format_rfc3339_seconds(modified).to_string()),
+            language: detect_language(path).map(String::from),
+            section,
+            lines: None,
+            tokens: None,
+            sha256: None,
+        }
+    }
+}
+
+/// Lists every file of a project: the files gathered with `options`, each with its line count,
+/// token estimate and digest, along with the files skipped and the entries ignored, sorted by
+/// path. Included files are read through `cache`, if given.
+pub fn list_project(
+    root: &Path,
+    options: &GatherOptions,
+    categories: &CategoryRules,
+    cache: Option<&ContentCache>,
+) -> Result<Vec<FileEntry>, ContexterError> {
+    let categories = categories.compile()?;
+    let sections = categories.section_names();
+    let section_of = |path: &Path| {
+        let relative_path = path.strip_prefix(root).unwrap_or(path);
+        sections[categories.categorize(relative_path)].clone()
+    };
+
+    let gathered = gather_files_with_report(root, &[], options)?;
+    let tokenizer = TokenizerKind::Estimate.build();
+    let mut entries = Vec::new();
+    map_in_order(
+        gathered.files,
+        |path| {
+            let entry = FileEntry::new(root, &path, EntryStatus::Included, Some(section_of(&path)));
+            (entry, read_text_file(path, cache))
+        },
+        |(mut entry, read)| -> Result<(), ContexterError> {
+            match read {
+                Ok(file) => {
+                    entry.lines = Some(file.content.lines().count());
+                    entry.tokens = Some(tokenizer.count_tokens(&file.content));
+                    entry.sha256 = Some(file.digest);
+                }
+                // The file turned out not to be includable after all
+                Err(skipped) => {
+                    entry.status = EntryStatus::Skipped;
+                    entry.reason = Some(skipped.reason);
+                }
+            }
+            entries.push(entry);
+            Ok(())
+        },
+    )?;
+
+    for SkippedFile { path, reason, .. } in gathered.skipped {
+        let path = PathBuf::from(path);
+        let mut entry = FileEntry::new(root, &path, EntryStatus::Skipped, None);
+        if !entry.directory {
+            entry.section = Some(section_of(&path));
+        }
+        entry.reason = Some(reason);
+        entries.push(entry);
+    }
+    for path in gather_ignored(root, options)? {
+        entries.push(FileEntry::new(root, &path, EntryStatus::Ignored, None));
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
@@ -40,7 +40,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             run_server(config).await?;
         }
         _ => {
-            run_cli()?;
+            if let Err(e) = run_cli() {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
         }
     }
 
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+/// How many items may be in flight per worker thread, ahead of the next one due.
+const ITEMS_PER_WORKER: usize = 4;
+
+/// Applies `map` to every item on all available cores and hands the results to `sink` in the
+/// original order of the items.
+///
+/// Workers only run a bounded window of items ahead of the next result due, so no more than a
+/// few results per core are held at once however many items there are. An error from `sink`
+/// stops the pipeline and is returned.
+pub(crate) fn map_in_order<T, R, E, F, S>(items: Vec<T>, map: F, mut sink: S) -> Result<(), E>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> R + Sync,
+    S: FnMut(R) -> Result<(), E>,
+{
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(items.len());
+    if workers <= 1 {
+        return items.into_iter().try_for_each(|item| sink(map(item)));
+    }
+    let window = workers * ITEMS_PER_WORKER;
+
+    let (job_tx, job_rx) = mpsc::channel::<(usize, T)>();
+    let (result_tx, result_rx) = mpsc::channel::<(usize, R)>();
+    let job_rx = Mutex::new(job_rx);
+    thread::scope(|scope| {
+        // The job sender is moved in so that it is dropped on the way out, even after an error,
+        // which lets the workers finish before the scope waits for them
+        let job_tx = job_tx;
+        for _ in 0..workers {
+            let job_rx = &job_rx;
+            let result_tx = result_tx.clone();
+            let map = &map;
+            scope.spawn(move || loop {
+                // Hold the lock only while taking a job, not while working on it
+                let job = job_rx.lock().unwrap().recv();
+                let Ok((index, item)) = job else {
+                    break;
+                };
+                if result_tx.send((index, map(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(result_tx);
+
+        let total = items.len();
+        let mut items = items.into_iter().enumerate().peekable();
+        let mut ready = HashMap::new();
+        let mut next = 0;
+        while next < total {
+            while let Some((index, _)) = items.peek() {
+                if *index >= next + window {
+                    break;
+                }
+                let job = items.next().unwrap();
+                job_tx
+                    .send(job)
+                    .expect("workers run until the jobs are done");
+            }
+
+            let result = match ready.remove(&next) {
+                Some(result) => result,
+                None => {
+                    let (index, result) = result_rx
+                        .recv()
+                        .expect("workers run until the jobs are done");
+                    ready.insert(index, result);
+                    continue;
+                }
+            };
+            sink(result)?;
+            next += 1;
+        }
+        Ok(())
+    })
+}
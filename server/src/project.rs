@@ -0,0 +1,308 @@
+use crate::cache::ContentCache;
+use crate::config::Config;
+use crate::contexter::{
+    check_paths, concatenate_files_with_options, gather_files_with_report,
+    stream_files_with_options, BudgetStrategy, ContextChunk, ContextOptions, ContextOutput,
+    ContexterError, GatherOptions, GatheredFiles, IncludeFilter, OversizePolicy, SizeLimits,
+};
+use crate::formatter::{HeaderOptions, OutputFormat};
+use crate::git::GitChanges;
+use crate::tokenizer::TokenizerKind;
+use crate::transform::{Transforms, DEFAULT_LICENSE_PATTERN};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use thiserror::Error;
+
+/// What to gather from a registered project and how to assemble it.
+///
+/// This is the body of `POST /api/v1/projects/{name}` and also what a preset stores. Options
+/// left unset fall back to the project's profile and then to the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContexterRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<String>>, // Files, directories or globs relative to the project root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>, // Globs narrowing the selection, on top of the profile's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<TokenizerKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_strategy: Option<BudgetStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>, // Bytes, overriding the profile's limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_size: Option<u64>, // Bytes of all the files together
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oversized: Option<OversizePolicy>, // Skip or truncate files over a limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitChanges>, // Only gather the files changed in git
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_diff: Option<bool>, // Add the diffs of the changed files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_context: Option<u32>, // Lines of context around each change in the diffs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_only: Option<bool>, // Send the diffs without the files' content
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skeleton: Option<Vec<String>>, // Globs of files reduced to their item signatures
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus: Option<Vec<String>>, // Globs of files kept whole despite `skeleton`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<Transforms>, // Stripping comments, blank lines and the like
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omit_size: Option<bool>, // Leave sizes out of the file headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omit_modified: Option<bool>, // Leave modification times out of the file headers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree: Option<bool>, // Open the output with a tree of the files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_only: Option<bool>, // Send only the tree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tree_skipped: Option<bool>, // Draw the skipped files in the tree too
+}
+
+impl ContexterRequest {
+    /// Builds the assembly options requested by the client, falling back to defaults.
+    pub fn context_options(&self) -> ContextOptions {
+        ContextOptions {
+            max_tokens: self.max_tokens,
+            tokenizer: self.tokenizer.unwrap_or_default(),
+            budget_strategy: self.budget_strategy.unwrap_or_default(),
+            format: self.format.unwrap_or_default(),
+            header: HeaderOptions {
+                omit_size: self.omit_size.unwrap_or_default(),
+                omit_modified: self.omit_modified.unwrap_or_default(),
+            },
+            tree: self.tree.unwrap_or_default(),
+            tree_only: self.tree_only.unwrap_or_default(),
+            diffs: self.git.clone().filter(|_| self.git_diff == Some(true)),
+            revision: self
+                .git
+                .as_ref()
+                .and_then(GitChanges::revision)
+                .map(String::from),
+            diff_context: self.diff_context,
+            diffs_only: self.diff_only.unwrap_or_default(),
+            skeleton: self.skeleton.clone().unwrap_or_default(),
+            focus: self.focus.clone().unwrap_or_default(),
+            transforms: self.transforms.clone().unwrap_or_default(),
+            size_limits: self.size_limits(SizeLimits::default()),
+            ..Default::default()
+        }
+    }
+
+    /// Checks the patterns of the request against the project it is for, as running it would,
+    /// but without gathering anything: its paths and globs, the skeleton and focus globs, the
+    /// license pattern and the project's section rules.
+    pub fn validate(&self, config: &Config, project_name: &str) -> Result<(), ProjectError> {
+        let project = config
+            .projects
+            .get(project_name)
+            .ok_or_else(|| ProjectError::NotFound(project_name.to_string()))?;
+        let check = || -> Result<(), ContexterError> {
+            check_paths(&project.path, self.paths.as_deref().unwrap_or_default())?;
+            for globs in [&self.include, &self.skeleton, &self.focus]
+                .into_iter()
+                .flatten()
+            {
+                IncludeFilter::new(globs)?;
+            }
+            let transforms = config.transforms(self.transforms.clone().unwrap_or_default());
+            if let Err(e) = transforms.compile() {
+                let pattern = transforms.license_pattern.as_deref();
+                return Err(ContexterError::InvalidPattern {
+                    pattern: pattern.unwrap_or(DEFAULT_LICENSE_PATTERN).to_string(),
+                    message: e.to_string(),
+                });
+            }
+            config.category_rules(Some(project_name)).compile()?;
+            Ok(())
+        };
+        check().map_err(ProjectError::Gather)
+    }
+
+    /// The size limits requested by the client, falling back to `defaults`.
+    pub fn size_limits(&self, defaults: SizeLimits) -> SizeLimits {
+        SizeLimits {
+            max_file_size: self.max_file_size.or(defaults.max_file_size),
+            max_total_size: self.max_total_size.or(defaults.max_total_size),
+            oversized: self.oversized.unwrap_or(defaults.oversized),
+        }
+    }
+}
+
+/// What to compare in a project for review, and how to assemble the diffs.
+///
+/// This is the body of `POST /api/v1/projects/{name}/diff`. Without revisions the working tree
+/// is compared against `HEAD`; with `from` alone it is compared against `from`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>, // Revision to compare from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>, // Revision to compare to, instead of the working tree
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_lines: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub full_files: Option<bool>, // Also send the whole content of the changed files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<TokenizerKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub budget_strategy: Option<BudgetStrategy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<OutputFormat>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_file_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_total_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub oversized: Option<OversizePolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omit_size: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omit_modified: Option<bool>,
+}
+
+impl DiffRequest {
+    /// Turns the comparison into the equivalent request for the changed files and their diffs.
+    pub fn contexter_request(&self) -> Result<ContexterRequest, ContexterError> {
+        let changes = GitChanges::comparing(self.from.clone(), self.to.clone())?;
+        Ok(ContexterRequest {
+            paths: self.paths.clone(),
+            include: self.include.clone(),
+            max_tokens: self.max_tokens,
+            tokenizer: self.tokenizer,
+            budget_strategy: self.budget_strategy,
+            format: self.format,
+            max_file_size: self.max_file_size,
+            max_total_size: self.max_total_size,
+            oversized: self.oversized,
+            git: Some(changes),
+            git_diff: Some(true),
+            diff_context: self.context_lines,
+            diff_only: Some(self.full_files != Some(true)),
+            omit_size: self.omit_size,
+            omit_modified: self.omit_modified,
+            ..Default::default()
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProjectError {
+    #[error("Project '{0}' not found")]
+    NotFound(String),
+    #[error("Failed to gather files: {0}")]
+    Gather(#[source] ContexterError),
+    #[error("Failed to concatenate files: {0}")]
+    Concatenate(#[source] ContexterError),
+}
+
+/// Gathers and assembles the context of a registered project, applying its profile and then
+/// the request.
+pub fn run_project(
+    config: &Config,
+    project_name: &str,
+    request: &ContexterRequest,
+    cache: Option<Arc<ContentCache>>,
+) -> Result<ContextOutput, ProjectError> {
+    let (gathered, options) = prepare_project(config, project_name, request, cache)?;
+    concatenate_files_with_options(gathered.files, &options)
+        .map(|output| output.with_gather_report(gathered.skipped))
+        .map_err(ProjectError::Concatenate)
+}
+
+/// Like `run_project`, but hands the context to `sink` piece by piece as files are read.
+pub fn stream_project<F>(
+    config: &Config,
+    project_name: &str,
+    request: &ContexterRequest,
+    cache: Option<Arc<ContentCache>>,
+    sink: F,
+) -> Result<ContextOutput, ProjectError>
+where
+    F: FnMut(ContextChunk<'_>) -> io::Result<()>,
+{
+    let (gathered, options) = prepare_project(config, project_name, request, cache)?;
+    stream_files_with_options(gathered.files, &options, sink)
+        .map(|output| output.with_gather_report(gathered.skipped))
+        .map_err(ProjectError::Concatenate)
+}
+
+/// Gathers the files a request selects from a project and works out how to assemble them,
+/// reading through `cache` if given.
+pub fn prepare_project(
+    config: &Config,
+    project_name: &str,
+    request: &ContexterRequest,
+    cache: Option<Arc<ContentCache>>,
+) -> Result<(GatheredFiles, ContextOptions), ProjectError> {
+    let project = config
+        .projects
+        .get(project_name)
+        .ok_or_else(|| ProjectError::NotFound(project_name.to_string()))?;
+
+    let paths = request.paths.clone().unwrap_or_default();
+    if paths.is_empty() {
+        debug!("Running contexter on entire project: {}", project_name);
+    } else {
+        debug!(
+            "Running contexter on specific paths for project: {}",
+            project_name
+        );
+    }
+
+    // The request's limits override the profile's, which override the defaults
+    let size_limits = request.size_limits(config.size_limits(Some(project)));
+    let gather = GatherOptions {
+        git: request.git.clone(),
+        max_file_size: size_limits.skip_size(),
+        defer_content_check: true,
+        ..config.gather_options(project)
+    };
+    let mut gathered =
+        gather_files_with_report(&project.path, &paths, &gather).map_err(ProjectError::Gather)?;
+
+    // The request's include globs apply on top of the profile's: a file must match both
+    if let Some(include) = &request.include {
+        let include_filter = IncludeFilter::new(include).map_err(ProjectError::Gather)?;
+        gathered
+            .files
+            .retain(|file| include_filter.is_included(&project.path, file));
+        gathered
+            .skipped
+            .retain(|skipped| include_filter.is_included(&project.path, Path::new(&skipped.path)));
+        gathered
+            .removed
+            .retain(|path| include_filter.is_included(&project.path, path));
+    }
+
+    let options = ContextOptions {
+        format: request.format.or(project.format).unwrap_or_default(),
+        categories: config.category_rules(Some(project_name)),
+        root: Some(project.path.clone()),
+        cache,
+        tree_skipped: match request.tree_skipped {
+            Some(true) => gathered.skipped.clone(),
+            _ => Vec::new(),
+        },
+        removed: gathered.removed.clone(),
+        transforms: config.transforms(request.transforms.clone().unwrap_or_default()),
+        redaction: config.redaction.redaction(),
+        size_limits,
+        ..request.context_options()
+    };
+
+    Ok((gathered, options))
+}
@@ -0,0 +1,283 @@
+use crate::contexter::ContexterError;
+use crate::digest::ContentDigest;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// Gitignore-style globs for files and directories that are never gathered, whatever else is
+/// configured, as they hold little but secrets: environment files, private keys, keystores and
+/// credential stores. Examples of environment files are let through, redacted.
+pub const BLOCKED_FILES: &[&str] = &[
+    ".env",
+    ".env.*",
+    "!.env.example",
+    "!.env.sample",
+    "!.env.template",
+    "*.pem",
+    "*.key",
+    "*.p12",
+    "*.pfx",
+    "*.jks",
+    "*.keystore",
+    "*.kdbx",
+    "id_rsa",
+    "id_dsa",
+    "id_ecdsa",
+    "id_ed25519",
+    ".ssh/",
+    ".gnupg/",
+    ".aws/",
+    ".netrc",
+    ".pgpass",
+    ".htpasswd",
+    ".npmrc",
+    ".pypirc",
+];
+
+/// Strings shorter than this are never taken for random ones.
+const MIN_RANDOM_LENGTH: usize = 20;
+
+/// How close to the most a string of its length can have its entropy has to be for it to look
+/// random.
+const RANDOM_ENTROPY_RATIO: f64 = 0.85;
+
+/// A detector for secrets of a known shape: what to mark them with, and a regex finding them. If
+/// the regex has a capture group, only what the first one matches is replaced.
+struct Detector {
+    kind: String,
+    regex: Regex,
+}
+
+impl Detector {
+    fn new(kind: &str, pattern: &str) -> Self {
+        Detector {
+            kind: kind.to_string(),
+            regex: Regex::new(pattern).expect("built-in secret patterns are valid"),
+        }
+    }
+}
+
+static BUILT_IN: LazyLock<Vec<Detector>> = LazyLock::new(|| {
+    vec![
+        // A block cut short still gives the key away, so it is redacted to the end
+        Detector::new(
+            "private_key",
+            r"(?s)-----BEGIN [A-Z0-9 ]*PRIVATE KEY[A-Z ]*-----.*?(?:-----END [A-Z0-9 ]*PRIVATE KEY[A-Z ]*-----|\z)",
+        ),
+        // So does the end of one whose start was cut off, as in the tail of an excerpt or a diff
+        // hunk, taken back to the first line that could be part of the key
+        Detector::new(
+            "private_key",
+            r"(?m)(?:^[ +\-]?[A-Za-z0-9+/=]+\r?\n)+[ +\-]?-----END [A-Z0-9 ]*PRIVATE KEY[A-Z ]*-----",
+        ),
+        Detector::new(
+            "aws_key",
+            r"\b(?:AKIA|ASIA|AGPA|AIDA|AROA|ANPA|ANVA|AIPA)[0-9A-Z]{16}\b",
+        ),
+        Detector::new(
+            "aws_secret",
+            r#"(?i)aws_?secret_?(?:access_?)?key["']?\s*[:=]\s*["']?([A-Za-z0-9/+]{40})"#,
+        ),
+        Detector::new(
+            "github_token",
+            r"\b(?:gh[pousr]_[A-Za-z0-9]{36,}|github_pat_[A-Za-z0-9_]{22,})",
+        ),
+        Detector::new("gitlab_token", r"\bglpat-[A-Za-z0-9_\-]{20,}"),
+        Detector::new("slack_token", r"\bxox[abposr]-[A-Za-z0-9\-]{10,}"),
+        Detector::new("stripe_key", r"\b[sr]k_(?:live|test)_[A-Za-z0-9]{16,}"),
+        Detector::new("google_api_key", r"\bAIza[0-9A-Za-z_\-]{35}"),
+        Detector::new("anthropic_key", r"\bsk-ant-[A-Za-z0-9_\-]{20,}"),
+        Detector::new("openai_key", r"\bsk-(?:proj-)?[A-Za-z0-9_\-]{20,}"),
+        Detector::new(
+            "jwt",
+            r"\beyJ[A-Za-z0-9_\-]{10,}\.eyJ[A-Za-z0-9_\-]{10,}\.[A-Za-z0-9_\-]{10,}",
+        ),
+        Detector::new(
+            "url_password",
+            r"\b[a-zA-Z][a-zA-Z0-9+.\-]*://[^/\s:@]+:([^/\s@]+)@",
+        ),
+    ]
+});
+
+/// The value of an assignment in an environment file. Lines of a diff of one are covered too.
+static ENV_ASSIGNMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?m)^[+\- ]?[ \t]*(?:export[ \t]+)?[A-Za-z_][A-Za-z0-9_.]*[ \t]*=[ \t]*(\S[^\r\n]*?)[ \t]*\r?$")
+        .unwrap()
+});
+
+/// A run of characters that a key, a token or base64 may be made of.
+static TOKEN: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[A-Za-z0-9+/=_\-]{20,}").unwrap());
+
+/// A custom secret detector: a regex, and the name its matches are marked with. If the regex
+/// has a capture group, only what the first one matches is replaced, so that e.g. the name of a
+/// setting can be kept.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SecretPattern {
+    pub name: String,
+    pub pattern: String,
+}
+
+/// What is redacted from files before they are placed in the output, on top of the secrets of
+/// a known shape, such as private keys, API keys and tokens, and the values in environment
+/// files, which always are.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Redaction {
+    /// Also redact long runs of mixed-case letters and digits that look random, which catches
+    /// secrets of no known shape at the cost of the odd false alarm.
+    pub high_entropy: bool,
+    /// Custom detectors, tried after the built-in ones.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patterns: Vec<SecretPattern>,
+}
+
+impl Redaction {
+    /// Sums up the settings, so that what was worked out with them can be told apart from what
+    /// was worked out with others. It is the SHA-256 of the settings serialized, which stays the
+    /// same across builds, so costs saved with the cache keep matching them.
+    pub fn fingerprint(&self) -> ContentDigest {
+        let settings = serde_json::to_vec(self).expect("redaction settings always serialize");
+        ContentDigest::of(&settings)
+    }
+
+    /// Compiles the detectors, failing if a custom pattern is not a valid regex.
+    pub fn compile(&self) -> Result<Redactor, ContexterError> {
+        let custom = self
+            .patterns
+            .iter()
+            .map(|pattern| {
+                Ok(Detector {
+                    kind: pattern.name.clone(),
+                    regex: Regex::new(&pattern.pattern).map_err(|e| {
+                        ContexterError::InvalidPattern {
+                            pattern: pattern.pattern.clone(),
+                            message: e.to_string(),
+                        }
+                    })?,
+                })
+            })
+            .collect::<Result<_, ContexterError>>()?;
+        Ok(Redactor {
+            custom,
+            high_entropy: self.high_entropy,
+        })
+    }
+}
+
+/// Compiled detectors, ready to redact files.
+pub struct Redactor {
+    custom: Vec<Detector>,
+    high_entropy: bool,
+}
+
+/// Content with its secrets replaced by markers such as `[REDACTED:aws_key]`, and how many of
+/// each kind were replaced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redacted {
+    pub content: String,
+    pub redactions: BTreeMap<String, usize>,
+}
+
+impl Redactor {
+    /// Replaces the secrets in the content of the file at `path`, or in a diff of it, with
+    /// markers naming their kind. Returns `None` if no secret was found.
+    ///
+    /// Where detectors overlap, the first to find a secret wins: the built-in ones, then the
+    /// custom ones, then the values in environment files and finally random-looking strings.
+    pub fn redact(&self, path: &Path, content: &str) -> Option<Redacted> {
+        let mut found: Vec<(Range<usize>, &str)> = Vec::new();
+        let mut claim = |range: Range<usize>, kind| {
+            if !range.is_empty()
+                && !found
+                    .iter()
+                    .any(|(claimed, _)| claimed.start < range.end && range.start < claimed.end)
+            {
+                found.push((range, kind));
+            }
+        };
+
+        for detector in BUILT_IN.iter().chain(&self.custom) {
+            for captures in detector.regex.captures_iter(content) {
+                let secret = captures.get(1).or_else(|| captures.get(0));
+                if let Some(secret) = secret {
+                    claim(secret.range(), detector.kind.as_str());
+                }
+            }
+        }
+        if is_env_file(path) {
+            for captures in ENV_ASSIGNMENT.captures_iter(content) {
+                if let Some(value) = captures.get(1) {
+                    claim(value.range(), "env_value");
+                }
+            }
+        }
+        if self.high_entropy {
+            for token in TOKEN.find_iter(content) {
+                if looks_random(token.as_str()) {
+                    claim(token.range(), "high_entropy");
+                }
+            }
+        }
+        if found.is_empty() {
+            return None;
+        }
+
+        found.sort_by_key(|(range, _)| range.start);
+        let mut redacted = Redacted {
+            content: String::with_capacity(content.len()),
+            redactions: BTreeMap::new(),
+        };
+        let mut position = 0;
+        for (range, kind) in found {
+            redacted.content.push_str(&content[position..range.start]);
+            redacted.content.push_str(&format!("[REDACTED:{}]", kind));
+            *redacted.redactions.entry(kind.to_string()).or_default() += 1;
+            position = range.end;
+        }
+        redacted.content.push_str(&content[position..]);
+        Some(redacted)
+    }
+}
+
+/// Checks if a file holds environment variables, like `.env.example` or `docker.env`.
+fn is_env_file(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    name == ".env"
+        || name.starts_with(".env.")
+        || path.extension().and_then(|e| e.to_str()) == Some("env")
+}
+
+/// Checks if a string has capitals, small letters and digits, and its characters are nearly as
+/// varied as they can be for its length, as in keys and tokens but not in words and names.
+fn looks_random(token: &str) -> bool {
+    let bytes = token.as_bytes();
+    if bytes.len() < MIN_RANDOM_LENGTH
+        || !bytes.iter().any(u8::is_ascii_uppercase)
+        || !bytes.iter().any(u8::is_ascii_lowercase)
+        || !bytes.iter().any(u8::is_ascii_digit)
+    {
+        return false;
+    }
+    let mut counts = [0usize; 256];
+    for &byte in bytes {
+        counts[byte as usize] += 1;
+    }
+    let length = bytes.len() as f64;
+    let entropy: f64 = counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / length;
+            -p * p.log2()
+        })
+        .sum();
+    // A string can have no more distinct characters than its length, nor than base64 has
+    let most = length.min(64.0).log2();
+    entropy >= RANDOM_ENTROPY_RATIO * most
+}
@@ -1,12 +1,37 @@
+use crate::cache::ContentCache;
 use crate::config::Config;
+use crate::contexter::{
+    DecodedFile, DroppedFile, ExcerptedFile, RedactedFile, SkippedFile, TransformedFile,
+};
+use crate::digest::ContentDigest;
+use crate::index::ProjectIndexes;
+use crate::listing::FileEntry;
+use crate::project::ContexterRequest;
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 pub struct AppState {
     pub config: Arc<RwLock<Config>>,
+    pub config_path: Option<PathBuf>, // Where changes made through the API are saved, if anywhere
+    pub cache: Option<Arc<ContentCache>>, // Shared by all requests, unless caching is disabled
+    pub indexes: Option<ProjectIndexes>, // Lists of the projects' files, kept up to date
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        AppState {
+            cache: config.cache.build(),
+            config: Arc::new(RwLock::new(config)),
+            config_path: None,
+            indexes: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -25,16 +50,120 @@ pub struct ProjectMetadata {
     pub name: String,
     pub path: String,
     pub files: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entries: Option<Vec<FileEntry>>, // Every file with its details, if asked for
+}
+
+/// Query parameters of `GET /api/v1/projects/{name}`.
+#[derive(Deserialize, Debug, Default)]
+pub struct MetadataQuery {
+    #[serde(default)]
+    pub details: bool,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProjectContentResponse {
     pub content: String,
+    #[serde(default)]
+    pub tokens: usize,
+    #[serde(default)]
+    pub files: Vec<String>, // Files included in the content, in output order
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub digests: BTreeMap<String, ContentDigest>, // SHA-256 of each included file, by path
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dropped: Vec<DroppedFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub truncated: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skeletonized: Vec<String>, // Files included as their skeleton
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excerpted: Vec<ExcerptedFile>, // Oversized files included as their start and end
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transformed: Vec<TransformedFile>, // Files changed by the transforms, with token counts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redacted: Vec<RedactedFile>, // Files and diffs with secrets redacted, by kind
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub decoded: Vec<DecodedFile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<SkippedFile>,
 }
 
-#[derive(Serialize)]
+/// One line of the NDJSON stream returned by `POST /api/v1/projects/{name}/stream`: the project
+/// structure if asked for, a record per file as it is read, then a summary, or an error if the
+/// stream had to stop.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StreamRecord {
+    /// The tree of the project's files, sent first when asked for.
+    Structure {
+        tree: String,
+    },
+    File {
+        path: String,
+        section: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        size: Option<u64>, // Unless omitted from the headers
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        modified: Option<String>,
+        sha256: ContentDigest,
+        content: String,
+        truncated: bool,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        skeleton: bool, // The content is the file's skeleton
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        excerpt: bool, // The content is the start and end of an oversized file
+    },
+    /// A file left out because its content is identical to a file already sent.
+    Duplicate {
+        path: String,
+        section: String,
+        identical_to: String,
+    },
+    /// The diff of a changed file, sent after the files when asked for.
+    Diff {
+        path: String,
+        patch: String,
+    },
+    Summary {
+        files: usize,
+        tokens: usize,
+        dropped: Vec<DroppedFile>,
+        truncated: Vec<String>,
+        #[serde(default)]
+        decoded: Vec<DecodedFile>,
+        #[serde(default)]
+        skipped: Vec<SkippedFile>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        transformed: Vec<TransformedFile>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        redacted: Vec<RedactedFile>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        excerpted: Vec<ExcerptedFile>,
+    },
+    Error {
+        error: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PresetListResponse {
+    pub presets: HashMap<String, ContexterRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ErrorResponse {
     pub error: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>, // The pattern at fault, for invalid pattern errors
+}
+
+impl ErrorResponse {
+    pub fn new(error: impl Into<String>) -> Self {
+        ErrorResponse {
+            error: error.into(),
+            pattern: None,
+        }
+    }
 }
 
 pub fn config_routes(cfg: &mut web::ServiceConfig) {
@@ -51,14 +180,68 @@ pub fn config_routes(cfg: &mut web::ServiceConfig) {
             .route(
                 "/projects/{name}",
                 web::post().to(crate::server_handlers::run_contexter),
+            )
+            .route(
+                "/projects/{name}/stream",
+                web::post().to(crate::server_handlers::stream_contexter),
+            )
+            .route(
+                "/projects/{name}/diff",
+                web::post().to(crate::server_handlers::diff_contexter),
+            )
+            .route(
+                "/projects/{name}/presets",
+                web::get().to(crate::server_handlers::list_presets),
+            )
+            .route(
+                "/projects/{name}/presets/{preset}",
+                web::get().to(crate::server_handlers::get_preset),
+            )
+            .route(
+                "/projects/{name}/presets/{preset}",
+                web::put().to(crate::server_handlers::put_preset),
+            )
+            .route(
+                "/projects/{name}/presets/{preset}",
+                web::delete().to(crate::server_handlers::delete_preset),
+            )
+            .route(
+                "/projects/{name}/presets/{preset}/run",
+                web::post().to(crate::server_handlers::run_preset),
+            )
+            .route(
+                "/cache/stats",
+                web::get().to(crate::server_handlers::cache_stats),
             ),
     );
 }
 
 pub async fn run_server(config: Config) -> std::io::Result<()> {
+    let cache_path = config.cache.path.clone();
+    let cache = match config.cache.load() {
+        Ok(cache) => cache,
+        Err(e) => {
+            warn!("Could not load the saved cache, starting empty: {}", e);
+            config.cache.build()
+        }
+    };
+    let indexes = match ProjectIndexes::watch(&config) {
+        Ok(indexes) => Some(indexes),
+        Err(e) => {
+            warn!(
+                "Could not watch the projects, their files will be listed from disk: {}",
+                e
+            );
+            None
+        }
+    };
     let app_state = web::Data::new(AppState {
-        config: Arc::new(RwLock::new(config)),
+        config_path: Config::get_config_path().ok(),
+        cache,
+        indexes,
+        ..AppState::new(config)
     });
+    let saved_state = app_state.clone();
 
     let listen_address = app_state.config.read().await.listen_address.clone();
     let port = app_state.config.read().await.port;
@@ -73,5 +256,13 @@ pub async fn run_server(config: Config) -> std::io::Result<()> {
     })
     .bind((listen_address, port))?
     .run()
-    .await
+    .await?;
+
+    if let (Some(cache), Some(path)) = (&saved_state.cache, cache_path) {
+        info!("Saving the cache to {}", path.display());
+        if let Err(e) = cache.save(&path) {
+            error!("Could not save the cache: {}", e);
+        }
+    }
+    Ok(())
 }
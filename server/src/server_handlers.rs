@@ -1,32 +1,40 @@
-use crate::contexter::{concatenate_files, gather_relevant_files};
+use crate::cache::ContentCache;
+use crate::config::Config;
+use crate::contexter::{
+    gather_files, stream_files_with_options, ContextChunk, ContextOutput, ContexterError,
+};
+use crate::listing::{list_project, EntryStatus};
+use crate::project::{prepare_project, run_project, DiffRequest, ProjectError};
 use crate::server::{
-    AppState, ErrorResponse, ProjectContentResponse, ProjectListResponse, ProjectMetadata,
-    ProjectSummary,
+    AppState, ErrorResponse, MetadataQuery, PresetListResponse, ProjectContentResponse,
+    ProjectListResponse, ProjectMetadata, ProjectSummary, StreamRecord,
 };
 use crate::utils::validate_api_key;
+use actix_web::http::header;
+use actix_web::web::Bytes;
 use actix_web::{web, HttpRequest, HttpResponse, Responder};
 use log::{debug, error, info, warn};
-use serde::Deserialize;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
 
-#[derive(Deserialize)]
-pub struct ContexterRequest {
-    pub paths: Option<Vec<String>>,
-}
+/// Number of chunks a streaming response may read ahead of the client.
+const STREAM_BUFFER: usize = 16;
+
+pub use crate::project::ContexterRequest;
 
 pub async fn list_projects(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
     let config = data.config.read().await;
     if !validate_api_key(&req, &config).await {
-        return HttpResponse::Unauthorized().json(ErrorResponse {
-            error: "Invalid or missing API key".to_string(),
-        });
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
     }
 
     let projects: Vec<ProjectSummary> = config
         .projects
         .iter()
-        .map(|(name, path)| ProjectSummary {
+        .map(|(name, project)| ProjectSummary {
             name: name.clone(),
-            path: path.to_string_lossy().into_owned(),
+            path: project.path.to_string_lossy().into_owned(),
         })
         .collect();
 
@@ -38,55 +46,96 @@ pub async fn list_projects(req: HttpRequest, data: web::Data<AppState>) -> impl
 pub async fn get_project_metadata(
     req: HttpRequest,
     project_name: web::Path<String>,
+    query: web::Query<MetadataQuery>,
     data: web::Data<AppState>,
 ) -> impl Responder {
     let config = data.config.read().await;
     if !validate_api_key(&req, &config).await {
-        return HttpResponse::Unauthorized().json(ErrorResponse {
-            error: "Invalid or missing API key".to_string(),
-        });
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
     }
 
     let project_name = project_name.into_inner();
+    let Some(project) = config.projects.get(&project_name) else {
+        return project_not_found(&project_name);
+    };
 
-    if let Some(project_path) = config.projects.get(&project_name) {
-        debug!("Gathering metadata for project: {}", project_name);
-        match gather_relevant_files(project_path.to_str().unwrap(), vec![], vec![]) {
-            Ok(files) => {
-                let file_paths: Vec<String> = files
+    // What the listing needs is taken from the config so that the lock is not held while the
+    // project is read, which happens on a blocking thread
+    let project_path = project.path.clone();
+    let gather = config.gather_options(project);
+    let categories = config.category_rules(Some(&project_name));
+    drop(config);
+    let details = query.details;
+    let indexed = if details {
+        None
+    } else {
+        data.indexes
+            .as_ref()
+            .and_then(|indexes| indexes.get(&project_name))
+            .and_then(|index| index.files())
+    };
+    let cache = data.cache.clone();
+
+    let name = project_name.clone();
+    let root = project_path.clone();
+    let listing = web::block(move || {
+        if details {
+            debug!("Listing the files of project: {}", name);
+            return list_project(&root, &gather, &categories, cache.as_deref()).map(|entries| {
+                let files = entries
                     .iter()
-                    .map(|path| {
-                        path.strip_prefix(project_path)
-                            .unwrap()
-                            .to_string_lossy()
-                            .into_owned()
-                    })
+                    .filter(|entry| entry.status == EntryStatus::Included)
+                    .map(|entry| entry.path.clone())
                     .collect();
+                (files, Some(entries))
+            });
+        }
+        let files = match indexed {
+            Some(files) => files,
+            None => {
+                debug!("Gathering metadata for project: {}", name);
+                gather_files(&root, &[], &gather)?
+            }
+        };
+        let file_paths = files
+            .iter()
+            .map(|path| {
+                path.strip_prefix(&root)
+                    .map(|relative| relative.to_string_lossy().into_owned())
+                    .map_err(|_| ContexterError::PathOutsideProject {
+                        path: path.to_string_lossy().into_owned(),
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((file_paths, None))
+    })
+    .await;
 
-                let metadata = ProjectMetadata {
-                    name: project_name,
-                    path: project_path.to_string_lossy().into_owned(),
-                    files: file_paths,
-                };
+    match listing {
+        Ok(Ok((files, entries))) => {
+            let metadata = ProjectMetadata {
+                name: project_name,
+                path: project_path.to_string_lossy().into_owned(),
+                files,
+                entries,
+            };
 
-                info!(
-                    "Successfully retrieved metadata for project: {}",
-                    metadata.name
-                );
-                HttpResponse::Ok().json(metadata)
-            }
-            Err(e) => {
-                error!("Error gathering files for project {}: {}", project_name, e);
-                HttpResponse::InternalServerError().json(ErrorResponse {
-                    error: "Failed to gather project metadata".to_string(),
-                })
-            }
+            info!(
+                "Successfully retrieved metadata for project: {}",
+                metadata.name
+            );
+            HttpResponse::Ok().json(metadata)
+        }
+        Ok(Err(e)) => {
+            error!("Error gathering files for project {}: {}", project_name, e);
+            HttpResponse::InternalServerError()
+                .json(ErrorResponse::new("Failed to gather project metadata"))
+        }
+        Err(e) => {
+            error!("Listing project {} failed: {}", project_name, e);
+            HttpResponse::InternalServerError()
+                .json(ErrorResponse::new("Failed to gather project metadata"))
         }
-    } else {
-        warn!("Project not found: {}", project_name);
-        HttpResponse::NotFound().json(ErrorResponse {
-            error: format!("Project '{}' not found", project_name),
-        })
     }
 }
 
@@ -98,79 +147,468 @@ pub async fn run_contexter(
 ) -> impl Responder {
     let config = data.config.read().await;
     if !validate_api_key(&req, &config).await {
-        return HttpResponse::Unauthorized().json(ErrorResponse {
-            error: "Invalid or missing API key".to_string(),
-        });
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
     }
 
+    let snapshot = config.clone();
+    drop(config);
     let project_name = project_name.into_inner();
+    let contexter_req = contexter_req.into_inner().unwrap_or_default();
+    contexter_response(snapshot, project_name, contexter_req, data.cache.clone()).await
+}
 
-    if let Some(project_path) = config.projects.get(&project_name) {
-        let base_path = project_path.clone();
-        let files_to_process = if let Some(ContexterRequest { paths }) = contexter_req.into_inner()
-        {
-            if let Some(paths) = paths {
-                debug!(
-                    "Running contexter on specific paths for project: {}",
-                    project_name
-                );
-                paths
-                    .iter()
-                    .flat_map(|p| {
-                        let full_path = base_path.join(p);
-                        gather_relevant_files(full_path.to_str().unwrap(), vec![], vec![])
-                            .unwrap_or_default()
-                    })
-                    .collect()
-            } else {
-                debug!("Running contexter on entire project: {}", project_name);
-                match gather_relevant_files(project_path.to_str().unwrap(), vec![], vec![]) {
-                    Ok(files) => files,
-                    Err(e) => {
-                        error!("Error gathering files for project {}: {}", project_name, e);
-                        return HttpResponse::InternalServerError().json(ErrorResponse {
-                            error: "Failed to gather files".to_string(),
-                        });
-                    }
-                }
-            }
-        } else {
-            debug!("Running contexter on entire project: {}", project_name);
-            match gather_relevant_files(project_path.to_str().unwrap(), vec![], vec![]) {
-                Ok(files) => files,
+/// Assembles the diffs of a project's changes for review, with the whole changed files if asked.
+pub async fn diff_contexter(
+    req: HttpRequest,
+    project_name: web::Path<String>,
+    diff_req: web::Json<Option<DiffRequest>>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.config.read().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let snapshot = config.clone();
+    drop(config);
+    let project_name = project_name.into_inner();
+    let diff_req = diff_req.into_inner().unwrap_or_default();
+    match diff_req.contexter_request() {
+        Ok(contexter_req) => {
+            contexter_response(snapshot, project_name, contexter_req, data.cache.clone()).await
+        }
+        Err(e) => project_error_response(&project_name, ProjectError::Gather(e)),
+    }
+}
+
+/// Streams a project's context as it is read, either as NDJSON records or, when the client
+/// accepts `text/plain`, as the rendered text itself.
+pub async fn stream_contexter(
+    req: HttpRequest,
+    project_name: web::Path<String>,
+    contexter_req: web::Json<Option<ContexterRequest>>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.config.read().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    // Cloned so the lock is not held while the project is walked
+    let snapshot = config.clone();
+    drop(config);
+    let project_name = project_name.into_inner();
+    let contexter_req = contexter_req.into_inner().unwrap_or_default();
+    let cache = data.cache.clone();
+
+    let plain_text = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/plain"));
+
+    // The walk happens on the blocking task too; only whether it succeeded comes back here, so
+    // that a project that cannot be gathered still gets its error status
+    let (prepared_tx, prepared_rx) = oneshot::channel::<Result<(), ProjectError>>();
+    let (tx, rx) = mpsc::channel::<io::Result<Bytes>>(STREAM_BUFFER);
+    let name = project_name.clone();
+    tokio::task::spawn_blocking(move || {
+        let project_name = name;
+        let (gathered, options) =
+            match prepare_project(&snapshot, &project_name, &contexter_req, cache) {
+                Ok(prepared) => prepared,
                 Err(e) => {
-                    error!("Error gathering files for project {}: {}", project_name, e);
-                    return HttpResponse::InternalServerError().json(ErrorResponse {
-                        error: "Failed to gather files".to_string(),
-                    });
+                    let _ = prepared_tx.send(Err(e));
+                    return;
                 }
-            }
+            };
+        drop(snapshot);
+        if prepared_tx.send(Ok(())).is_err() {
+            return;
+        }
+
+        let send = |bytes: Bytes| {
+            tx.blocking_send(Ok(bytes))
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
         };
+        let result = stream_files_with_options(gathered.files, &options, |chunk| {
+            match chunk {
+                ContextChunk::Text(text) if plain_text => send(Bytes::from(text))?,
+                ContextChunk::Text(_) => {}
+                ContextChunk::Structure { rendered, .. } if plain_text => {
+                    send(Bytes::from(rendered))?
+                }
+                ContextChunk::Structure { tree, .. } => {
+                    send(ndjson_line(&StreamRecord::Structure { tree }))?
+                }
+                ContextChunk::File { rendered, .. } if plain_text => send(Bytes::from(rendered))?,
+                ContextChunk::File {
+                    path,
+                    record,
+                    section,
+                    truncated,
+                    skeleton,
+                    excerpt,
+                    ..
+                } => send(ndjson_line(&StreamRecord::File {
+                    path: path.to_string_lossy().into_owned(),
+                    section: section.to_string(),
+                    size: record.size,
+                    modified: record
+                        .modified
+                        .map(|modified| humantime::format_rfc3339_seconds(modified).to_string()),
+                    sha256: record.digest,
+                    content: record.content.to_string(),
+                    truncated,
+                    skeleton,
+                    excerpt,
+                }))?,
+                ContextChunk::Duplicate { rendered, .. } if plain_text => {
+                    send(Bytes::from(rendered))?
+                }
+                ContextChunk::Duplicate {
+                    path,
+                    original,
+                    section,
+                    ..
+                } => send(ndjson_line(&StreamRecord::Duplicate {
+                    path: path.to_string_lossy().into_owned(),
+                    section: section.to_string(),
+                    identical_to: original.to_string_lossy().into_owned(),
+                }))?,
+                ContextChunk::Diff { rendered, .. } if plain_text => send(Bytes::from(rendered))?,
+                ContextChunk::Diff { path, patch, .. } => send(ndjson_line(&StreamRecord::Diff {
+                    path: path.to_string_lossy().into_owned(),
+                    patch: patch.to_string(),
+                }))?,
+            }
+            Ok(())
+        });
 
-        match concatenate_files(files_to_process) {
-            Ok((content, processed_files)) => {
+        match result.map(|output| output.with_gather_report(gathered.skipped)) {
+            Ok(output) => {
                 info!(
-                    "Successfully ran contexter on {} files for project: {}",
-                    processed_files.len(),
-                    project_name
+                    "Successfully streamed {} files for project: {} ({} tokens, {} dropped)",
+                    output.files.len(),
+                    project_name,
+                    output.tokens,
+                    output.dropped.len()
                 );
-                let response = ProjectContentResponse { content };
-                HttpResponse::Ok().json(response)
+                if !plain_text {
+                    let _ = send(ndjson_line(&StreamRecord::Summary {
+                        files: output.files.len(),
+                        tokens: output.tokens,
+                        dropped: output.dropped,
+                        truncated: output.truncated,
+                        decoded: output.decoded,
+                        skipped: output.skipped,
+                        transformed: output.transformed,
+                        redacted: output.redacted,
+                        excerpted: output.excerpted,
+                    }));
+                }
             }
             Err(e) => {
-                error!(
-                    "Error concatenating files for project {}: {}",
-                    project_name, e
-                );
-                HttpResponse::InternalServerError().json(ErrorResponse {
-                    error: "Failed to concatenate files".to_string(),
-                })
+                error!("Error streaming files for project {}: {}", project_name, e);
+                if plain_text {
+                    // Plain text has no record to carry the error, so the response is cut off
+                    // instead of ending as if it were complete
+                    let _ = tx.blocking_send(Err(io::Error::other(e.to_string())));
+                } else {
+                    let _ = send(ndjson_line(&StreamRecord::Error {
+                        error: "Failed to concatenate files".to_string(),
+                    }));
+                }
             }
         }
+    });
+
+    match prepared_rx.await {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return project_error_response(&project_name, e),
+        Err(_) => {
+            error!("Preparing project {} stopped unexpectedly", project_name);
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new("Failed to concatenate files"));
+        }
+    }
+
+    let body = futures_util::stream::unfold(rx, |mut rx| async move {
+        let chunk = rx.recv().await?.map_err(actix_web::Error::from);
+        Some((chunk, rx))
+    });
+    let content_type = if plain_text {
+        "text/plain; charset=utf-8"
     } else {
-        warn!("Project not found: {}", project_name);
-        HttpResponse::NotFound().json(ErrorResponse {
-            error: format!("Project '{}' not found", project_name),
-        })
+        "application/x-ndjson"
+    };
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(body)
+}
+
+fn ndjson_line(record: &StreamRecord) -> Bytes {
+    let mut line = serde_json::to_vec(record).expect("stream records always serialize");
+    line.push(b'\n');
+    Bytes::from(line)
+}
+
+pub async fn list_presets(
+    req: HttpRequest,
+    project_name: web::Path<String>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.config.read().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let project_name = project_name.into_inner();
+    match config.projects.get(&project_name) {
+        Some(project) => HttpResponse::Ok().json(PresetListResponse {
+            presets: project.presets.clone(),
+        }),
+        None => project_not_found(&project_name),
+    }
+}
+
+pub async fn get_preset(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.config.read().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let (project_name, preset_name) = path.into_inner();
+    let Some(project) = config.projects.get(&project_name) else {
+        return project_not_found(&project_name);
+    };
+    match project.presets.get(&preset_name) {
+        Some(preset) => HttpResponse::Ok().json(preset),
+        None => preset_not_found(&project_name, &preset_name),
+    }
+}
+
+pub async fn put_preset(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    preset: web::Json<ContexterRequest>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut config = data.config.write().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let (project_name, preset_name) = path.into_inner();
+    let preset = preset.into_inner();
+    // A preset that could never run is refused rather than saved
+    if let Err(e) = preset.validate(&config, &project_name) {
+        return project_error_response(&project_name, e);
+    }
+    let Some(project) = config.projects.get_mut(&project_name) else {
+        return project_not_found(&project_name);
+    };
+    project.presets.insert(preset_name.clone(), preset.clone());
+
+    if let Some(response) = save_config(&config, &data) {
+        return response;
+    }
+    info!(
+        "Saved preset '{}' for project: {}",
+        preset_name, project_name
+    );
+    HttpResponse::Ok().json(preset)
+}
+
+pub async fn delete_preset(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let mut config = data.config.write().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let (project_name, preset_name) = path.into_inner();
+    let Some(project) = config.projects.get_mut(&project_name) else {
+        return project_not_found(&project_name);
+    };
+    if project.presets.remove(&preset_name).is_none() {
+        return preset_not_found(&project_name, &preset_name);
+    }
+
+    if let Some(response) = save_config(&config, &data) {
+        return response;
     }
+    info!(
+        "Removed preset '{}' from project: {}",
+        preset_name, project_name
+    );
+    HttpResponse::NoContent().finish()
+}
+
+pub async fn run_preset(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    data: web::Data<AppState>,
+) -> impl Responder {
+    let config = data.config.read().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let (project_name, preset_name) = path.into_inner();
+    let Some(project) = config.projects.get(&project_name) else {
+        return project_not_found(&project_name);
+    };
+    let Some(preset) = project.presets.get(&preset_name).cloned() else {
+        return preset_not_found(&project_name, &preset_name);
+    };
+    let snapshot = config.clone();
+    drop(config);
+    debug!(
+        "Running preset '{}' for project: {}",
+        preset_name, project_name
+    );
+    contexter_response(snapshot, project_name, preset, data.cache.clone()).await
+}
+
+/// Reports how the cache of file contents is doing.
+pub async fn cache_stats(req: HttpRequest, data: web::Data<AppState>) -> impl Responder {
+    let config = data.config.read().await;
+    if !validate_api_key(&req, &config).await {
+        return HttpResponse::Unauthorized().json(ErrorResponse::new("Invalid or missing API key"));
+    }
+
+    let stats = data
+        .cache
+        .as_ref()
+        .map(|cache| cache.stats())
+        .unwrap_or_default();
+    HttpResponse::Ok().json(stats)
+}
+
+/// Runs the contexter for a project against a snapshot of the config and turns the outcome into
+/// a response, on a blocking thread so that neither the config lock nor the async workers are
+/// held while the project is read.
+async fn contexter_response(
+    config: Config,
+    project_name: String,
+    contexter_req: ContexterRequest,
+    cache: Option<Arc<ContentCache>>,
+) -> HttpResponse {
+    let name = project_name.clone();
+    let result = web::block(move || run_project(&config, &name, &contexter_req, cache)).await;
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            error!(
+                "Running contexter for project {} failed: {}",
+                project_name, e
+            );
+            return HttpResponse::InternalServerError()
+                .json(ErrorResponse::new("Failed to concatenate files"));
+        }
+    };
+    match result {
+        Ok(output) => {
+            info!(
+                "Successfully ran contexter on {} files for project: {} ({} tokens, {} dropped)",
+                output.files.len(),
+                project_name,
+                output.tokens,
+                output.dropped.len()
+            );
+            HttpResponse::Ok().json(content_response(output))
+        }
+        Err(e) => project_error_response(&project_name, e),
+    }
+}
+
+fn project_error_response(project_name: &str, e: ProjectError) -> HttpResponse {
+    match e {
+        ProjectError::NotFound(_) => project_not_found(project_name),
+        ProjectError::Gather(e) | ProjectError::Concatenate(e) if is_client_error(&e) => {
+            warn!("Rejected request for project {}: {}", project_name, e);
+            HttpResponse::BadRequest().json(ErrorResponse {
+                error: e.to_string(),
+                pattern: e.pattern().map(String::from),
+            })
+        }
+        ProjectError::Gather(e) => {
+            error!("Error gathering files for project {}: {}", project_name, e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to gather files"))
+        }
+        ProjectError::Concatenate(e) => {
+            error!(
+                "Error concatenating files for project {}: {}",
+                project_name, e
+            );
+            HttpResponse::InternalServerError()
+                .json(ErrorResponse::new("Failed to concatenate files"))
+        }
+    }
+}
+
+/// Checks if an error was caused by what the client asked for rather than by the server.
+fn is_client_error(e: &ContexterError) -> bool {
+    matches!(
+        e,
+        ContexterError::InvalidPattern { .. }
+            | ContexterError::PathOutsideProject { .. }
+            | ContexterError::Git(_)
+    )
+}
+
+fn content_response(output: ContextOutput) -> ProjectContentResponse {
+    ProjectContentResponse {
+        content: output.content,
+        tokens: output.tokens,
+        files: output.files,
+        digests: output.digests,
+        dropped: output.dropped,
+        truncated: output.truncated,
+        skeletonized: output.skeletonized,
+        excerpted: output.excerpted,
+        transformed: output.transformed,
+        redacted: output.redacted,
+        decoded: output.decoded,
+        skipped: output.skipped,
+    }
+}
+
+/// Writes the configuration back to disk when the server was started from a config file,
+/// returning the error response to send if that fails.
+fn save_config(config: &Config, data: &AppState) -> Option<HttpResponse> {
+    let config_path = data.config_path.as_ref()?;
+    let e = config.save_to(config_path).err()?;
+    error!("Error saving configuration to {:?}: {}", config_path, e);
+    Some(
+        HttpResponse::InternalServerError()
+            .json(ErrorResponse::new("Failed to save configuration")),
+    )
+}
+
+fn project_not_found(project_name: &str) -> HttpResponse {
+    warn!("Project not found: {}", project_name);
+    HttpResponse::NotFound().json(ErrorResponse::new(format!(
+        "Project '{}' not found",
+        project_name
+    )))
+}
+
+fn preset_not_found(project_name: &str, preset_name: &str) -> HttpResponse {
+    warn!(
+        "Preset not found: {} in project {}",
+        preset_name, project_name
+    );
+    HttpResponse::NotFound().json(ErrorResponse::new(format!(
+        "Preset '{}' not found in project '{}'",
+        preset_name, project_name
+    )))
 }
@@ -0,0 +1,194 @@
+use crate::language::detect_language;
+use std::ops::Range;
+use std::path::Path;
+use tree_sitter::{Language, Node, Parser};
+
+/// What stands in for an elided function body in languages with braces.
+const BRACED_BODY: &str = "{ ... }";
+/// What stands in for an elided function body in Python, after the colon.
+const INDENTED_BODY: &str = " ...";
+
+/// The languages that can be reduced to a skeleton, each with a tree-sitter grammar compiled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Grammar {
+    Rust,
+    Python,
+    TypeScript,
+    Tsx,
+}
+
+impl Grammar {
+    fn of(path: &Path) -> Option<Self> {
+        match detect_language(path)? {
+            "rust" => Some(Grammar::Rust),
+            "python" => Some(Grammar::Python),
+            "typescript" => Some(Grammar::TypeScript),
+            "tsx" => Some(Grammar::Tsx),
+            _ => None,
+        }
+    }
+
+    fn language(self) -> Language {
+        match self {
+            Grammar::Rust => tree_sitter_rust::LANGUAGE.into(),
+            Grammar::Python => tree_sitter_python::LANGUAGE.into(),
+            Grammar::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            Grammar::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
+        }
+    }
+
+    /// Checks if a top-level node is part of the file's shape: an item, an import or a doc
+    /// comment, rather than a statement or a plain comment.
+    fn is_item(self, node: Node, source: &str) -> bool {
+        let text = &source[node.byte_range()];
+        match self {
+            Grammar::Rust => match node.kind() {
+                "line_comment" | "block_comment" => ["///", "//!", "/**", "/*!"]
+                    .iter()
+                    .any(|doc| text.starts_with(doc)),
+                "expression_statement" => false,
+                _ => true,
+            },
+            Grammar::Python => match node.kind() {
+                "import_statement"
+                | "import_from_statement"
+                | "future_import_statement"
+                | "function_definition"
+                | "class_definition"
+                | "decorated_definition"
+                | "type_alias_statement" => true,
+                // Module-level constants
+                "expression_statement" => node
+                    .named_child(0)
+                    .is_some_and(|child| child.kind() == "assignment"),
+                _ => false,
+            },
+            Grammar::TypeScript | Grammar::Tsx => match node.kind() {
+                "comment" => text.starts_with("/**"),
+                "import_statement"
+                | "export_statement"
+                | "function_declaration"
+                | "generator_function_declaration"
+                | "function_signature"
+                | "class_declaration"
+                | "abstract_class_declaration"
+                | "interface_declaration"
+                | "type_alias_declaration"
+                | "enum_declaration"
+                | "lexical_declaration"
+                | "variable_declaration"
+                | "module"
+                | "internal_module"
+                | "ambient_declaration" => true,
+                _ => false,
+            },
+        }
+    }
+
+    /// The body of a function, method or closure with a block body, which is what gets elided.
+    fn body<'t>(self, node: Node<'t>) -> Option<Node<'t>> {
+        let function = match self {
+            Grammar::Rust => node.kind() == "function_item",
+            Grammar::Python => node.kind() == "function_definition",
+            Grammar::TypeScript | Grammar::Tsx => matches!(
+                node.kind(),
+                "function_declaration"
+                    | "generator_function_declaration"
+                    | "function_expression"
+                    | "generator_function"
+                    | "arrow_function"
+                    | "method_definition"
+            ),
+        };
+        if !function {
+            return None;
+        }
+        // Arrow functions returning an expression have no block to elide
+        node.child_by_field_name("body")
+            .filter(|body| matches!(body.kind(), "block" | "statement_block"))
+    }
+
+    /// The bytes to replace when eliding `body`, and what to replace them with.
+    fn elision(self, function: Node, body: Node) -> (Range<usize>, &'static str) {
+        match self {
+            // From the colon ending the signature, so the body's indentation goes too
+            Grammar::Python => {
+                let mut cursor = function.walk();
+                let colon = function
+                    .children(&mut cursor)
+                    .filter(|child| child.kind() == ":" && child.end_byte() <= body.start_byte())
+                    .last()
+                    .map_or(body.start_byte(), |colon| colon.end_byte());
+                (colon..body.end_byte(), INDENTED_BODY)
+            }
+            _ => (body.byte_range(), BRACED_BODY),
+        }
+    }
+
+    /// Finds the function bodies within `node` to elide, in the order they appear.
+    fn collect_bodies(self, node: Node, elided: &mut Vec<(Range<usize>, &'static str)>) {
+        if let Some(body) = self.body(node) {
+            elided.push(self.elision(node, body));
+            return;
+        }
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            self.collect_bodies(child, elided);
+        }
+    }
+}
+
+/// Reduces a source file to its skeleton: its imports and the signatures of its items, such as
+/// functions, structs, enums, traits, impls and classes, with function bodies elided. Doc
+/// comments are kept, while other comments and top-level statements are dropped.
+///
+/// Returns `None` when the file's language has no grammar compiled in, when it does not parse
+/// cleanly or when nothing in it is an item, so that the file can be kept whole instead.
+pub fn skeletonize(path: &Path, source: &str) -> Option<String> {
+    let grammar = Grammar::of(path)?;
+    let mut parser = Parser::new();
+    parser.set_language(&grammar.language()).ok()?;
+    let tree = parser.parse(source, None)?;
+    let root = tree.root_node();
+    if root.has_error() {
+        return None;
+    }
+
+    let mut skeleton = String::new();
+    let mut last_row = None;
+    let mut cursor = root.walk();
+    for item in root.named_children(&mut cursor) {
+        if !grammar.is_item(item, source) {
+            continue;
+        }
+        // Keep items apart by a blank line where the source had one, or lost a statement
+        if let Some(row) = last_row {
+            skeleton.push('\n');
+            if item.start_position().row > row + 1 {
+                skeleton.push('\n');
+            }
+        }
+        let mut elided = Vec::new();
+        grammar.collect_bodies(item, &mut elided);
+        let mut start = item.start_byte();
+        let mut text = String::new();
+        for (range, replacement) in elided {
+            text.push_str(&source[start..range.start]);
+            text.push_str(replacement);
+            start = range.end;
+        }
+        text.push_str(&source[start..item.end_byte()]);
+        skeleton.push_str(text.trim_end());
+        // Comments may take the line break ending them along
+        let end = item.end_position();
+        last_row = Some(if end.column == 0 {
+            end.row - 1
+        } else {
+            end.row
+        });
+    }
+    // Nothing but statements, so there is no shape to show
+    last_row?;
+    skeleton.push('\n');
+    Some(skeleton)
+}
@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use tiktoken_rs::CoreBPE;
+
+/// Counts tokens so gathered context can be kept within an LLM window.
+pub trait Tokenizer: Send + Sync {
+    /// Returns the number of tokens in `text`.
+    fn count_tokens(&self, text: &str) -> usize;
+
+    /// Returns the longest prefix of `text` that fits within `max_tokens`.
+    fn truncate<'a>(&self, text: &'a str, max_tokens: usize) -> &'a str {
+        if self.count_tokens(text) <= max_tokens {
+            return text;
+        }
+
+        // Binary search for the longest prefix, in whole chars, that fits
+        let ends: Vec<usize> = text.char_indices().map(|(i, c)| i + c.len_utf8()).collect();
+        let (mut low, mut high) = (0, ends.len());
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if self.count_tokens(&text[..ends[mid - 1]]) <= max_tokens {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        if low == 0 {
+            ""
+        } else {
+            &text[..ends[low - 1]]
+        }
+    }
+}
+
+/// A cheap estimator assuming roughly four characters per token.
+pub struct CharEstimator;
+
+impl Tokenizer for CharEstimator {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(4)
+    }
+}
+
+/// A byte-pair encoding tokenizer backed by the BPE tables bundled with `tiktoken-rs`.
+pub struct BpeTokenizer {
+    bpe: &'static CoreBPE,
+}
+
+impl Tokenizer for BpeTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_ordinary(text).len()
+    }
+}
+
+/// The tokenizers that can be selected from the CLI, the config or an HTTP request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenizerKind {
+    /// Characters divided by four; fast and good enough for rough budgets.
+    #[default]
+    Estimate,
+    /// The `cl100k_base` encoding.
+    Cl100k,
+    /// The `o200k_base` encoding.
+    O200k,
+}
+
+impl TokenizerKind {
+    /// Creates the tokenizer for this kind. BPE tables are loaded once and shared.
+    pub fn build(self) -> Box<dyn Tokenizer> {
+        match self {
+            TokenizerKind::Estimate => Box::new(CharEstimator),
+            TokenizerKind::Cl100k => Box::new(BpeTokenizer {
+                bpe: tiktoken_rs::cl100k_base_singleton(),
+            }),
+            TokenizerKind::O200k => Box::new(BpeTokenizer {
+                bpe: tiktoken_rs::o200k_base_singleton(),
+            }),
+        }
+    }
+}
+
+impl FromStr for TokenizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "estimate" | "chars" => Ok(TokenizerKind::Estimate),
+            "cl100k" | "cl100k_base" => Ok(TokenizerKind::Cl100k),
+            "o200k" | "o200k_base" => Ok(TokenizerKind::O200k),
+            _ => Err(format!(
+                "Unknown tokenizer '{}' (expected estimate, cl100k or o200k)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TokenizerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TokenizerKind::Estimate => "estimate",
+            TokenizerKind::Cl100k => "cl100k",
+            TokenizerKind::O200k => "o200k",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,481 @@
+use crate::language::detect_language;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::Path;
+
+/// Recognizes a license header from the text of a file's leading comments.
+pub const DEFAULT_LICENSE_PATTERN: &str =
+    r"(?i)\b(copyright|licen[cs]ed?|spdx-license-identifier)\b";
+
+/// Transforms applied to each file's content before it is placed in the output, to spend fewer
+/// tokens on what a model does not need. All are off by default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transforms {
+    /// Remove comments, in the languages whose comment syntax is known.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub strip_comments: bool,
+    /// Keep doc comments, such as `///` and `/** */`, when stripping comments.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub keep_doc_comments: bool,
+    /// Remove the comments a file opens with if they match `license_pattern`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub strip_license: bool,
+    /// Regex recognizing a license header. Defaults to `DEFAULT_LICENSE_PATTERN`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub license_pattern: Option<String>,
+    /// Replace runs of blank lines with a single one, and drop blank lines opening the file.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub collapse_blank_lines: bool,
+    /// Remove whitespace at the end of lines.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub trim_trailing_whitespace: bool,
+}
+
+impl Transforms {
+    /// Checks if any transform is turned on.
+    pub fn is_active(&self) -> bool {
+        self.strip_comments
+            || self.strip_license
+            || self.collapse_blank_lines
+            || self.trim_trailing_whitespace
+    }
+
+    /// Compiles the transforms, failing if the license pattern is not a valid regex.
+    pub fn compile(&self) -> Result<TransformPipeline, regex::Error> {
+        let license = match (self.strip_license, &self.license_pattern) {
+            (false, _) => None,
+            (true, Some(pattern)) => Some(Regex::new(pattern)?),
+            (true, None) => Some(Regex::new(DEFAULT_LICENSE_PATTERN)?),
+        };
+        Ok(TransformPipeline {
+            strip_comments: self.strip_comments,
+            keep_doc_comments: self.keep_doc_comments,
+            license,
+            collapse_blank_lines: self.collapse_blank_lines,
+            trim_trailing_whitespace: self.trim_trailing_whitespace,
+        })
+    }
+}
+
+/// Compiled transforms, ready to apply to files.
+pub struct TransformPipeline {
+    strip_comments: bool,
+    keep_doc_comments: bool,
+    license: Option<Regex>,
+    collapse_blank_lines: bool,
+    trim_trailing_whitespace: bool,
+}
+
+impl TransformPipeline {
+    /// Applies the transforms to the content of the file at `path`, in order: the license
+    /// header, comments, trailing whitespace and then blank lines.
+    pub fn apply(&self, path: &Path, content: &str) -> String {
+        let mut content = content.to_string();
+        if let Some(syntax) = CommentSyntax::of(path) {
+            if let Some(license) = &self.license {
+                content = strip_license(&content, &syntax, license);
+            }
+            if self.strip_comments {
+                let comments: Vec<Range<usize>> = syntax
+                    .comments(&content)
+                    .into_iter()
+                    .filter(|comment| !(self.keep_doc_comments && comment.doc))
+                    .map(|comment| comment.range)
+                    .collect();
+                content = remove_comments(&content, &comments);
+            }
+        }
+        if self.trim_trailing_whitespace {
+            content = trim_trailing_whitespace(&content);
+        }
+        if self.collapse_blank_lines {
+            content = collapse_blank_lines(&content);
+        }
+        content
+    }
+}
+
+/// How comments and the strings that may hide comment markers are written in a language.
+struct CommentSyntax {
+    line: &'static [&'static str],
+    block: &'static [(&'static str, &'static str)],
+    /// Prefixes marking a comment as documentation.
+    doc: &'static [&'static str],
+    quotes: &'static [&'static str],
+    /// `'` opens a character literal rather than a string, so a lone one is a lifetime or label.
+    char_literals: bool,
+    /// Raw strings such as `r"..."`, `r#"..."#` and `br"..."`, closed by a quote followed by as
+    /// many `#` as they were opened with, and in which backslashes escape nothing.
+    raw_strings: bool,
+    /// `#` only opens a comment at the start of a word, as in shells and YAML.
+    hash_at_word_start: bool,
+}
+
+const C_LIKE_DOC: &[&str] = &["///", "//!", "/**", "/*!"];
+
+impl CommentSyntax {
+    fn of(path: &Path) -> Option<Self> {
+        let syntax = match detect_language(path)? {
+            "rust" => CommentSyntax {
+                line: &["//"],
+                block: &[("/*", "*/")],
+                doc: C_LIKE_DOC,
+                quotes: &["\""],
+                char_literals: true,
+                raw_strings: true,
+                hash_at_word_start: false,
+            },
+            "c" | "cpp" | "java" | "kotlin" | "scala" | "csharp" | "swift" => CommentSyntax {
+                line: &["//"],
+                block: &[("/*", "*/")],
+                doc: C_LIKE_DOC,
+                quotes: &["\""],
+                char_literals: true,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "go" => CommentSyntax {
+                line: &["//"],
+                block: &[("/*", "*/")],
+                doc: &[],
+                quotes: &["\"", "`"],
+                char_literals: true,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "typescript" | "tsx" | "javascript" | "jsx" => CommentSyntax {
+                line: &["//"],
+                block: &[("/*", "*/")],
+                doc: &["/**"],
+                quotes: &["\"", "'", "`"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "php" => CommentSyntax {
+                line: &["//", "#"],
+                block: &[("/*", "*/")],
+                doc: &["/**"],
+                quotes: &["\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "css" => CommentSyntax {
+                line: &[],
+                block: &[("/*", "*/")],
+                doc: &[],
+                quotes: &["\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "scss" => CommentSyntax {
+                line: &["//"],
+                block: &[("/*", "*/")],
+                doc: &["///"],
+                quotes: &["\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "python" => CommentSyntax {
+                line: &["#"],
+                block: &[],
+                doc: &[],
+                quotes: &["\"\"\"", "'''", "\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "ruby" => CommentSyntax {
+                line: &["#"],
+                block: &[],
+                doc: &[],
+                quotes: &["\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "bash" | "yaml" | "toml" | "makefile" | "dockerfile" | "cmake" => CommentSyntax {
+                line: &["#"],
+                block: &[],
+                doc: &[],
+                quotes: &["\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: true,
+            },
+            "powershell" => CommentSyntax {
+                line: &["#"],
+                block: &[("<#", "#>")],
+                doc: &[],
+                quotes: &["\"", "'"],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: true,
+            },
+            "sql" => CommentSyntax {
+                line: &["--"],
+                block: &[("/*", "*/")],
+                doc: &[],
+                quotes: &["'", "\""],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            "html" | "xml" => CommentSyntax {
+                line: &[],
+                block: &[("<!--", "-->")],
+                doc: &[],
+                quotes: &[],
+                char_literals: false,
+                raw_strings: false,
+                hash_at_word_start: false,
+            },
+            _ => return None,
+        };
+        Some(syntax)
+    }
+
+    /// Finds the comments in `source`, skipping over strings so that markers inside them are
+    /// left alone. A shebang line is not a comment.
+    fn comments(&self, source: &str) -> Vec<Comment> {
+        let bytes = source.as_bytes();
+        let mut comments = Vec::new();
+        let mut i = if source.starts_with("#!") {
+            source.find('\n').unwrap_or(source.len())
+        } else {
+            0
+        };
+        'scan: while i < source.len() {
+            let rest = &source[i..];
+            if self.raw_strings {
+                let word_start = i == 0 || !is_identifier_byte(bytes[i - 1]);
+                if let Some(end) = word_start.then(|| skip_raw_string(source, i)).flatten() {
+                    i = end;
+                    continue;
+                }
+            }
+            if let Some(quote) = self.quotes.iter().find(|quote| rest.starts_with(**quote)) {
+                i = skip_string(source, i, quote);
+                continue;
+            }
+            if rest.starts_with('\'') && self.char_literals {
+                i = skip_char_literal(source, i);
+                continue;
+            }
+            for marker in self.line {
+                let word_start = i == 0 || bytes[i - 1].is_ascii_whitespace();
+                if rest.starts_with(marker)
+                    && (*marker != "#" || !self.hash_at_word_start || word_start)
+                {
+                    let end = rest.find('\n').map_or(source.len(), |end| i + end);
+                    comments.push(self.comment(source, i..end));
+                    i = end;
+                    continue 'scan;
+                }
+            }
+            for (open, close) in self.block {
+                if let Some(body) = rest.strip_prefix(open) {
+                    let end = body
+                        .find(close)
+                        .map_or(source.len(), |end| i + open.len() + end + close.len());
+                    comments.push(self.comment(source, i..end));
+                    i = end;
+                    continue 'scan;
+                }
+            }
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+        comments
+    }
+
+    fn comment(&self, source: &str, range: Range<usize>) -> Comment {
+        let text = &source[range.clone()];
+        // `/**/` is an empty comment rather than documentation
+        let doc = self.doc.iter().any(|doc| text.starts_with(doc)) && text != "/**/";
+        Comment { range, doc }
+    }
+}
+
+struct Comment {
+    range: Range<usize>,
+    doc: bool,
+}
+
+/// Returns where the string opened by `quote` at `start` ends, honouring backslash escapes.
+/// Steps a character at a time, so that the end is always on a character boundary.
+fn skip_string(source: &str, start: usize, quote: &str) -> usize {
+    let body = start + quote.len();
+    let mut chars = source[body..].char_indices();
+    while let Some((offset, c)) = chars.next() {
+        if c == '\\' {
+            // The escaped character, however wide it is
+            chars.next();
+        } else if source[body + offset..].starts_with(quote) {
+            return body + offset + quote.len();
+        }
+    }
+    source.len()
+}
+
+/// Returns where the raw string opened at `start` ends, or `None` if there is no raw string
+/// there.
+fn skip_raw_string(source: &str, start: usize) -> Option<usize> {
+    let rest = &source[start..];
+    let rest = rest
+        .strip_prefix("br")
+        .or_else(|| rest.strip_prefix("cr"))
+        .or_else(|| rest.strip_prefix('r'))?;
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    let body = rest[hashes..].strip_prefix('"')?;
+    let close = format!("\"{}", "#".repeat(hashes));
+    let opened = source.len() - body.len();
+    Some(
+        body.find(&close)
+            .map_or(source.len(), |end| opened + end + close.len()),
+    )
+}
+
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Returns where a character literal such as `'a'` or `'\n'` at `start` ends, or just past the
+/// quote if it is a lifetime or label such as `'a` instead.
+fn skip_char_literal(source: &str, start: usize) -> usize {
+    let rest = &source[start + 1..];
+    if let Some(escape) = rest.strip_prefix('\\') {
+        // The escaped character may itself be a quote
+        let escaped = escape.chars().next().map_or(0, char::len_utf8);
+        return escape[escaped..]
+            .find('\'')
+            .map_or(start + 1, |end| start + 2 + escaped + end + 1);
+    }
+    let mut chars = rest.char_indices();
+    match (chars.next(), chars.next()) {
+        (Some(_), Some((end, '\''))) => start + 1 + end + 1,
+        _ => start + 1,
+    }
+}
+
+/// Removes comments from `source`, dropping the lines they leave blank and the whitespace left
+/// before comments at the end of a line.
+fn remove_comments(source: &str, comments: &[Range<usize>]) -> String {
+    if comments.is_empty() {
+        return source.to_string();
+    }
+    let mut result = String::with_capacity(source.len());
+    let mut comments = comments.iter().peekable();
+    let mut line_start = 0;
+    for line in source.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        let (body, newline) = match line.strip_suffix('\n') {
+            Some(body) => (body, "\n"),
+            None => (line, ""),
+        };
+        let body_end = line_start + body.len();
+
+        let mut kept = String::new();
+        let mut position = line_start;
+        let mut had_comment = false;
+        while let Some(comment) = comments.peek() {
+            if comment.start >= body_end {
+                break;
+            }
+            had_comment = true;
+            if comment.start > position {
+                kept.push_str(&source[position..comment.start]);
+            }
+            position = position.max(comment.end);
+            if comment.end > body_end {
+                // Carries on to the next line
+                break;
+            }
+            comments.next();
+        }
+        if position < body_end {
+            kept.push_str(&source[position..body_end]);
+        }
+
+        if had_comment && kept.trim().is_empty() {
+            // Nothing but comments on the line
+        } else if had_comment {
+            result.push_str(kept.trim_end());
+            result.push_str(newline);
+        } else {
+            result.push_str(&kept);
+            result.push_str(newline);
+        }
+        line_start = line_end;
+    }
+    result
+}
+
+/// Removes the comments a file opens with, after any shebang, if together they match `license`,
+/// along with the blank lines following them.
+fn strip_license(source: &str, syntax: &CommentSyntax, license: &Regex) -> String {
+    let comments = syntax.comments(source);
+    let start = if source.starts_with("#!") {
+        source.find('\n').map_or(source.len(), |end| end + 1)
+    } else {
+        0
+    };
+    let Some(first) = comments.first() else {
+        return source.to_string();
+    };
+    if !source[start..first.range.start].trim().is_empty() {
+        return source.to_string();
+    }
+    // The header runs on for as long as comments follow each other without a blank line
+    let mut end = first.range.end;
+    for comment in &comments[1..] {
+        let gap = &source[end..comment.range.start];
+        if !gap.trim().is_empty() || gap.matches('\n').count() > 1 {
+            break;
+        }
+        end = comment.range.end;
+    }
+    if !license.is_match(&source[first.range.start..end]) {
+        return source.to_string();
+    }
+    // Drop the rest of the header's last line and the blank lines after it
+    let mut rest = &source[end..];
+    while let Some(newline) = rest.find('\n') {
+        if !rest[..newline].trim().is_empty() {
+            break;
+        }
+        rest = &rest[newline + 1..];
+    }
+    if rest.trim().is_empty() {
+        rest = "";
+    }
+    format!("{}{}", &source[..start], rest)
+}
+
+fn trim_trailing_whitespace(source: &str) -> String {
+    source
+        .split_inclusive('\n')
+        .map(|line| {
+            let newline = if line.ends_with('\n') { "\n" } else { "" };
+            format!("{}{}", line.trim_end(), newline)
+        })
+        .collect()
+}
+
+fn collapse_blank_lines(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut blank_run = true; // Blank lines opening the file are dropped
+    for line in source.split_inclusive('\n') {
+        let blank = line.trim().is_empty();
+        if blank && blank_run {
+            continue;
+        }
+        blank_run = blank;
+        result.push_str(line);
+    }
+    result
+}
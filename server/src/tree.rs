@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::path::{Component, Path};
+
+/// The layout of a project's files, drawn as an ASCII tree like `tree --charset=ascii` does.
+///
+/// Entries are added by path and may carry a note, such as their size, printed after their
+/// name. Directories are created as paths need them and listed alongside files, by name.
+pub struct ProjectTree {
+    root: String,
+    children: BTreeMap<String, Node>,
+}
+
+#[derive(Default)]
+struct Node {
+    directory: bool,
+    note: Option<String>,
+    children: BTreeMap<String, Node>,
+}
+
+impl ProjectTree {
+    /// Starts an empty tree, labelled with the name of `root`.
+    pub fn new(root: &Path) -> Self {
+        // Relative roots such as `.` have no name of their own
+        let name = root
+            .file_name()
+            .map(|name| name.to_os_string())
+            .or_else(|| root.canonicalize().ok()?.file_name().map(Into::into))
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| ".".to_string());
+        ProjectTree {
+            root: name,
+            children: BTreeMap::new(),
+        }
+    }
+
+    /// Adds an entry at `path`, relative to the root. `directory` marks an entry standing for
+    /// a whole directory, such as an excluded one, rather than a file.
+    pub fn add(&mut self, path: &Path, directory: bool, note: Option<String>) {
+        let names: Vec<String> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let Some((last, parents)) = names.split_last() else {
+            return;
+        };
+        let mut children = &mut self.children;
+        for name in parents {
+            let node = children.entry(name.clone()).or_default();
+            node.directory = true;
+            children = &mut node.children;
+        }
+        let node = children.entry(last.clone()).or_default();
+        node.directory |= directory;
+        node.note = note;
+    }
+
+    /// Draws the tree, one entry per line, directories marked with a trailing `/`.
+    pub fn render(&self) -> String {
+        let mut rendered = format!("{}/\n", self.root);
+        render_children(&self.children, "", &mut rendered);
+        rendered
+    }
+}
+
+fn render_children(children: &BTreeMap<String, Node>, prefix: &str, rendered: &mut String) {
+    let count = children.len();
+    for (i, (name, node)) in children.iter().enumerate() {
+        let last = i + 1 == count;
+        rendered.push_str(prefix);
+        rendered.push_str(if last { "`-- " } else { "|-- " });
+        rendered.push_str(name);
+        if node.directory {
+            rendered.push('/');
+        }
+        if let Some(note) = &node.note {
+            rendered.push(' ');
+            rendered.push_str(note);
+        }
+        rendered.push('\n');
+        let prefix = format!("{}{}", prefix, if last { "    " } else { "|   " });
+        render_children(&node.children, &prefix, rendered);
+    }
+}
+
+/// Formats a size in bytes for people, such as `512 B` or `1.5 KB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,199 @@
+use contexter::cache::{CachedContent, CachedFile, ContentCache, EncodingName, TokenCost};
+use contexter::contexter::{concatenate_files_with_options, ContextOptions};
+use contexter::digest::ContentDigest;
+use contexter::formatter::{HeaderOptions, OutputFormat};
+use contexter::redact::Redaction;
+use contexter::tokenizer::TokenizerKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tempfile::tempdir;
+
+fn text_file(text: &str, modified: SystemTime) -> CachedFile {
+    CachedFile {
+        size: text.len() as u64,
+        modified,
+        content: CachedContent::Text {
+            text: text.into(),
+            encoding: EncodingName("UTF-8"),
+            digest: ContentDigest::of(text.as_bytes()),
+        },
+        costs: Vec::new(),
+    }
+}
+
+#[test]
+fn test_entries_are_only_used_while_the_file_is_unchanged() {
+    let cache = ContentCache::new(10, 1024);
+    let path = PathBuf::from("/project/main.rs");
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+    cache.insert(path.clone(), text_file("fn main() {}", modified));
+
+    assert!(cache.get(&path, 12, modified).is_some());
+    assert!(cache.get(&path, 13, modified).is_none());
+    assert!(cache
+        .get(&path, 12, modified + Duration::from_secs(1))
+        .is_none());
+    assert!(cache
+        .get(&PathBuf::from("/project/lib.rs"), 12, modified)
+        .is_none());
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (1, 3));
+    assert_eq!((stats.entries, stats.bytes), (1, 12));
+}
+
+#[test]
+fn test_least_recently_used_entries_are_evicted() {
+    let modified = SystemTime::UNIX_EPOCH;
+    let cache = ContentCache::new(10, 1024);
+    for i in 0..10 {
+        cache.insert(PathBuf::from(format!("{}.rs", i)), text_file("x", modified));
+    }
+    // Using the oldest entry keeps it around
+    assert!(cache.get(&PathBuf::from("0.rs"), 1, modified).is_some());
+    cache.insert(PathBuf::from("10.rs"), text_file("x", modified));
+
+    let stats = cache.stats();
+    assert!(stats.entries <= 10);
+    assert!(stats.evictions >= 1);
+    assert!(cache.get(&PathBuf::from("0.rs"), 1, modified).is_some());
+    assert!(cache.get(&PathBuf::from("1.rs"), 1, modified).is_none());
+    assert!(cache.get(&PathBuf::from("10.rs"), 1, modified).is_some());
+
+    // The byte limit applies too, and files that could never fit are not cached at all
+    let cache = ContentCache::new(100, 10);
+    cache.insert(PathBuf::from("big.rs"), text_file("01234567890", modified));
+    assert_eq!(cache.stats().entries, 0);
+    for i in 0..4 {
+        cache.insert(
+            PathBuf::from(format!("{}.rs", i)),
+            text_file("abcd", modified),
+        );
+    }
+    let stats = cache.stats();
+    assert!(stats.bytes <= 10);
+    assert!(cache.get(&PathBuf::from("3.rs"), 4, modified).is_some());
+}
+
+#[test]
+fn test_cache_survives_a_save_and_load() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let snapshot = dir.path().join("cache/contents.json");
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_millis(1_234_567);
+
+    let cache = ContentCache::new(10, 1024);
+    let mut file = text_file("fn main() {}", modified);
+    file.costs.push(TokenCost {
+        tokenizer: TokenizerKind::Cl100k,
+        format: OutputFormat::Markdown,
+        header: HeaderOptions::default(),
+        skeleton: false,
+        redaction: None,
+        tokens: 7,
+    });
+    cache.insert(PathBuf::from("main.rs"), file);
+    cache.insert(
+        PathBuf::from("logo.png"),
+        CachedFile {
+            size: 10,
+            modified,
+            content: CachedContent::Binary,
+            costs: Vec::new(),
+        },
+    );
+    cache.save(&snapshot)?;
+
+    let loaded = ContentCache::load(&snapshot, 10, 1024)?;
+    assert_eq!(loaded.stats().entries, 2);
+    let main = loaded.get(&PathBuf::from("main.rs"), 12, modified).unwrap();
+    assert_eq!(
+        main.cost(
+            TokenizerKind::Cl100k,
+            OutputFormat::Markdown,
+            HeaderOptions::default(),
+            false,
+            None
+        ),
+        Some(7)
+    );
+    // The skeleton of a file costs something else
+    assert_eq!(
+        main.cost(
+            TokenizerKind::Cl100k,
+            OutputFormat::Markdown,
+            HeaderOptions::default(),
+            true,
+            None
+        ),
+        None
+    );
+    let header = HeaderOptions {
+        omit_size: true,
+        omit_modified: false,
+    };
+    assert_eq!(
+        main.cost(
+            TokenizerKind::Cl100k,
+            OutputFormat::Markdown,
+            header,
+            false,
+            None
+        ),
+        None
+    );
+    // So does the file with its secrets redacted
+    assert_eq!(
+        main.cost(
+            TokenizerKind::Cl100k,
+            OutputFormat::Markdown,
+            HeaderOptions::default(),
+            false,
+            Some(Redaction::default().fingerprint())
+        ),
+        None
+    );
+    assert!(
+        matches!(main.content, CachedContent::Text { ref text, .. } if &**text == "fn main() {}")
+    );
+    let logo = loaded
+        .get(&PathBuf::from("logo.png"), 10, modified)
+        .unwrap();
+    assert!(matches!(logo.content, CachedContent::Binary));
+
+    // A missing snapshot just means starting empty
+    let empty = ContentCache::load(&dir.path().join("missing.json"), 10, 1024)?;
+    assert_eq!(empty.stats().entries, 0);
+    Ok(())
+}
+
+#[test]
+fn test_cached_output_matches_a_fresh_read() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    std::fs::write(dir.path().join("a.rs"), "fn a() {}\n")?;
+    std::fs::write(dir.path().join("b.rs"), "fn a() {}\n")?;
+    std::fs::write(dir.path().join("c.bin"), [0u8, 1, 2, 3])?;
+    let files: Vec<PathBuf> = ["a.rs", "b.rs", "c.bin"]
+        .iter()
+        .map(|name| dir.path().join(name))
+        .collect();
+
+    let cache = Arc::new(ContentCache::new(100, 1024 * 1024));
+    let options = ContextOptions {
+        cache: Some(cache.clone()),
+        max_tokens: Some(1_000),
+        ..Default::default()
+    };
+    let fresh = concatenate_files_with_options(files.clone(), &ContextOptions::default())?;
+    let first = concatenate_files_with_options(files.clone(), &options)?;
+    let second = concatenate_files_with_options(files, &options)?;
+
+    for output in [&first, &second] {
+        assert_eq!(output.content, fresh.content);
+        assert_eq!(output.files, fresh.files);
+        assert_eq!(output.skipped, fresh.skipped);
+    }
+    assert_eq!(first.tokens, second.tokens);
+    assert_eq!(cache.stats().hits, 3);
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use contexter::categories::{CategoryRules, SectionRule};
+use contexter::contexter::{concatenate_files_with_options, gather_relevant_files, ContextOptions};
+use std::fs::{create_dir_all, File};
+use std::io::Write;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn section_of(rules: &CategoryRules, path: &str) -> String {
+    let matcher = rules.compile().unwrap();
+    matcher.section_names()[matcher.categorize(Path::new(path))].clone()
+}
+
+#[test]
+fn test_default_rules_recognise_tests_across_languages() {
+    let rules = CategoryRules::default();
+
+    assert_eq!(section_of(&rules, "pkg/server_test.go"), "Tests");
+    assert_eq!(section_of(&rules, "tests/api.rs"), "Tests");
+    assert_eq!(section_of(&rules, "crates/core/tests/util/mod.rs"), "Tests");
+    assert_eq!(section_of(&rules, "app/test_models.py"), "Tests");
+    assert_eq!(section_of(&rules, "web/src/button.test.tsx"), "Tests");
+    assert_eq!(
+        section_of(&rules, "src/test/java/com/acme/FooTest.java"),
+        "Tests"
+    );
+    assert_eq!(section_of(&rules, "lib/parser_unittest.cc"), "Tests");
+
+    assert_eq!(section_of(&rules, "src/main.rs"), "Source Files");
+    assert_eq!(section_of(&rules, "src/contest.rs"), "Source Files");
+    assert_eq!(section_of(&rules, "docs/guide.md"), "Documentation");
+    assert_eq!(section_of(&rules, "Cargo.toml"), "Configuration Files");
+    assert_eq!(section_of(&rules, "go.mod"), "Configuration Files");
+    assert_eq!(
+        section_of(&rules, "requirements-dev.txt"),
+        "Configuration Files"
+    );
+}
+
+#[test]
+fn test_custom_rules_control_names_and_order() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    create_dir_all(dir_path.join("src/api"))?;
+    writeln!(
+        File::create(dir_path.join("src/api/routes.rs"))?,
+        "// routes"
+    )?;
+    writeln!(File::create(dir_path.join("src/lib.rs"))?, "// lib")?;
+    writeln!(
+        File::create(dir_path.join("src/api/generated.rs"))?,
+        "// generated"
+    )?;
+    writeln!(File::create(dir_path.join("NOTES.md"))?, "notes")?;
+
+    let categories = CategoryRules {
+        sections: vec![
+            SectionRule {
+                name: "API Layer".to_string(),
+                patterns: vec!["src/api/".to_string(), "!generated.rs".to_string()],
+            },
+            SectionRule {
+                name: "Everything Else".to_string(),
+                patterns: vec![],
+            },
+        ],
+        default_section: "Everything Else".to_string(),
+    };
+
+    let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        categories,
+        root: Some(dir_path.to_path_buf()),
+        ..Default::default()
+    };
+    let content = concatenate_files_with_options(files, &options)?.content;
+
+    let api_section = content.find("Section: API Layer").unwrap();
+    let other_section = content.find("Section: Everything Else").unwrap();
+    assert!(api_section < other_section);
+    assert!(content.find("// routes").unwrap() < other_section);
+    assert!(content.find("// generated").unwrap() > other_section);
+    assert!(content.find("// lib").unwrap() > other_section);
+    assert!(content.find("notes").unwrap() > other_section);
+    assert!(!content.contains("Section: Documentation"));
+
+    Ok(())
+}
+
+#[test]
+fn test_unlisted_default_section_comes_last() {
+    let rules = CategoryRules {
+        sections: vec![SectionRule {
+            name: "Docs".to_string(),
+            patterns: vec!["*.md".to_string()],
+        }],
+        default_section: "Code".to_string(),
+    };
+    let matcher = rules.compile().unwrap();
+
+    assert_eq!(matcher.section_names(), ["Docs", "Code"]);
+    assert_eq!(matcher.categorize(Path::new("main.py")), 1);
+}
+
+#[test]
+fn test_invalid_pattern_is_rejected() {
+    let rules = CategoryRules {
+        sections: vec![SectionRule {
+            name: "Broken".to_string(),
+            patterns: vec!["src/{api,core".to_string()],
+        }],
+        default_section: "Source Files".to_string(),
+    };
+
+    assert!(rules.compile().is_err());
+}
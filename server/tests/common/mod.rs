@@ -0,0 +1,11 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Writes each `(path, content)` of `files` into `dir`.
+pub fn create_test_files(dir: &Path, files: &[(&str, &str)]) -> io::Result<()> {
+    for (path, content) in files {
+        fs::write(dir.join(path), content)?;
+    }
+    Ok(())
+}
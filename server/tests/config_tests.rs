@@ -0,0 +1,128 @@
+use contexter::config::{Config, ProjectConfig};
+use contexter::contexter::OversizePolicy;
+use contexter::formatter::OutputFormat;
+use std::path::PathBuf;
+
+#[test]
+fn test_legacy_project_paths_still_load() {
+    let config: Config = serde_json::from_str(
+        r#"{
+            "projects": { "legacy": "/path/to/legacy" },
+            "port": 3030,
+            "listen_address": "127.0.0.1",
+            "api_keys": {}
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.projects["legacy"],
+        ProjectConfig::new(PathBuf::from("/path/to/legacy"))
+    );
+}
+
+#[test]
+fn test_project_profiles_round_trip() {
+    let mut config = Config::default();
+    config.add_project("plain".to_string(), PathBuf::from("/path/to/plain"));
+    config.projects.insert(
+        "profiled".to_string(),
+        ProjectConfig {
+            extensions: vec!["rs".to_string()],
+            excludes: vec!["generated".to_string()],
+            includes: vec!["src/**".to_string()],
+            max_file_size: Some(65536),
+            format: Some(OutputFormat::Markdown),
+            ..ProjectConfig::new(PathBuf::from("/path/to/profiled"))
+        },
+    );
+
+    let saved = serde_json::to_string_pretty(&config).unwrap();
+    let loaded: Config = serde_json::from_str(&saved).unwrap();
+
+    assert_eq!(loaded.projects, config.projects);
+    assert!(!saved.contains("\"extensions\": []"));
+}
+
+#[test]
+fn test_gather_options_from_profile() {
+    let project = ProjectConfig {
+        extensions: vec!["py".to_string()],
+        includes: vec!["app/**".to_string()],
+        max_file_size: Some(1024),
+        ..ProjectConfig::new(PathBuf::from("/path/to/project"))
+    };
+
+    let options = project.gather_options();
+    assert_eq!(options.extensions, vec!["py".to_string()]);
+    assert_eq!(options.includes, vec!["app/**".to_string()]);
+    assert_eq!(options.max_file_size, Some(1024));
+    assert!(options.excludes.is_empty());
+}
+
+#[test]
+fn test_project_size_limits_override_the_defaults() {
+    let config = Config {
+        max_file_size: Some(4096),
+        max_total_size: Some(65536),
+        ..Default::default()
+    };
+    let project = ProjectConfig {
+        max_file_size: Some(1024),
+        oversized: Some(OversizePolicy::Truncate),
+        ..ProjectConfig::new(PathBuf::from("/path/to/project"))
+    };
+
+    let limits = config.size_limits(Some(&project));
+    assert_eq!(limits.max_file_size, Some(1024));
+    assert_eq!(limits.max_total_size, Some(65536));
+    assert_eq!(limits.oversized, OversizePolicy::Truncate);
+    // Truncated files have to be gathered to be excerpted
+    assert_eq!(config.gather_options(&project).max_file_size, None);
+
+    let limits = config.size_limits(None);
+    assert_eq!(limits.max_file_size, Some(4096));
+    assert_eq!(limits.oversized, OversizePolicy::Skip);
+    let plain = ProjectConfig::new(PathBuf::from("/path/to/plain"));
+    assert_eq!(config.gather_options(&plain).max_file_size, Some(4096));
+}
+
+#[test]
+fn test_default_excludes_can_be_replaced_and_extended() {
+    let mut config = Config::default();
+    let globs = config.default_exclude_globs();
+    assert!(globs.contains(&"node_modules/".to_string()));
+
+    config.extra_excludes = vec!["vendor/".to_string()];
+    let globs = config.default_exclude_globs();
+    assert!(globs.contains(&"node_modules/".to_string()));
+    assert_eq!(globs.last(), Some(&"vendor/".to_string()));
+
+    config.default_excludes = Some(vec![".git/".to_string()]);
+    let project = ProjectConfig::new(PathBuf::from("/path/to/project"));
+    assert_eq!(
+        config.gather_options(&project).default_excludes,
+        Some(vec![".git/".to_string(), "vendor/".to_string()])
+    );
+}
+
+#[test]
+fn test_random_string_redaction_is_opt_in() {
+    let config = Config::default();
+    assert!(config.redaction.enabled);
+    assert!(!config.redaction.high_entropy);
+    assert!(!config.redaction.redaction().unwrap().high_entropy);
+
+    let config: Config = serde_json::from_str(
+        r#"{
+            "projects": {},
+            "port": 3030,
+            "listen_address": "127.0.0.1",
+            "api_keys": {},
+            "redaction": { "high_entropy": true }
+        }"#,
+    )
+    .unwrap();
+    assert!(config.redaction.enabled);
+    assert!(config.redaction.redaction().unwrap().high_entropy);
+}
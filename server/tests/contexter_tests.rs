@@ -1,33 +1,29 @@
-use contexter::contexter::{concatenate_files, gather_relevant_files};
+mod common;
+
+use common::create_test_files;
+use contexter::contexter::{
+    concatenate_files, concatenate_files_with_options, gather_files, gather_files_with_report,
+    gather_relevant_files, BudgetStrategy, ContextOptions, ContexterError, DecodedFile,
+    GatherOptions, SkipReason, SkippedFile,
+};
+use contexter::formatter::{HeaderOptions, OutputFormat};
+use contexter::tokenizer::TokenizerKind;
 use std::fs::File;
 use std::io::Write;
 use tempfile::tempdir;
 
-fn create_test_files(dir_path: &std::path::Path) -> std::io::Result<()> {
-    let file1_path = dir_path.join("test1.txt");
-    let mut file1 = File::create(&file1_path)?;
-    writeln!(file1, "This is test file 1.\nfn test_function() {{}}")?;
-
-    let file2_path = dir_path.join("test2.rs");
-    let mut file2 = File::create(&file2_path)?;
-    writeln!(file2, "This is test file 2.\nstruct TestStruct {{}}")?;
-
-    let file3_path = dir_path.join("test3.txt");
-    let mut file3 = File::create(&file3_path)?;
-    writeln!(file3, "This is test file 3.\nlet x = 42;")?;
-
-    let gitignore_path = dir_path.join(".gitignore");
-    let mut gitignore = File::create(&gitignore_path)?;
-    writeln!(gitignore, "*.ignore")?;
-
-    Ok(())
-}
+const TEST_FILES: &[(&str, &str)] = &[
+    ("test1.txt", "This is test file 1.\nfn test_function() {}\n"),
+    ("test2.rs", "This is test file 2.\nstruct TestStruct {}\n"),
+    ("test3.txt", "This is test file 3.\nlet x = 42;\n"),
+    (".gitignore", "*.ignore\n"),
+];
 
 #[test]
 fn test_gather_relevant_files_basic() -> std::io::Result<()> {
     let dir = tempdir()?;
     let dir_path = dir.path();
-    create_test_files(dir_path)?;
+    create_test_files(dir_path, TEST_FILES)?;
 
     let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
 
@@ -44,7 +40,7 @@ fn test_gather_relevant_files_basic() -> std::io::Result<()> {
 fn test_exclusion_patterns() -> std::io::Result<()> {
     let dir = tempdir()?;
     let dir_path = dir.path();
-    create_test_files(dir_path)?;
+    create_test_files(dir_path, TEST_FILES)?;
 
     // Exclude .txt files
     let files = gather_relevant_files(
@@ -64,7 +60,7 @@ fn test_exclusion_patterns() -> std::io::Result<()> {
 fn test_concatenate_files() -> std::io::Result<()> {
     let dir = tempdir()?;
     let dir_path = dir.path();
-    create_test_files(dir_path)?;
+    create_test_files(dir_path, TEST_FILES)?;
 
     let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
     let (content, _) = concatenate_files(files)?;
@@ -166,7 +162,7 @@ fn test_file_order_and_duplicate_detection() -> std::io::Result<()> {
 fn test_binary_file_skipping() -> std::io::Result<()> {
     let dir = tempdir()?;
     let dir_path = dir.path();
-    create_test_files(dir_path)?;
+    create_test_files(dir_path, TEST_FILES)?;
 
     // Create a binary file
     let binary_file_path = dir_path.join("binary_file.bin");
@@ -184,7 +180,7 @@ fn test_binary_file_skipping() -> std::io::Result<()> {
 fn test_built_in_exclusions() -> std::io::Result<()> {
     let dir = tempdir()?;
     let dir_path = dir.path();
-    create_test_files(dir_path)?;
+    create_test_files(dir_path, TEST_FILES)?;
 
     // Create a file that should be excluded by default
     let node_modules_path = dir_path.join("node_modules");
@@ -200,3 +196,631 @@ fn test_built_in_exclusions() -> std::io::Result<()> {
 
     Ok(())
 }
+
+fn create_budget_files(dir_path: &std::path::Path) -> std::io::Result<()> {
+    File::create(dir_path.join("a_small.rs"))?.write_all(b"fn small() {}\n")?;
+    File::create(dir_path.join("b_large.rs"))?.write_all("let x = 1;\n".repeat(200).as_bytes())?;
+    File::create(dir_path.join("c_small.rs"))?.write_all(b"fn other() {}\n")?;
+    Ok(())
+}
+
+#[test]
+fn test_token_budget_stop() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    create_budget_files(dir_path)?;
+
+    let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        max_tokens: Some(150),
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+
+    assert!(output.tokens <= 150);
+    assert!(output.content.contains("fn small() {}"));
+    assert!(!output.content.contains("fn other() {}"));
+    assert_eq!(output.files.len(), 1);
+    assert_eq!(output.dropped.len(), 2);
+    assert!(output.dropped[0].path.ends_with("b_large.rs"));
+    assert!(output.dropped[1].path.ends_with("c_small.rs"));
+
+    Ok(())
+}
+
+#[test]
+fn test_token_budget_truncate() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    create_budget_files(dir_path)?;
+
+    let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        max_tokens: Some(300),
+        budget_strategy: BudgetStrategy::Truncate,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+
+    assert!(output.tokens <= 300);
+    assert!(output.content.contains("let x = 1;"));
+    assert!(output.content.contains("truncated to fit the token budget"));
+    assert_eq!(output.truncated.len(), 1);
+    assert!(output.truncated[0].ends_with("b_large.rs"));
+    assert!(output
+        .dropped
+        .iter()
+        .any(|d| d.path.ends_with("c_small.rs")));
+
+    Ok(())
+}
+
+#[test]
+fn test_token_budget_prioritize() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    create_budget_files(dir_path)?;
+
+    let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        max_tokens: Some(300),
+        tokenizer: TokenizerKind::Cl100k,
+        budget_strategy: BudgetStrategy::Prioritize,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+
+    assert!(output.tokens <= 300);
+    assert!(output.content.contains("fn small() {}"));
+    assert!(output.content.contains("fn other() {}"));
+    assert_eq!(output.dropped.len(), 1);
+    assert!(output.dropped[0].path.ends_with("b_large.rs"));
+
+    Ok(())
+}
+
+#[test]
+fn test_token_budget_prioritize_with_duplicates() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    create_budget_files(dir_path)?;
+    // Identical to a_small.rs, so it is left out, and the selections of the files after it
+    // must still line up with them
+    File::create(dir_path.join("b_copy.rs"))?.write_all(b"fn small() {}\n")?;
+
+    let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        max_tokens: Some(300),
+        tokenizer: TokenizerKind::Cl100k,
+        budget_strategy: BudgetStrategy::Prioritize,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+
+    assert!(output.tokens <= 300);
+    assert_eq!(output.files.len(), 2);
+    assert!(output.files[0].ends_with("a_small.rs"));
+    assert!(output.files[1].ends_with("c_small.rs"));
+    assert_eq!(output.dropped.len(), 1);
+    assert!(output.dropped[0].path.ends_with("b_large.rs"));
+    assert!(output
+        .skipped
+        .iter()
+        .any(|skipped| skipped.reason == SkipReason::Duplicate
+            && skipped.path.ends_with("b_copy.rs")));
+
+    Ok(())
+}
+
+#[test]
+fn test_gather_files_with_includes_and_size_limit() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    std::fs::create_dir_all(dir_path.join("src/generated"))?;
+    std::fs::create_dir_all(dir_path.join("docs"))?;
+    writeln!(
+        File::create(dir_path.join("src/lib.rs"))?,
+        "pub fn lib() {{}}"
+    )?;
+    writeln!(
+        File::create(dir_path.join("src/generated/api.rs"))?,
+        "// generated"
+    )?;
+    writeln!(
+        File::create(dir_path.join("src/big.rs"))?,
+        "{}",
+        "x".repeat(4096)
+    )?;
+    writeln!(File::create(dir_path.join("docs/guide.md"))?, "# Guide")?;
+
+    let options = GatherOptions {
+        includes: vec!["src/".to_string(), "!src/generated/".to_string()],
+        max_file_size: Some(1024),
+        ..Default::default()
+    };
+    let files = gather_files(dir_path, &[], &options)?;
+
+    assert_eq!(files, vec![dir_path.join("src/lib.rs")]);
+
+    // Restricting to a sub-path still matches includes relative to the root
+    let files = gather_files(dir_path, &["src/generated".to_string()], &options)?;
+    assert!(files.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_gather_files_with_glob_paths() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    std::fs::create_dir_all(dir_path.join("src/api"))?;
+    std::fs::create_dir_all(dir_path.join("docs"))?;
+    writeln!(File::create(dir_path.join("src/lib.rs"))?, "pub mod api;")?;
+    writeln!(File::create(dir_path.join("src/api/mod.rs"))?, "// api")?;
+    writeln!(File::create(dir_path.join("src/api/notes.md"))?, "# API")?;
+    writeln!(File::create(dir_path.join("docs/guide.md"))?, "# Guide")?;
+
+    // Plain paths and globs are combined
+    let paths = vec!["docs".to_string(), "src/**/*.rs".to_string()];
+    let files = gather_files(dir_path, &paths, &GatherOptions::default())?;
+
+    assert_eq!(
+        files,
+        vec![
+            dir_path.join("docs/guide.md"),
+            dir_path.join("src/api/mod.rs"),
+            dir_path.join("src/lib.rs"),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_default_excludes_are_relative_to_the_root() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path().join("build");
+    std::fs::create_dir_all(root.join("target/debug"))?;
+    std::fs::create_dir_all(root.join("src"))?;
+    writeln!(
+        File::create(root.join("src/targeting.rs"))?,
+        "fn aim() {{}}"
+    )?;
+    writeln!(File::create(root.join("target/debug/out.rs"))?, "// built")?;
+    writeln!(File::create(root.join("Cargo.lock"))?, "# lock")?;
+
+    // A project living under a directory named like an exclude is still gathered
+    let files = gather_files(&root, &[], &GatherOptions::default())?;
+    assert_eq!(files, vec![root.join("src/targeting.rs")]);
+
+    // Turning the defaults off gathers everything
+    let options = GatherOptions {
+        default_excludes: Some(Vec::new()),
+        ..Default::default()
+    };
+    let files = gather_files(&root, &[], &options)?;
+    assert_eq!(files.len(), 3);
+
+    // Custom defaults replace the built-in ones
+    let options = GatherOptions {
+        default_excludes: Some(vec!["src/".to_string()]),
+        ..Default::default()
+    };
+    let files = gather_files(&root, &[], &options)?;
+    assert_eq!(
+        files,
+        vec![root.join("Cargo.lock"), root.join("target/debug/out.rs")]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_invalid_patterns_are_reported() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let dir_path = dir.path();
+    create_test_files(dir_path, TEST_FILES)?;
+
+    let result = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec!["(".to_string()]);
+    match result {
+        Err(ContexterError::InvalidPattern { pattern, .. }) => assert_eq!(pattern, "("),
+        other => panic!("expected an invalid pattern error, got {:?}", other),
+    }
+
+    let options = GatherOptions {
+        includes: vec!["src/{api".to_string()],
+        ..Default::default()
+    };
+    let error = gather_files(dir_path, &[], &options).unwrap_err();
+    assert_eq!(error.pattern(), Some("src/{api"));
+
+    Ok(())
+}
+
+#[test]
+fn test_files_are_decoded_from_their_encoding() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    std::fs::write(root.join("latin1.txt"), b"caf\xe9 cr\xe8me\n")?;
+    let utf16: Vec<u8> = [0xFF, 0xFE]
+        .into_iter()
+        .chain("na\u{ef}ve\n".encode_utf16().flat_map(u16::to_le_bytes))
+        .collect();
+    std::fs::write(root.join("utf16.txt"), utf16)?;
+    std::fs::write(root.join("plain.txt"), "plain\n")?;
+    std::fs::write(
+        root.join("garbage.txt"),
+        [0x81, 0x8d, 0x01, 0x02].repeat(16),
+    )?;
+
+    let files = gather_files(root, &[], &GatherOptions::default())?;
+    assert_eq!(files.len(), 4);
+
+    let output = concatenate_files_with_options(files, &ContextOptions::default())?;
+    assert!(output.content.contains("café crème"));
+    assert!(output.content.contains("naïve"));
+    assert!(output.content.contains("plain"));
+    assert_eq!(output.files.len(), 3);
+
+    let path = |name: &str| root.join(name).to_string_lossy().to_string();
+    assert_eq!(
+        output.decoded,
+        vec![
+            DecodedFile {
+                path: path("latin1.txt"),
+                encoding: "windows-1252".to_string(),
+            },
+            DecodedFile {
+                path: path("utf16.txt"),
+                encoding: "UTF-16LE".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        output.skipped,
+        vec![SkippedFile {
+            path: path("garbage.txt"),
+            reason: SkipReason::InvalidEncoding,
+            detail: None,
+        }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_gather_report_lists_skipped_files_with_reasons() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    std::fs::create_dir_all(root.join("node_modules/pkg"))?;
+    std::fs::create_dir_all(root.join("src"))?;
+    std::fs::write(
+        root.join("node_modules/pkg/index.js"),
+        "module.exports = {};\n",
+    )?;
+    std::fs::write(root.join("src/main.rs"), "fn main() {}\n")?;
+    std::fs::write(root.join("src/copy.rs"), "fn main() {}\n")?;
+    std::fs::write(root.join("src/generated.rs"), "// generated\n")?;
+    std::fs::write(root.join("src/big.rs"), "x".repeat(2048))?;
+    std::fs::write(root.join("logo.png"), [0x89, b'P', b'N', b'G'])?;
+
+    let options = GatherOptions {
+        excludes: vec!["generated".to_string()],
+        max_file_size: Some(1024),
+        ..Default::default()
+    };
+    let gathered = gather_files_with_report(root, &[], &options)?;
+    assert_eq!(
+        gathered.files,
+        vec![root.join("src/copy.rs"), root.join("src/main.rs")]
+    );
+
+    let reasons: Vec<(String, SkipReason)> = gathered
+        .skipped
+        .iter()
+        .map(|skipped| (skipped.path.clone(), skipped.reason))
+        .collect();
+    let path = |name: &str| root.join(name).to_string_lossy().to_string();
+    assert_eq!(
+        reasons,
+        vec![
+            (path("logo.png"), SkipReason::Binary),
+            (path("node_modules"), SkipReason::Excluded),
+            (path("src/big.rs"), SkipReason::TooLarge),
+            (path("src/generated.rs"), SkipReason::Excluded),
+        ]
+    );
+    assert_eq!(
+        gathered.skipped[3].detail.as_deref(),
+        Some("matches exclude pattern 'generated'")
+    );
+
+    // Duplicates are only found once the files are read
+    let output = concatenate_files_with_options(gathered.files, &ContextOptions::default())?
+        .with_gather_report(gathered.skipped);
+    assert_eq!(output.files, vec![path("src/copy.rs")]);
+    assert_eq!(output.skipped.len(), 5);
+    assert_eq!(output.skipped[4].path, path("src/main.rs"));
+    assert_eq!(output.skipped[4].reason, SkipReason::Duplicate);
+
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unreadable_files_are_skipped() -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempdir()?;
+    let root = dir.path();
+    std::fs::write(root.join("readable.txt"), "readable\n")?;
+    let secret = root.join("secret.txt");
+    std::fs::write(&secret, "secret\n")?;
+    std::fs::set_permissions(&secret, std::fs::Permissions::from_mode(0o000))?;
+    if File::open(&secret).is_ok() {
+        // Permissions are not enforced, e.g. when running as root
+        return Ok(());
+    }
+
+    let gathered = gather_files_with_report(root, &[], &GatherOptions::default())?;
+    assert_eq!(gathered.files, vec![root.join("readable.txt")]);
+    assert_eq!(gathered.skipped.len(), 1);
+    assert_eq!(gathered.skipped[0].reason, SkipReason::PermissionDenied);
+
+    // A file that becomes unreadable after gathering is skipped rather than failing the run
+    let files = vec![root.join("readable.txt"), secret.clone()];
+    let output = concatenate_files_with_options(files, &ContextOptions::default())?;
+    assert!(output.content.contains("readable"));
+    assert_eq!(output.skipped[0].reason, SkipReason::PermissionDenied);
+
+    Ok(())
+}
+
+#[test]
+fn test_duplicates_point_to_the_file_they_copy() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let original = dir.path().join("a.rs");
+    let copy = dir.path().join("b.rs");
+    std::fs::write(&original, "hello\n")?;
+    std::fs::write(&copy, "hello\n")?;
+    std::fs::write(dir.path().join("c.rs"), "fn c() {}\n")?;
+
+    let files = gather_files(dir.path(), &[], &GatherOptions::default())?;
+    let output = concatenate_files_with_options(files.clone(), &ContextOptions::default())?;
+
+    // The digest is the SHA-256 of the file, as `sha256sum` prints it
+    let original_path = original.to_string_lossy().to_string();
+    assert_eq!(
+        output.digests[&original_path].to_string(),
+        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+    );
+    assert_eq!(output.digests.len(), 2);
+    assert_eq!(
+        output.skipped,
+        vec![SkippedFile::new(
+            &copy,
+            SkipReason::Duplicate,
+            Some(format!("identical to {}", original_path))
+        )]
+    );
+    assert!(output.content.contains("File: b.rs\nIdentical to: a.rs\n"));
+    assert_eq!(output.content.matches("hello").count(), 1);
+
+    // No note points to a file that did not make it into the output
+    let options = ContextOptions {
+        max_tokens: Some(5),
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+    assert!(output.files.is_empty());
+    assert!(!output.content.contains("Identical to"));
+    assert_eq!(output.skipped[0].reason, SkipReason::Duplicate);
+
+    Ok(())
+}
+
+#[test]
+fn test_tree_opens_the_output() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path().join("demo");
+    std::fs::create_dir_all(root.join("src/bin"))?;
+    std::fs::write(root.join("README.md"), "# Demo\n")?;
+    std::fs::write(root.join("src/lib.rs"), "pub fn demo() {}\n")?;
+    std::fs::write(root.join("src/bin/cli.rs"), "fn main() {}\n")?;
+    std::fs::write(root.join("logo.png"), vec![0u8; 2048])?;
+    let gathered = gather_files_with_report(&root, &[], &GatherOptions::default())?;
+
+    let options = ContextOptions {
+        tree: true,
+        tree_skipped: gathered.skipped.clone(),
+        root: Some(root.clone()),
+        format: OutputFormat::Markdown,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(gathered.files.clone(), &options)?;
+    let tree = "## Project Structure\n\n```\ndemo/\n\
+        |-- README.md (7 B)\n\
+        |-- logo.png (2.0 KB) [binary]\n\
+        `-- src/\n    \
+            |-- bin/\n    \
+            |   `-- cli.rs (13 B)\n    \
+            `-- lib.rs (17 B)\n\
+        ```\n\n";
+    assert!(output.content.starts_with(tree), "{}", output.content);
+    assert_eq!(output.files.len(), 3);
+
+    // Only the tree is sent, and files are neither read nor reported
+    let options = ContextOptions {
+        tree_only: true,
+        root: Some(root.clone()),
+        format: OutputFormat::Json,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(gathered.files, &options)?;
+    let elements: Vec<serde_json::Value> = serde_json::from_str(&output.content)?;
+    assert_eq!(elements.len(), 1);
+    assert!(elements[0]["structure"]
+        .as_str()
+        .unwrap()
+        .ends_with("`-- lib.rs (17 B)\n"));
+    assert!(output.files.is_empty());
+    assert!(output.skipped.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_tree_over_the_token_budget() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path().join("demo");
+    std::fs::create_dir_all(root.join("src"))?;
+    for i in 0..40 {
+        let name = format!("src/module_with_a_long_name_{:02}.rs", i);
+        std::fs::write(root.join(name), format!("pub fn f{}() {{}}\n", i))?;
+    }
+    let files = gather_files(&root, &[], &GatherOptions::default())?;
+
+    // A tree that does not fit on its own is cut down to the budget, leaving nothing for files
+    let options = ContextOptions {
+        tree: true,
+        root: Some(root.clone()),
+        format: OutputFormat::Markdown,
+        max_tokens: Some(100),
+        budget_strategy: BudgetStrategy::Truncate,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    assert!(output.tokens <= 100, "{} tokens", output.tokens);
+    assert!(output
+        .content
+        .starts_with("## Project Structure\n\n```\ndemo/\n"));
+    assert!(output
+        .content
+        .contains("[... truncated to fit the token budget ...]"));
+    assert!(output.files.is_empty());
+    assert_eq!(output.dropped.len(), 40);
+
+    // A tree of which nothing fits is left out
+    let options = ContextOptions {
+        max_tokens: Some(5),
+        ..options
+    };
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    assert!(!output.content.contains("Project Structure"));
+    assert!(output.tokens <= 5);
+
+    // Whatever the budget, the output keeps within it, including the file cut down to fit after
+    // the tree and many others
+    for max_tokens in (400..1200).step_by(23) {
+        let options = ContextOptions {
+            max_tokens: Some(max_tokens),
+            format: OutputFormat::Xml,
+            ..options.clone()
+        };
+        let output = concatenate_files_with_options(files.clone(), &options)?;
+        assert!(
+            output.tokens <= max_tokens,
+            "{} tokens for a budget of {}",
+            output.tokens,
+            max_tokens
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_truncated_file_within_budget_in_every_format() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    // Content the formats have to escape costs more once rendered than on its own
+    let line = "if a < b && c > \"d\" { s = \"C:\\\\path\\n\"; } // <tag attr='x'> & more\n";
+    std::fs::write(root.join("escaped.rs"), line.repeat(200))?;
+    let files = gather_files(root, &[], &GatherOptions::default())?;
+
+    for format in [
+        OutputFormat::Plain,
+        OutputFormat::Markdown,
+        OutputFormat::Xml,
+        OutputFormat::Json,
+    ] {
+        for tokenizer in [TokenizerKind::Estimate, TokenizerKind::Cl100k] {
+            for max_tokens in [150, 300, 1000] {
+                let options = ContextOptions {
+                    format,
+                    tokenizer,
+                    max_tokens: Some(max_tokens),
+                    budget_strategy: BudgetStrategy::Truncate,
+                    ..Default::default()
+                };
+                let output = concatenate_files_with_options(files.clone(), &options)?;
+                assert!(
+                    output.tokens <= max_tokens,
+                    "{:?} with {:?}: {} tokens for a budget of {}",
+                    format,
+                    tokenizer,
+                    output.tokens,
+                    max_tokens
+                );
+                assert!(output.content.contains("truncated to fit the token budget"));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_headers_show_relative_paths_and_rfc3339_times() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    std::fs::create_dir(root.join("src"))?;
+    let main = root.join("src/main.rs");
+    std::fs::write(&main, "fn main() {}\n")?;
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    File::options()
+        .write(true)
+        .open(&main)?
+        .set_modified(modified)?;
+
+    let options = ContextOptions {
+        root: Some(root.to_path_buf()),
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(vec![main.clone()], &options)?;
+    assert!(output
+        .content
+        .contains("File: src/main.rs\nSize: 13 bytes\nLast Modified: 2023-11-14T22:13:20Z\n"));
+    assert!(!output.content.contains(&*root.to_string_lossy()));
+    // The report still names files by where they are
+    assert_eq!(output.files, vec![main.to_string_lossy().to_string()]);
+
+    // Without sizes and times, the output only changes with the content
+    let options = ContextOptions {
+        root: Some(root.to_path_buf()),
+        header: HeaderOptions {
+            omit_size: true,
+            omit_modified: true,
+        },
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(vec![main.clone()], &options)?;
+    assert!(output.content.contains(
+        "========================================\n\
+        File: src/main.rs\n\
+        ========================================\n\
+        fn main() {}\n"
+    ));
+    std::fs::write(&main, "fn main() {}\n")?;
+    let rewritten = concatenate_files_with_options(vec![main], &options)?;
+    assert_eq!(rewritten.content, output.content);
+
+    let options = ContextOptions {
+        format: OutputFormat::Json,
+        ..options
+    };
+    let output = concatenate_files_with_options(vec![root.join("src/main.rs")], &options)?;
+    let records: Vec<serde_json::Value> = serde_json::from_str(&output.content)?;
+    assert_eq!(
+        records[0],
+        serde_json::json!({ "path": "src/main.rs", "content": "fn main() {}\n" })
+    );
+    Ok(())
+}
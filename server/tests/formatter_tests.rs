@@ -0,0 +1,148 @@
+mod common;
+
+use common::create_test_files;
+use contexter::contexter::{concatenate_files_with_options, gather_relevant_files, ContextOptions};
+use contexter::formatter::OutputFormat;
+use std::fs::File;
+use std::io::Write;
+use tempfile::tempdir;
+
+fn gather_with_format(dir_path: &std::path::Path, format: OutputFormat) -> std::io::Result<String> {
+    let files = gather_relevant_files(dir_path.to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        format,
+        ..Default::default()
+    };
+    Ok(concatenate_files_with_options(files, &options)?.content)
+}
+
+const TEST_FILES: &[(&str, &str)] = &[
+    ("main.rs", "fn main() {}\n"),
+    ("README.md", "# Readme\n```sh\nls\n```\n"),
+    ("script.py", "print(\"<hi>\")\n"),
+];
+
+#[test]
+fn test_markdown_format() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    create_test_files(dir.path(), TEST_FILES)?;
+
+    let content = gather_with_format(dir.path(), OutputFormat::Markdown)?;
+
+    assert!(content.contains("## Documentation"));
+    assert!(content.contains("## Source Files"));
+    assert!(content.contains("```rust\nfn main() {}\n```"));
+    assert!(content.contains("```python\nprint(\"<hi>\")\n```"));
+    // A file containing a fence is wrapped in a longer one
+    assert!(content.contains("````markdown\n# Readme\n```sh\nls\n```\n````"));
+    assert!(!content.contains("========================================"));
+
+    Ok(())
+}
+
+#[test]
+fn test_xml_format() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    create_test_files(dir.path(), TEST_FILES)?;
+
+    let content = gather_with_format(dir.path(), OutputFormat::Xml)?;
+
+    assert!(content.starts_with("<documents>\n"));
+    assert!(content.ends_with("</documents>\n"));
+    assert!(content.contains("<document index=\"1\">"));
+    assert!(content.contains("<document index=\"3\">"));
+    assert!(
+        content.contains("main.rs</source>\n<document_content>\nfn main() {}\n</document_content>")
+    );
+    // Markup in a file's content is escaped like everything else
+    assert!(content.contains("<document_content>\nprint(\"&lt;hi&gt;\")\n</document_content>"));
+    assert!(!content.contains("<hi>"));
+
+    let xml = OutputFormat::Xml.formatter();
+    assert_eq!(
+        xml.diff(std::path::Path::new("a&b.rs"), "-if a < b {}\n+if a > b {}", 0),
+        "<document index=\"1\">\n<source>a&amp;b.rs</source>\n<diff>\n-if a &lt; b {}\n+if a &gt; b {}\n</diff>\n</document>\n"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_json_format() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    create_test_files(dir.path(), TEST_FILES)?;
+
+    let content = gather_with_format(dir.path(), OutputFormat::Json)?;
+    let records: Vec<serde_json::Value> = serde_json::from_str(&content)?;
+
+    assert_eq!(records.len(), 3);
+    let main = records
+        .iter()
+        .find(|r| r["path"].as_str().unwrap().ends_with("main.rs"))
+        .unwrap();
+    assert_eq!(main["content"], "fn main() {}\n");
+    assert_eq!(main["size"], 13);
+    assert!(main["modified"].as_str().unwrap().ends_with('Z'));
+
+    Ok(())
+}
+
+#[test]
+fn test_json_format_within_budget_stays_valid() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    create_test_files(dir.path(), TEST_FILES)?;
+    writeln!(
+        File::create(dir.path().join("big.rs"))?,
+        "{}",
+        "let x = 1;\n".repeat(500)
+    )?;
+
+    let files = gather_relevant_files(dir.path().to_str().unwrap(), vec![], vec![])?;
+    let options = ContextOptions {
+        format: OutputFormat::Json,
+        max_tokens: Some(200),
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+    let records: Vec<serde_json::Value> = serde_json::from_str(&output.content)?;
+
+    assert!(output.tokens <= 200);
+    assert_eq!(records.len(), output.files.len());
+    assert!(!output.dropped.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_output_format_parsing() {
+    assert_eq!("md".parse::<OutputFormat>(), Ok(OutputFormat::Markdown));
+    assert_eq!("XML".parse::<OutputFormat>(), Ok(OutputFormat::Xml));
+    assert_eq!("plain".parse::<OutputFormat>(), Ok(OutputFormat::Plain));
+    assert!("yaml".parse::<OutputFormat>().is_err());
+}
+
+#[test]
+fn test_duplicates_are_noted_in_every_format() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    create_test_files(dir.path(), TEST_FILES)?;
+    writeln!(File::create(dir.path().join("other.rs"))?, "fn main() {{}}")?;
+
+    let markdown = gather_with_format(dir.path(), OutputFormat::Markdown)?;
+    assert!(markdown.contains("other.rs\n\nIdentical to `"));
+
+    let xml = gather_with_format(dir.path(), OutputFormat::Xml)?;
+    assert!(xml.contains("<document index=\"4\">"));
+    assert!(xml.contains("main.rs</identical_to>"));
+
+    let json = gather_with_format(dir.path(), OutputFormat::Json)?;
+    let records: Vec<serde_json::Value> = serde_json::from_str(&json)?;
+    assert_eq!(records.len(), 4);
+    let note = records
+        .iter()
+        .find(|r| r["path"].as_str().unwrap().ends_with("other.rs"))
+        .unwrap();
+    assert!(note["identical_to"].as_str().unwrap().ends_with("main.rs"));
+    assert!(note.get("content").is_none());
+
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use contexter::config::Config;
+use contexter::contexter::{
+    concatenate_files_with_options, gather_files, gather_files_with_report, ContextOptions,
+    ContexterError, GatherOptions,
+};
+use contexter::formatter::OutputFormat;
+use contexter::git::GitChanges;
+use contexter::project::{run_project, ContexterRequest};
+use git2::{IndexAddOption, Repository, Signature};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tempfile::tempdir;
+
+fn write(root: &Path, path: &str, content: &str) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+/// Stages every file and commits it on the current branch.
+fn commit_all(repository: &Repository, message: &str) -> Result<(), git2::Error> {
+    let mut index = repository.index()?;
+    index.add_all(["*"], IndexAddOption::DEFAULT, None)?;
+    index.write()?;
+    let tree = repository.find_tree(index.write_tree()?)?;
+    let signature = Signature::now("Test", "test@example.com")?;
+    let parent = repository
+        .head()
+        .ok()
+        .and_then(|head| head.peel_to_commit().ok());
+    let parents: Vec<_> = parent.iter().collect();
+    repository.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )?;
+    Ok(())
+}
+
+fn relative(root: &Path, files: &[PathBuf]) -> Vec<String> {
+    files
+        .iter()
+        .map(|file| {
+            file.strip_prefix(root)
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        })
+        .collect()
+}
+
+#[test]
+fn test_gathering_changed_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    let repository = Repository::init(root)?;
+    write(root, "README.md", "# Demo\n");
+    write(root, "src/main.rs", "fn main() {}\n");
+    write(root, "src/lib.rs", "pub fn a() {}\n");
+    write(root, "src/old.rs", "pub fn old() {}\n");
+    commit_all(&repository, "Initial commit")?;
+    let initial = repository.head()?.peel_to_commit()?.id().to_string();
+
+    // Committed since the first commit: a change and a deletion
+    write(root, "src/lib.rs", "pub fn a() {}\npub fn b() {}\n");
+    fs::remove_file(root.join("src/old.rs"))?;
+    commit_all(&repository, "Add b")?;
+    // Staged
+    write(root, "README.md", "# Demo\n\nStaged.\n");
+    let mut index = repository.index()?;
+    index.add_path(Path::new("README.md"))?;
+    index.write()?;
+    // Not staged, and untracked
+    write(root, "src/main.rs", "fn main() { run() }\n");
+    write(root, "notes/todo.txt", "Ship it\n");
+
+    let gather = |changes: GitChanges| -> Result<Vec<String>, ContexterError> {
+        let options = GatherOptions {
+            git: Some(changes),
+            ..Default::default()
+        };
+        Ok(relative(root, &gather_files(root, &[], &options)?))
+    };
+    assert_eq!(gather(GitChanges::Staged)?, vec!["README.md"]);
+    assert_eq!(
+        gather(GitChanges::Uncommitted)?,
+        vec!["README.md", "notes/todo.txt", "src/main.rs"]
+    );
+    assert_eq!(
+        gather(GitChanges::Since(initial.clone()))?,
+        vec!["README.md", "notes/todo.txt", "src/lib.rs", "src/main.rs"]
+    );
+
+    // The usual filters still apply to the changed files
+    let options = GatherOptions {
+        git: Some(GitChanges::Uncommitted),
+        extensions: vec!["rs".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(
+        relative(root, &gather_files(root, &[], &options)?),
+        vec!["src/main.rs"]
+    );
+
+    // A project in a subdirectory of the repository only sees its own changes
+    let src = root.join("src");
+    let options = GatherOptions {
+        git: Some(GitChanges::Since(initial)),
+        ..Default::default()
+    };
+    assert_eq!(
+        relative(&src, &gather_files(&src, &[], &options)?),
+        vec!["lib.rs", "main.rs"]
+    );
+
+    let options = GatherOptions {
+        git: Some(GitChanges::Since("no-such-branch".to_string())),
+        ..Default::default()
+    };
+    assert!(matches!(
+        gather_files(root, &[], &options),
+        Err(ContexterError::Git(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_diffs_follow_the_changed_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    let repository = Repository::init(root)?;
+    write(root, "src/lib.rs", "pub fn a() {}\n");
+    commit_all(&repository, "Initial commit")?;
+    write(root, "src/lib.rs", "pub fn a() {}\npub fn b() {}\n");
+
+    let changes = GitChanges::Uncommitted;
+    let gather = GatherOptions {
+        git: Some(changes.clone()),
+        ..Default::default()
+    };
+    let files = gather_files(root, &[], &gather)?;
+    let options = ContextOptions {
+        format: OutputFormat::Markdown,
+        root: Some(root.to_path_buf()),
+        diffs: Some(changes),
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    let diff = output.content.split("## Changes\n\n").nth(1).unwrap();
+    assert!(diff.starts_with("### src/lib.rs\n\n```diff\n"));
+    assert!(diff.contains("@@ -1 +1,2 @@\n pub fn a() {}\n+pub fn b() {}\n```\n"));
+
+    // Diffs that do not fit within the budget are dropped like files
+    let budget = ContextOptions {
+        max_tokens: Some(output.tokens - 5),
+        ..options
+    };
+    let output = concatenate_files_with_options(files, &budget)?;
+    assert!(!output.content.contains("## Changes"));
+    assert_eq!(output.files.len(), 1);
+    assert_eq!(output.dropped.len(), 1);
+    assert!(output.dropped[0].diff);
+    Ok(())
+}
+
+#[test]
+fn test_diffs_between_revisions_for_review() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    let repository = Repository::init(root)?;
+    let lines: Vec<String> = (1..=10).map(|i| format!("line {}", i)).collect();
+    write(root, "notes.txt", &(lines.join("\n") + "\n"));
+    write(root, "other.txt", "unchanged\n");
+    commit_all(&repository, "Initial commit")?;
+    let initial = repository.head()?.peel_to_commit()?.id().to_string();
+    write(
+        root,
+        "notes.txt",
+        &(lines.join("\n").replace("line 5", "line five") + "\n"),
+    );
+    commit_all(&repository, "Spell out five")?;
+    // Not committed, so left out of the comparison between the two commits
+    write(root, "other.txt", "changed\n");
+
+    let changes = GitChanges::comparing(Some(initial), Some("HEAD".to_string()))?;
+    let gather = GatherOptions {
+        git: Some(changes.clone()),
+        ..Default::default()
+    };
+    let files = gather_files(root, &[], &gather)?;
+    assert_eq!(relative(root, &files), vec!["notes.txt"]);
+
+    let options = ContextOptions {
+        format: OutputFormat::Markdown,
+        root: Some(root.to_path_buf()),
+        diffs: Some(changes),
+        diff_context: Some(1),
+        diffs_only: true,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    assert!(output.files.is_empty());
+    assert!(output
+        .content
+        .starts_with("## Changes\n\n### notes.txt\n\n```diff\n"));
+    assert!(output
+        .content
+        .contains("@@ -4,3 +4,3 @@ line 3\n line 4\n-line 5\n+line five\n line 6\n```\n"));
+
+    // The whole file can come along with its diff
+    let options = ContextOptions {
+        diffs_only: false,
+        ..options
+    };
+    let output = concatenate_files_with_options(files, &options)?;
+    assert_eq!(output.files.len(), 1);
+    assert!(output.content.contains("line 10\n"));
+    assert!(output.content.contains("## Changes"));
+
+    // A revision to compare to needs one to compare from
+    assert!(GitChanges::comparing(None, Some("HEAD".to_string())).is_err());
+    assert_eq!(GitChanges::comparing(None, None)?, GitChanges::Uncommitted);
+    Ok(())
+}
+
+#[test]
+fn test_diffs_of_files_missing_from_the_working_tree() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    let repository = Repository::init(root)?;
+    write(root, "a.txt", "a at first\n");
+    write(root, "gone.txt", "bye\n");
+    commit_all(&repository, "Initial commit")?;
+    let initial = repository.head()?.peel_to_commit()?.id().to_string();
+    write(root, "a.txt", "a at second\n");
+    fs::remove_file(root.join("gone.txt"))?;
+    write(root, "new.txt", "new\n");
+    write(root, "new.md", "not selected\n");
+    commit_all(&repository, "Second commit")?;
+    // Uncommitted: a change, and a file deleted from the working tree
+    write(root, "a.txt", "a in the working tree\n");
+    fs::remove_file(root.join("new.txt"))?;
+    fs::remove_file(root.join("new.md"))?;
+
+    // A deleted file has nothing to gather, but its diff is still sent
+    let changes = GitChanges::Uncommitted;
+    let gather = GatherOptions {
+        git: Some(changes.clone()),
+        extensions: vec!["txt".to_string()],
+        ..Default::default()
+    };
+    let gathered = gather_files_with_report(root, &[], &gather)?;
+    assert_eq!(relative(root, &gathered.files), vec!["a.txt"]);
+    assert_eq!(relative(root, &gathered.removed), vec!["new.txt"]);
+    let options = ContextOptions {
+        format: OutputFormat::Markdown,
+        root: Some(root.to_path_buf()),
+        diffs: Some(changes),
+        diffs_only: true,
+        removed: gathered.removed,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(gathered.files, &options)?;
+    assert!(output.content.contains("### new.txt\n\n```diff\n"));
+    assert!(output.content.contains("@@ -1 +0,0 @@\n-new\n"));
+    assert!(!output.content.contains("new.md"));
+
+    // Between revisions, the files are read as they are at the second one, including files
+    // that are no longer in the working tree, and files deleted by then only have diffs
+    let changes = GitChanges::comparing(Some(initial), Some("HEAD".to_string()))?;
+    let gather = GatherOptions {
+        git: Some(changes.clone()),
+        extensions: vec!["txt".to_string()],
+        ..Default::default()
+    };
+    let gathered = gather_files_with_report(root, &[], &gather)?;
+    assert_eq!(relative(root, &gathered.files), vec!["a.txt"]);
+    assert_eq!(
+        relative(root, &gathered.removed),
+        vec!["gone.txt", "new.txt"]
+    );
+    let options = ContextOptions {
+        format: OutputFormat::Markdown,
+        root: Some(root.to_path_buf()),
+        revision: changes.revision().map(String::from),
+        diffs: Some(changes),
+        removed: gathered.removed,
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(gathered.files, &options)?;
+    assert_eq!(
+        relative(
+            root,
+            &output.files.iter().map(PathBuf::from).collect::<Vec<_>>()
+        ),
+        vec!["a.txt", "new.txt"]
+    );
+    assert!(output.content.contains("a at second\n"));
+    assert!(!output.content.contains("a in the working tree"));
+    assert!(output.content.contains("### gone.txt\n\n```diff\n"));
+    assert!(output.content.contains("-bye\n"));
+    Ok(())
+}
+
+#[test]
+fn test_request_include_filters_deleted_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    let repository = Repository::init(root)?;
+    write(root, "src/lib.rs", "pub fn a() {}\n");
+    write(root, "docs/old.md", "# Old\n");
+    commit_all(&repository, "Initial commit")?;
+    write(root, "src/lib.rs", "pub fn a() {}\npub fn b() {}\n");
+    fs::remove_file(root.join("docs/old.md"))?;
+
+    let mut config = Config::default();
+    config.add_project("demo".to_string(), root.to_path_buf());
+    let request = ContexterRequest {
+        include: Some(vec!["src/**".to_string()]),
+        format: Some(OutputFormat::Markdown),
+        git: Some(GitChanges::Uncommitted),
+        git_diff: Some(true),
+        ..Default::default()
+    };
+    let output = run_project(&config, "demo", &request, None)?;
+    assert!(output.content.contains("### src/lib.rs\n\n```diff\n"));
+    assert!(!output.content.contains("old.md"));
+
+    // Without the include, the deleted file has its diff
+    let request = ContexterRequest {
+        include: None,
+        ..request
+    };
+    let output = run_project(&config, "demo", &request, None)?;
+    assert!(output.content.contains("### docs/old.md\n\n```diff\n"));
+    Ok(())
+}
@@ -0,0 +1,149 @@
+use contexter::config::Config;
+use contexter::contexter::GatherOptions;
+use contexter::index::{ProjectIndex, ProjectIndexes};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+fn relative(index: &ProjectIndex) -> Vec<String> {
+    index
+        .files()
+        .unwrap()
+        .iter()
+        .map(|file| {
+            file.strip_prefix(index.root())
+                .unwrap()
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect()
+}
+
+fn write(root: &Path, path: &str, content: &str) -> PathBuf {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(&path, content).unwrap();
+    path
+}
+
+#[test]
+fn test_index_follows_changes() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path().to_path_buf();
+    fs::create_dir(root.join(".git"))?;
+    write(&root, "src/main.rs", "fn main() {}");
+    write(&root, "src/lib.rs", "pub mod app;");
+    write(&root, "target/debug/build.rs", "// build output");
+    write(&root, ".gitignore", "target/\n");
+
+    let index = ProjectIndex::new(root.clone(), GatherOptions::default());
+    assert!(index.files().is_none());
+    index.update(&[])?;
+    assert_eq!(relative(&index), vec!["src/lib.rs", "src/main.rs"]);
+
+    // New files are picked up, but not those in ignored directories
+    let app = write(&root, "src/app/mod.rs", "pub fn run() {}");
+    let built = write(&root, "target/debug/app.rs", "// build output");
+    index.update(&[root.join("src/app"), app.clone(), built])?;
+    assert_eq!(
+        relative(&index),
+        vec!["src/app/mod.rs", "src/lib.rs", "src/main.rs"]
+    );
+
+    // Deleted files and directories disappear
+    fs::remove_file(root.join("src/main.rs"))?;
+    fs::remove_dir_all(root.join("src/app"))?;
+    index.update(&[root.join("src/main.rs"), root.join("src/app"), app])?;
+    assert_eq!(relative(&index), vec!["src/lib.rs"]);
+
+    // So do files that become ignored, and they come back once they no longer are
+    let gitignore = write(&root, ".gitignore", "target/\nsrc/lib.rs\n");
+    index.update(std::slice::from_ref(&gitignore))?;
+    assert_eq!(relative(&index), Vec::<String>::new());
+    write(&root, ".gitignore", "target/\n");
+    index.update(&[gitignore])?;
+    assert_eq!(relative(&index), vec!["src/lib.rs"]);
+
+    // A file that turns binary is dropped
+    let lib = root.join("src/lib.rs");
+    fs::write(&lib, [0u8, 159, 146, 150])?;
+    index.update(&[lib])?;
+    assert_eq!(relative(&index), Vec::<String>::new());
+    Ok(())
+}
+
+/// Polls `check` until it holds, failing after a few seconds.
+fn eventually(what: &str, mut check: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !check() {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "timed out waiting until {}",
+            what
+        );
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn test_watcher_keeps_indexes_up_to_date() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path().join("project");
+    write(&root, "src/main.rs", "fn main() {}");
+
+    let mut config = Config::default();
+    config.add_project("project".to_string(), root.clone());
+    let indexes = ProjectIndexes::watch(&config)?;
+    assert!(indexes.get("missing").is_none());
+    let index = indexes.get("project").unwrap();
+    eventually("the project is indexed", || index.files().is_some());
+    assert_eq!(relative(index), vec!["src/main.rs"]);
+
+    write(&root, "src/util/strings.rs", "pub fn trim() {}");
+    eventually("a new file is indexed", || relative(index).len() == 2);
+    assert_eq!(relative(index), vec!["src/main.rs", "src/util/strings.rs"]);
+
+    fs::remove_dir_all(root.join("src/util"))?;
+    eventually("a deleted directory is dropped", || {
+        relative(index).len() == 1
+    });
+
+    write(&root, ".ignore", "*.rs\n");
+    eventually("an ignored file is dropped", || relative(index).is_empty());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_index_of_a_project_behind_a_symlink() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let real = dir.path().join("real");
+    write(&real, "src/main.rs", "fn main() {}");
+    let root = dir.path().join("linked");
+    std::os::unix::fs::symlink(&real, &root)?;
+
+    let index = ProjectIndex::new(root.clone(), GatherOptions::default());
+    index.update(&[])?;
+    assert_eq!(relative(&index), vec!["src/main.rs"]);
+
+    // Some watchers report changes under the real directory, but the index keeps the
+    // project's paths as they are configured
+    let lib = write(&real, "src/lib.rs", "pub fn a() {}");
+    index.update(std::slice::from_ref(&lib))?;
+    assert_eq!(relative(&index), vec!["src/lib.rs", "src/main.rs"]);
+    assert!(index
+        .files()
+        .unwrap()
+        .iter()
+        .all(|file| file.starts_with(&root)));
+
+    fs::remove_file(real.join("src/main.rs"))?;
+    index.update(&[real.join("src/main.rs")])?;
+    assert_eq!(relative(&index), vec!["src/lib.rs"]);
+
+    assert_eq!(index.in_project(&lib), Some(root.join("src/lib.rs")));
+    assert_eq!(index.in_project(&dir.path().join("elsewhere")), None);
+    Ok(())
+}
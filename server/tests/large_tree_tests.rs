@@ -0,0 +1,99 @@
+use contexter::contexter::{
+    concatenate_files_with_options, gather_files, gather_files_with_report, ContextOptions,
+    GatherOptions, SkipReason,
+};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tempfile::tempdir;
+
+/// Generates a tree of `count` small source files, spread over directories of a hundred, where
+/// every tenth file is a copy of the one before it.
+fn generate_tree(root: &Path, count: usize) -> std::io::Result<()> {
+    for i in 0..count {
+        let dir = root.join(format!("src/module_{:04}", i / 100));
+        if i % 100 == 0 {
+            std::fs::create_dir_all(&dir)?;
+        }
+        let n = if i % 10 == 9 { i - 1 } else { i };
+        let content = format!("pub fn function_{}() -> usize {{\n    {}\n}}\n", n, n);
+        std::fs::write(dir.join(format!("file_{:05}.rs", i)), content)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_parallel_output_is_deterministic() -> std::io::Result<()> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    generate_tree(root, 2_000)?;
+
+    let files = gather_files(root, &[], &GatherOptions::default())?;
+    assert_eq!(files.len(), 2_000);
+    let mut sorted = files.clone();
+    sorted.sort();
+    assert_eq!(files, sorted);
+
+    let first = concatenate_files_with_options(files.clone(), &ContextOptions::default())?;
+    for _ in 0..3 {
+        let again = concatenate_files_with_options(files.clone(), &ContextOptions::default())?;
+        assert_eq!(again.content, first.content);
+        assert_eq!(again.skipped, first.skipped);
+    }
+
+    // Files come out ordered by name, and the first copy of a duplicate is the one kept
+    let mut expected: Vec<PathBuf> = files.iter().filter(|f| !is_copy(f)).cloned().collect();
+    expected.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    let expected: Vec<String> = expected
+        .iter()
+        .map(|f| f.to_string_lossy().to_string())
+        .collect();
+    assert_eq!(first.files, expected);
+    assert_eq!(first.skipped.len(), 200);
+    assert!(first
+        .skipped
+        .iter()
+        .all(|skipped| skipped.reason == SkipReason::Duplicate));
+
+    Ok(())
+}
+
+fn is_copy(path: &Path) -> bool {
+    path.file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| stem.ends_with('9'))
+}
+
+/// Gathers and assembles a tree of tens of thousands of files. Run it with
+/// `cargo test --release --test large_tree_tests -- --ignored --nocapture` to see the timings.
+#[test]
+#[ignore]
+fn benchmark_large_tree() -> std::io::Result<()> {
+    const FILES: usize = 30_000;
+
+    let dir = tempdir()?;
+    let root = dir.path();
+    generate_tree(root, FILES)?;
+
+    let start = Instant::now();
+    let gathered = gather_files_with_report(
+        root,
+        &[],
+        &GatherOptions {
+            defer_content_check: true,
+            ..Default::default()
+        },
+    )?;
+    let gathered_in = start.elapsed();
+    assert_eq!(gathered.files.len(), FILES);
+
+    let start = Instant::now();
+    let output = concatenate_files_with_options(gathered.files, &ContextOptions::default())?;
+    let assembled_in = start.elapsed();
+    assert_eq!(output.files.len(), FILES - FILES / 10);
+
+    println!(
+        "{} files: gathered in {:?}, assembled in {:?} ({} tokens)",
+        FILES, gathered_in, assembled_in, output.tokens
+    );
+    Ok(())
+}
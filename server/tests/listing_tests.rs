@@ -0,0 +1,71 @@
+use contexter::categories::CategoryRules;
+use contexter::contexter::{GatherOptions, SkipReason};
+use contexter::listing::{list_project, EntryStatus};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn write(root: &Path, path: &str, content: &[u8]) {
+    let path = root.join(path);
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, content).unwrap();
+}
+
+#[test]
+fn test_listing_covers_included_skipped_and_ignored_entries(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::create_dir(root.join(".git"))?;
+    write(root, ".gitignore", b"secrets.txt\nbuild/\n*.log\n");
+    write(root, "README.md", b"# Demo\n\nA demo project.\n");
+    write(
+        root,
+        "src/main.rs",
+        b"fn main() {\n    println!(\"hi\");\n}\n",
+    );
+    write(root, "logo.png", b"\x89PNG\r\n\x1a\n\0\0\0");
+    write(root, "secrets.txt", b"hunter2");
+    write(root, "build/out.rs", b"// generated");
+    write(root, "app.log", b"started");
+    write(root, "node_modules/pkg/index.js", b"module.exports = {};");
+
+    let entries = list_project(
+        root,
+        &GatherOptions::default(),
+        &CategoryRules::default(),
+        None,
+    )?;
+    let summary: Vec<(&str, EntryStatus)> = entries
+        .iter()
+        .map(|entry| (entry.path.as_str(), entry.status))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            ("README.md", EntryStatus::Included),
+            ("app.log", EntryStatus::Ignored),
+            ("build", EntryStatus::Ignored),
+            ("logo.png", EntryStatus::Skipped),
+            ("node_modules", EntryStatus::Skipped),
+            ("secrets.txt", EntryStatus::Ignored),
+            ("src/main.rs", EntryStatus::Included),
+        ]
+    );
+
+    let main = &entries[6];
+    assert_eq!(main.size, Some(34));
+    assert_eq!(main.lines, Some(3));
+    assert_eq!(main.tokens, Some(9));
+    assert_eq!(main.language.as_deref(), Some("rust"));
+    assert!(main.section.is_some());
+    assert!(main.sha256.is_some());
+
+    assert_eq!(entries[3].reason, Some(SkipReason::Binary));
+    assert!(entries[3].lines.is_none());
+    assert_eq!(entries[4].reason, Some(SkipReason::Excluded));
+    assert!(entries[4].directory);
+    assert!(entries[2].directory);
+    assert!(entries[1].reason.is_none());
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use contexter::contexter::{
+    concatenate_files_with_options, ContextOptions, OversizePolicy, SizeLimits, SkipReason,
+};
+use contexter::digest::ContentDigest;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+/// Writes a SQL dump of `rows` numbered inserts, returning its content.
+fn write_dump(path: &Path, rows: usize) -> String {
+    let dump: String = (0..rows)
+        .map(|row| format!("INSERT INTO users VALUES ({}, 'user{}');\n", row, row))
+        .collect();
+    fs::write(path, &dump).unwrap();
+    dump
+}
+
+fn limits(
+    max_file_size: Option<u64>,
+    max_total_size: Option<u64>,
+    oversized: OversizePolicy,
+) -> ContextOptions {
+    ContextOptions {
+        size_limits: SizeLimits {
+            max_file_size,
+            max_total_size,
+            oversized,
+        },
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_oversized_files_are_skipped() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.rs"), "fn a() {}\n".repeat(10))?;
+    write_dump(&root.join("b.sql"), 200);
+    fs::write(root.join("c.rs"), "fn c() {}\n".repeat(10))?;
+    let files = vec![root.join("a.rs"), root.join("b.sql"), root.join("c.rs")];
+
+    let options = limits(Some(1000), None, OversizePolicy::Skip);
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    assert_eq!(output.files.len(), 2);
+    assert!(!output.content.contains("INSERT"));
+    assert_eq!(output.skipped.len(), 1);
+    assert_eq!(output.skipped[0].reason, SkipReason::TooLarge);
+    assert!(output.skipped[0].path.ends_with("b.sql"));
+    assert!(output.skipped[0]
+        .detail
+        .as_deref()
+        .unwrap()
+        .ends_with("bytes, limit is 1000"));
+    assert!(output.excerpted.is_empty());
+
+    // Files are taken in output order until the total is reached, and those that would not fit
+    // are skipped without stopping at the first
+    let options = limits(Some(1000), Some(150), OversizePolicy::Skip);
+    let output = concatenate_files_with_options(files, &options)?;
+    assert_eq!(output.files.len(), 1);
+    assert!(output.files[0].ends_with("a.rs"));
+    let reasons: Vec<_> = output
+        .skipped
+        .iter()
+        .map(|skipped| skipped.reason)
+        .collect();
+    assert_eq!(
+        reasons,
+        vec![SkipReason::TooLarge, SkipReason::TotalSizeExceeded]
+    );
+    assert!(output.skipped[1].path.ends_with("c.rs"));
+    Ok(())
+}
+
+#[test]
+fn test_oversized_files_are_excerpted() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let dump_path = dir.path().join("dump.sql");
+    let dump = write_dump(&dump_path, 1000);
+
+    let options = limits(Some(2048), None, OversizePolicy::Truncate);
+    let output = concatenate_files_with_options(vec![dump_path.clone()], &options)?;
+    assert_eq!(output.excerpted.len(), 1);
+    let excerpted = &output.excerpted[0];
+    assert_eq!(excerpted.size, dump.len() as u64);
+    assert!(excerpted.kept <= 2048);
+    assert!(excerpted.kept > 1024);
+
+    let omitted = format!(
+        "[... {} bytes omitted ...]",
+        excerpted.size - excerpted.kept
+    );
+    assert!(output.content.contains(&omitted));
+    assert!(output
+        .content
+        .contains("INSERT INTO users VALUES (0, 'user0');\n"));
+    assert!(output
+        .content
+        .contains("INSERT INTO users VALUES (999, 'user999');\n"));
+    assert!(!output.content.contains("(500, 'user500')"));
+    // The excerpts are cut between lines
+    for line in output
+        .content
+        .lines()
+        .filter(|line| line.contains("INSERT"))
+    {
+        assert!(line.starts_with("INSERT INTO users VALUES ("));
+        assert!(line.ends_with("');"));
+    }
+    // The digest is of the excerpts, which is all that is read of the file
+    let head = &dump[..dump[..1024].rfind('\n').unwrap() + 1];
+    let tail_start = dump.len() - 1024;
+    let tail = &dump[tail_start + dump[tail_start..].find('\n').unwrap() + 1..];
+    let digest = output.digests.values().next().unwrap();
+    assert_eq!(
+        *digest,
+        ContentDigest::of(format!("{}{}", head, tail).as_bytes())
+    );
+    Ok(())
+}
+
+#[test]
+fn test_excerpts_are_decoded_like_their_start() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let options = limits(Some(2049), None, OversizePolicy::Truncate);
+
+    // The end of a Windows-1252 file that happens to be valid UTF-8 is still read as
+    // Windows-1252
+    let path = dir.path().join("latin.txt");
+    let mut latin: Vec<u8> = Vec::new();
+    for row in 0..500 {
+        latin.extend(format!("caf\u{e9} {}\n", row).chars().map(|c| c as u8));
+    }
+    latin.extend("d\u{c3}\u{a9}j\u{c3}\u{a0} vu\n".chars().map(|c| c as u8));
+    fs::write(&path, &latin)?;
+    let output = concatenate_files_with_options(vec![path], &options)?;
+    assert_eq!(output.excerpted.len(), 1);
+    assert!(output.content.contains("caf\u{e9} 0\n"));
+    assert!(output.content.contains("d\u{c3}\u{a9}j\u{c3}\u{a0} vu\n"));
+
+    // The end of a UTF-16 file starts on a whole character, even where an odd number of bytes
+    // is left out
+    let path = dir.path().join("wide.txt");
+    let text: String = (0..500)
+        .map(|row| format!("ligne \u{e9} {}\n", row))
+        .collect();
+    let mut wide = vec![0xFF, 0xFE];
+    wide.extend(text.encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+    fs::write(&path, &wide)?;
+    let output = concatenate_files_with_options(vec![path], &options)?;
+    assert_eq!(output.excerpted.len(), 1);
+    assert!(output.content.contains("ligne \u{e9} 0\n"));
+    assert!(output.content.contains("ligne \u{e9} 499\n"));
+    assert!(!output.content.contains('\u{fffd}'));
+    let excerpt = output.content.split("bytes omitted ...]\n").nth(1).unwrap();
+    assert!(excerpt.starts_with("ligne \u{e9} "));
+    Ok(())
+}
+
+#[test]
+fn test_total_size_truncates_the_last_file_that_fits() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    let first = write_dump(&root.join("a.sql"), 50);
+    write_dump(&root.join("b.sql"), 60);
+    write_dump(&root.join("c.sql"), 70);
+    let files = vec![root.join("a.sql"), root.join("b.sql"), root.join("c.sql")];
+
+    let total = first.len() as u64 + 1500;
+    let options = limits(None, Some(total), OversizePolicy::Truncate);
+    let output = concatenate_files_with_options(files, &options)?;
+    assert_eq!(output.files.len(), 2);
+    assert_eq!(output.excerpted.len(), 1);
+    assert!(output.excerpted[0].path.ends_with("b.sql"));
+    assert!(output.excerpted[0].kept <= 1500);
+    // Too little is left of the total to make an excerpt of the last file worth it
+    assert_eq!(output.skipped.len(), 1);
+    assert_eq!(output.skipped[0].reason, SkipReason::TotalSizeExceeded);
+    assert!(output.skipped[0].path.ends_with("c.sql"));
+    Ok(())
+}
@@ -0,0 +1,215 @@
+use contexter::contexter::{concatenate_files_with_options, gather_files, ContextOptions};
+use contexter::skeleton::skeletonize;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+#[test]
+fn test_rust_skeleton() {
+    let source = r#"use std::fmt;
+
+// Not a doc comment
+const LIMIT: usize = 3;
+
+/// A point.
+#[derive(Debug)]
+pub struct Point {
+    pub x: i32,
+}
+
+impl Point {
+    /// Moves right.
+    pub fn step(&mut self) -> i32 {
+        self.x += 1;
+        self.x
+    }
+}
+
+pub trait Shape {
+    fn area(&self) -> f64;
+    fn name(&self) -> &str {
+        "shape"
+    }
+}
+
+fn main() {
+    println!("{}", LIMIT);
+}
+"#;
+    let expected = r#"use std::fmt;
+
+const LIMIT: usize = 3;
+
+/// A point.
+#[derive(Debug)]
+pub struct Point {
+    pub x: i32,
+}
+
+impl Point {
+    /// Moves right.
+    pub fn step(&mut self) -> i32 { ... }
+}
+
+pub trait Shape {
+    fn area(&self) -> f64;
+    fn name(&self) -> &str { ... }
+}
+
+fn main() { ... }
+"#;
+    assert_eq!(
+        skeletonize(Path::new("src/lib.rs"), source).as_deref(),
+        Some(expected)
+    );
+}
+
+#[test]
+fn test_python_skeleton() {
+    let source = r#"import os
+
+TIMEOUT = 5
+
+@cached
+def load(path: str) -> str:
+    """Reads a file."""
+    with open(path) as f:
+        return f.read()
+
+class Store(Base):
+    name = "store"
+
+    def get(self, key):  # look it up
+        return self.data[key]
+
+if __name__ == "__main__":
+    load("x")
+"#;
+    let expected = r#"import os
+
+TIMEOUT = 5
+
+@cached
+def load(path: str) -> str: ...
+
+class Store(Base):
+    name = "store"
+
+    def get(self, key): ...
+"#;
+    assert_eq!(
+        skeletonize(Path::new("store.py"), source).as_deref(),
+        Some(expected)
+    );
+}
+
+#[test]
+fn test_typescript_skeleton() {
+    let source = r#"import { Db } from "./db";
+
+/** Adds two numbers. */
+export function add(a: number, b: number): number {
+  return a + b;
+}
+
+export const handle = async (req: Request) => {
+  await run(req);
+};
+
+export const double = (x: number) => x * 2;
+
+export interface Item {
+  id: string;
+}
+
+export class Service {
+  constructor(private db: Db) {
+    this.db = db;
+  }
+  get(id: string): Item {
+    return this.db.get(id);
+  }
+}
+
+console.log("loaded");
+"#;
+    let expected = r#"import { Db } from "./db";
+
+/** Adds two numbers. */
+export function add(a: number, b: number): number { ... }
+
+export const handle = async (req: Request) => { ... };
+
+export const double = (x: number) => x * 2;
+
+export interface Item {
+  id: string;
+}
+
+export class Service {
+  constructor(private db: Db) { ... }
+  get(id: string): Item { ... }
+}
+"#;
+    assert_eq!(
+        skeletonize(Path::new("service.ts"), source).as_deref(),
+        Some(expected)
+    );
+}
+
+#[test]
+fn test_files_that_cannot_be_skeletonized() {
+    // No grammar for the language
+    assert_eq!(skeletonize(Path::new("notes.md"), "# Notes\n"), None);
+    // Does not parse
+    assert_eq!(skeletonize(Path::new("broken.rs"), "fn main( {\n"), None);
+    // Nothing but statements
+    assert_eq!(skeletonize(Path::new("script.py"), "print('hi')\n"), None);
+}
+
+#[test]
+fn test_skeleton_globs_spare_focus_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("src/api"))?;
+    fs::write(root.join("src/lib.rs"), "pub fn lib() {\n    run();\n}\n")?;
+    fs::write(
+        root.join("src/api/handlers.rs"),
+        "pub fn handle() {\n    serve();\n}\n",
+    )?;
+    fs::write(root.join("README.md"), "# Demo\n")?;
+    let files = gather_files(root, &[], &Default::default())?;
+
+    let options = ContextOptions {
+        root: Some(root.to_path_buf()),
+        skeleton: vec!["**".to_string()],
+        focus: vec!["src/api/".to_string()],
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    assert!(output.content.contains("pub fn lib() { ... }\n"));
+    assert!(!output.content.contains("run();"));
+    assert!(output.content.contains("    serve();\n"));
+    assert!(output.content.contains("# Demo\n"));
+    // Only the file actually reduced is reported, not the one without a grammar
+    let lib = root.join("src/lib.rs").to_string_lossy().into_owned();
+    assert_eq!(output.skeletonized, vec![lib]);
+    assert_eq!(output.files.len(), 3);
+
+    // Skeletons take fewer tokens than the whole files
+    let full = concatenate_files_with_options(
+        files.clone(),
+        &ContextOptions {
+            root: Some(root.to_path_buf()),
+            ..Default::default()
+        },
+    )?;
+    assert!(output.tokens < full.tokens);
+
+    let options = ContextOptions {
+        skeleton: vec!["src/{api".to_string()],
+        ..options
+    };
+    assert!(concatenate_files_with_options(files, &options).is_err());
+    Ok(())
+}
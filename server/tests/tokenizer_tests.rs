@@ -0,0 +1,45 @@
+use contexter::tokenizer::{CharEstimator, Tokenizer, TokenizerKind};
+
+#[test]
+fn test_char_estimator() {
+    assert_eq!(CharEstimator.count_tokens(""), 0);
+    assert_eq!(CharEstimator.count_tokens("abcd"), 1);
+    assert_eq!(CharEstimator.count_tokens("abcde"), 2);
+}
+
+#[test]
+fn test_bpe_tokenizers() {
+    let text = "fn main() { println!(\"Hello, world!\"); }";
+    for kind in [TokenizerKind::Cl100k, TokenizerKind::O200k] {
+        let tokenizer = kind.build();
+        let tokens = tokenizer.count_tokens(text);
+        assert!(tokens > 5 && tokens < text.len(), "{}: {}", kind, tokens);
+    }
+}
+
+#[test]
+fn test_truncate_fits_budget() {
+    let text = "héllo wörld ".repeat(100);
+    for kind in [TokenizerKind::Estimate, TokenizerKind::Cl100k] {
+        let tokenizer = kind.build();
+        let truncated = tokenizer.truncate(&text, 20);
+        assert!(!truncated.is_empty());
+        assert!(tokenizer.count_tokens(truncated) <= 20);
+        assert!(text.starts_with(truncated));
+        assert_eq!(tokenizer.truncate(&text, 100_000), text);
+    }
+}
+
+#[test]
+fn test_tokenizer_kind_parsing() {
+    assert_eq!("cl100k".parse::<TokenizerKind>(), Ok(TokenizerKind::Cl100k));
+    assert_eq!(
+        "o200k_base".parse::<TokenizerKind>(),
+        Ok(TokenizerKind::O200k)
+    );
+    assert_eq!(
+        "estimate".parse::<TokenizerKind>(),
+        Ok(TokenizerKind::Estimate)
+    );
+    assert!("gpt2".parse::<TokenizerKind>().is_err());
+}
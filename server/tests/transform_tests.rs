@@ -0,0 +1,217 @@
+use contexter::contexter::{concatenate_files_with_options, gather_files, ContextOptions};
+use contexter::transform::Transforms;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn apply(transforms: Transforms, path: &str, source: &str) -> String {
+    transforms.compile().unwrap().apply(Path::new(path), source)
+}
+
+#[test]
+fn test_strip_rust_comments() {
+    let source = r#"//! The crate.
+
+/// A label.
+fn label<'a>(name: &'a str) -> String {
+    // Markers in strings are not comments
+    let url = "http://example.com/*"; // trailing
+    let quote = '"'; /* inline */ let slash = '/';
+    format!("{}{}{}{}", name, url, quote, slash)
+}
+"#;
+    let stripped = r#"
+fn label<'a>(name: &'a str) -> String {
+    let url = "http://example.com/*";
+    let quote = '"';  let slash = '/';
+    format!("{}{}{}{}", name, url, quote, slash)
+}
+"#;
+    let transforms = Transforms {
+        strip_comments: true,
+        ..Default::default()
+    };
+    assert_eq!(apply(transforms.clone(), "src/lib.rs", source), stripped);
+
+    let kept = r#"//! The crate.
+
+/// A label.
+fn label<'a>(name: &'a str) -> String {
+    let url = "http://example.com/*";
+    let quote = '"';  let slash = '/';
+    format!("{}{}{}{}", name, url, quote, slash)
+}
+"#;
+    let transforms = Transforms {
+        keep_doc_comments: true,
+        ..transforms
+    };
+    assert_eq!(apply(transforms, "src/lib.rs", source), kept);
+}
+
+#[test]
+fn test_strip_python_and_typescript_comments() {
+    let transforms = Transforms {
+        strip_comments: true,
+        ..Default::default()
+    };
+
+    let python =
+        "#!/usr/bin/env python\n# Setup\nx = '#not'  # count\ns = \"\"\"\n# kept\n\"\"\"\n";
+    assert_eq!(
+        apply(transforms.clone(), "run.py", python),
+        "#!/usr/bin/env python\nx = '#not'\ns = \"\"\"\n# kept\n\"\"\"\n"
+    );
+
+    let typescript = "/** Adds. */\nconst a = `//${b}`; // sum\n/*\n * Block\n */\nexport {};\n";
+    assert_eq!(
+        apply(transforms.clone(), "add.ts", typescript),
+        "const a = `//${b}`;\nexport {};\n"
+    );
+    let transforms = Transforms {
+        keep_doc_comments: true,
+        ..transforms
+    };
+    assert_eq!(
+        apply(transforms.clone(), "add.ts", typescript),
+        "/** Adds. */\nconst a = `//${b}`;\nexport {};\n"
+    );
+
+    // Languages whose comment syntax is unknown are left alone
+    assert_eq!(apply(transforms, "notes.md", "# Notes\n"), "# Notes\n");
+}
+
+#[test]
+fn test_strip_comments_around_non_ascii_strings() {
+    let transforms = Transforms {
+        strip_comments: true,
+        ..Default::default()
+    };
+
+    let rust = "let s = \"h\u{e9}llo\"; // greet\nlet t = \"\\\u{e9}\\\"\u{1f600}\"; /* \u{fc} */\nlet c = '\\\u{e9}'; // \u{e9}\nlet d = '\u{e9}';\n";
+    assert_eq!(
+        apply(transforms.clone(), "main.rs", rust),
+        "let s = \"h\u{e9}llo\";\nlet t = \"\\\u{e9}\\\"\u{1f600}\";\nlet c = '\\\u{e9}';\nlet d = '\u{e9}';\n"
+    );
+
+    // Comment markers inside raw strings are left alone, whatever they are opened with
+    let raw = "let a = r#\"see \"http://example.com\" here\"#; // link\nlet b = br\"C:\\\"; // path\nlet c = r##\"/* \"# */\"##;\nlet var = 1; // r\n";
+    assert_eq!(
+        apply(transforms.clone(), "main.rs", raw),
+        "let a = r#\"see \"http://example.com\" here\"#;\nlet b = br\"C:\\\";\nlet c = r##\"/* \"# */\"##;\nlet var = 1;\n"
+    );
+
+    let python = "name = '\u{fc}ber'  # \u{e9}t\u{e9}\ntext = \"\"\"\u{e7}a # va\"\"\"\n";
+    assert_eq!(
+        apply(transforms, "run.py", python),
+        "name = '\u{fc}ber'\ntext = \"\"\"\u{e7}a # va\"\"\"\n"
+    );
+}
+
+#[test]
+fn test_strip_license_header() {
+    let source = "// Copyright 2024 Example Ltd.\n// Licensed under MIT.\n\n// Not part of it\nfn main() {}\n";
+    let transforms = Transforms {
+        strip_license: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        apply(transforms.clone(), "main.rs", source),
+        "// Not part of it\nfn main() {}\n"
+    );
+
+    // Leading comments that do not look like a license stay
+    let plain = "// Entry point\nfn main() {}\n";
+    assert_eq!(apply(transforms.clone(), "main.rs", plain), plain);
+
+    let custom = Transforms {
+        license_pattern: Some("Entry point".to_string()),
+        ..transforms.clone()
+    };
+    assert_eq!(apply(custom, "main.rs", plain), "fn main() {}\n");
+
+    let shell = "#!/bin/sh\n# SPDX-License-Identifier: MIT\necho hi\n";
+    assert_eq!(
+        apply(transforms.clone(), "run.sh", shell),
+        "#!/bin/sh\necho hi\n"
+    );
+
+    // Non-ASCII text in the header and in the strings after it
+    let accented = "// Copyright 2024 Soci\u{e9}t\u{e9} G\u{e9}n\u{e9}rale\n\nfn main() { println!(\"\\\u{e9}t\u{e9} // \u{e0}\"); }\n";
+    assert_eq!(
+        apply(transforms, "main.rs", accented),
+        "fn main() { println!(\"\\\u{e9}t\u{e9} // \u{e0}\"); }\n"
+    );
+
+    let invalid = Transforms {
+        strip_license: true,
+        license_pattern: Some("(unclosed".to_string()),
+        ..Default::default()
+    };
+    assert!(invalid.compile().is_err());
+}
+
+#[test]
+fn test_whitespace_transforms() {
+    let source = "\n\nfirst  \n\n\n\t\nsecond\t\nthird";
+    let transforms = Transforms {
+        trim_trailing_whitespace: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        apply(transforms.clone(), "notes.txt", source),
+        "\n\nfirst\n\n\n\nsecond\nthird"
+    );
+    let transforms = Transforms {
+        collapse_blank_lines: true,
+        ..transforms
+    };
+    assert_eq!(
+        apply(transforms, "notes.txt", source),
+        "first\n\nsecond\nthird"
+    );
+}
+
+#[test]
+fn test_transforms_report_token_savings() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempdir()?;
+    let root = dir.path();
+    fs::write(
+        root.join("main.rs"),
+        "// Copyright 2024 Example Ltd.\n\n// Says hello to everyone in the room\nfn main() {}\n",
+    )?;
+    fs::write(root.join("plain.rs"), "fn plain() {}\n")?;
+    let files = gather_files(root, &[], &Default::default())?;
+
+    let plain = concatenate_files_with_options(files.clone(), &ContextOptions::default())?;
+    assert!(plain.transformed.is_empty());
+
+    let options = ContextOptions {
+        transforms: Transforms {
+            strip_comments: true,
+            collapse_blank_lines: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let output = concatenate_files_with_options(files.clone(), &options)?;
+    assert!(!output.content.contains("Says hello"));
+    assert!(output.content.contains("fn main() {}"));
+    assert!(output.tokens < plain.tokens);
+    // Only the file whose content changed is reported
+    assert_eq!(output.transformed.len(), 1);
+    let main = &output.transformed[0];
+    assert!(main.path.ends_with("main.rs"));
+    assert!(main.tokens_after < main.tokens_before);
+
+    let options = ContextOptions {
+        transforms: Transforms {
+            strip_license: true,
+            license_pattern: Some("(unclosed".to_string()),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(concatenate_files_with_options(files, &options).is_err());
+    Ok(())
+}